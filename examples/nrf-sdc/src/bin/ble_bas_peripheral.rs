@@ -14,7 +14,7 @@ use sdc::rng_pool::RngPool;
 use static_cell::StaticCell;
 use trouble_host::adapter::{Adapter, HostResources};
 use trouble_host::advertise::{AdStructure, Advertisement, BR_EDR_NOT_SUPPORTED, LE_GENERAL_DISCOVERABLE};
-use trouble_host::attribute::{AttributeTable, CharacteristicProp, Service, Uuid};
+use trouble_host::attribute::{AttributeTable, CharacteristicProp, GapConfig, Service, Uuid};
 use trouble_host::{Address, PacketQos};
 use {defmt_rtt as _, panic_probe as _};
 
@@ -114,29 +114,24 @@ async fn main(spawner: Spawner) {
         Adapter::new(sdc, host_resources);
     adapter.set_random_address(my_addr());
 
-    let mut table: AttributeTable<'_, NoopRawMutex, 10> = AttributeTable::new();
+    let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
 
     // Generic Access Service (mandatory)
     let id = b"Trouble";
-    let appearance = [0x80, 0x07];
     let mut bat_level = [0; 1];
+    unwrap!(table.add_gap_service(GapConfig::new(id, 0x0780)));
     let handle = {
-        let mut svc = table.add_service(Service::new(0x1800));
-        let _ = svc.add_characteristic_ro(0x2a00, id);
-        let _ = svc.add_characteristic_ro(0x2a01, &appearance[..]);
-        drop(svc);
-
         // Generic attribute service (mandatory)
-        table.add_service(Service::new(0x1801));
+        unwrap!(table.add_gatt_service());
 
         // Battery service
-        let mut svc = table.add_service(Service::new(0x180f));
+        let mut svc = unwrap!(table.add_service(Service::new(0x180f)));
 
-        svc.add_characteristic(
+        unwrap!(svc.add_characteristic(
             0x2a19,
             &[CharacteristicProp::Read, CharacteristicProp::Notify],
             &mut bat_level,
-        )
+        ))
     };
 
     let server = adapter.gatt_server(&table);
@@ -145,7 +140,7 @@ async fn main(spawner: Spawner) {
         &[
             AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
             AdStructure::ServiceUuids16(&[Uuid::Uuid16([0x0f, 0x18])]),
-            AdStructure::CompleteLocalName(b"Trouble"),
+            AdStructure::CompleteLocalName(id),
         ],
         &mut adv_data[..],
     ));
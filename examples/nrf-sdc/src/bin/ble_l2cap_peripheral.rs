@@ -14,7 +14,7 @@ use sdc::rng_pool::RngPool;
 use static_cell::StaticCell;
 use trouble_host::adapter::{Adapter, HostResources};
 use trouble_host::advertise::{AdStructure, Advertisement, BR_EDR_NOT_SUPPORTED, LE_GENERAL_DISCOVERABLE};
-use trouble_host::l2cap::{L2capChannel, L2capChannelConfig};
+use trouble_host::l2cap::{L2capChannel, L2capChannelConfig, PsmRegistration};
 use trouble_host::{Address, PacketQos};
 use {defmt_rtt as _, panic_probe as _};
 
@@ -120,6 +120,7 @@ async fn main(spawner: Spawner) {
     let mut adapter: Adapter<'_, NoopRawMutex, _, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX, L2CAP_MTU> =
         Adapter::new(sdc, host_resources);
     adapter.set_random_address(my_addr());
+    unwrap!(adapter.register_psm(PsmRegistration::new(0x2349, L2CAP_MTU as u16)));
     let mut adv_data = [0; 31];
     unwrap!(AdStructure::encode_slice(
         &[AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),],
@@ -9,7 +9,7 @@ use tokio::time::Duration;
 use tokio_serial::{DataBits, Parity, SerialStream, StopBits};
 use trouble_host::adapter::{Adapter, HostResources};
 use trouble_host::advertise::{AdStructure, Advertisement, BR_EDR_NOT_SUPPORTED, LE_GENERAL_DISCOVERABLE};
-use trouble_host::attribute::{AttributeTable, CharacteristicProp, Service, Uuid};
+use trouble_host::attribute::{AttributeTable, CharacteristicProp, GapConfig, Service, Uuid};
 use trouble_host::{Address, PacketQos};
 
 #[tokio::main]
@@ -61,29 +61,25 @@ async fn main() {
     let mut adapter: Adapter<'_, NoopRawMutex, _, 2, 4, 27, 1, 1> = Adapter::new(controller, host_resources);
 
     adapter.set_random_address(Address::random([0xff, 0x9f, 0x1a, 0x05, 0xe4, 0xff]));
-    let mut table: AttributeTable<'_, NoopRawMutex, 10> = AttributeTable::new();
+    let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
 
     // Generic Access Service (mandatory)
     let id = b"Trouble HCI";
-    let appearance = [0x80, 0x07];
     let mut bat_level = [0; 1];
+    table.add_gap_service(GapConfig::new(id, 0x0780)).unwrap();
     let handle = {
-        let mut svc = table.add_service(Service::new(0x1800));
-        let _ = svc.add_characteristic_ro(0x2a00, id);
-        let _ = svc.add_characteristic_ro(0x2a01, &appearance[..]);
-        drop(svc);
-
         // Generic attribute service (mandatory)
-        table.add_service(Service::new(0x1801));
+        table.add_gatt_service().unwrap();
 
         // Battery service
-        let mut svc = table.add_service(Service::new(0x180f));
+        let mut svc = table.add_service(Service::new(0x180f)).unwrap();
 
         svc.add_characteristic(
             0x2a19,
             &[CharacteristicProp::Read, CharacteristicProp::Notify],
             &mut bat_level,
         )
+        .unwrap()
     };
 
     let mut adv_data = [0; 31];
@@ -91,7 +87,7 @@ async fn main() {
         &[
             AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
             AdStructure::ServiceUuids16(&[Uuid::Uuid16([0x0f, 0x18])]),
-            AdStructure::CompleteLocalName(b"Trouble HCI"),
+            AdStructure::CompleteLocalName(id),
         ],
         &mut adv_data[..],
     )
@@ -0,0 +1,47 @@
+//! Procedural macros for the trouble BLE host.
+//!
+//! The macros are re-exported by `trouble-host` when the `gatt` feature is enabled, and the generated
+//! code refers to items through the `trouble_host` crate.
+use proc_macro::TokenStream;
+
+mod service;
+mod uuid;
+
+/// Define a GATT service from a struct whose fields are characteristics.
+///
+/// ```ignore
+/// #[gatt_service(uuid = "180f")]
+/// pub struct BatteryService {
+///     #[characteristic(uuid = "2a19", read, write, notify, value = 100)]
+///     level: u8,
+/// }
+/// ```
+///
+/// Each field must be annotated with `#[characteristic(...)]`, taking the characteristic `uuid` and any of the
+/// `read`, `write`, `write_without_response`, `notify` and `indicate` properties. A UUID is either a 16-bit
+/// value (`"2a19"`) or a full 128-bit UUID (`"12345678-1234-5678-1234-56789abcdef0"`). The initial value
+/// can be given with `value = <expr>`, and defaults to `Default::default()` otherwise.
+///
+/// Field types must implement `GattValue`. The struct fields are replaced by a typed `Characteristic` handle
+/// for each characteristic, and the following items are generated:
+///
+/// * `<Name>Storage`, holding the value storage of all characteristics, which must outlive the table.
+/// * `<Name>::new(table, storage)`, registering the service and its characteristics in an `AttributeTable`,
+///   which fails if another service is being added to the table.
+/// * `get_<field>` and `set_<field>` for reading and writing the typed value in the table.
+/// * `notify_<field>` for characteristics with the `notify` property, and `indicate_<field>` for characteristics
+///   with the `indicate` property.
+/// * `<Name>Event` and `<Name>::on_event` for characteristics which can be written by clients, converting a
+///   `GattEvent` into a typed event for this service.
+#[proc_macro_attribute]
+pub fn gatt_service(args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut service_args = service::ServiceArgs::default();
+    let parser = syn::meta::parser(|meta| service_args.parse(meta));
+    syn::parse_macro_input!(args with parser);
+    let item = syn::parse_macro_input!(item as syn::ItemStruct);
+
+    match service::expand(service_args, item) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
@@ -0,0 +1,367 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::meta::ParseNestedMeta;
+use syn::{Attribute, Expr, Fields, Ident, ItemStruct, Lit, Type, Visibility};
+
+use crate::uuid;
+
+/// Arguments of the `gatt_service` attribute.
+#[derive(Default)]
+pub(crate) struct ServiceArgs {
+    uuid: Option<TokenStream>,
+}
+
+impl ServiceArgs {
+    pub(crate) fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("uuid") {
+            let lit: Lit = meta.value()?.parse()?;
+            self.uuid = Some(uuid::parse(&lit)?);
+            Ok(())
+        } else {
+            Err(meta.error("unsupported gatt_service property"))
+        }
+    }
+}
+
+/// A characteristic parsed from a struct field.
+struct Characteristic {
+    name: Ident,
+    ty: Type,
+    vis: Visibility,
+    attrs: Vec<Attribute>,
+    uuid: TokenStream,
+    value: Option<Expr>,
+    read: bool,
+    write: bool,
+    write_without_response: bool,
+    notify: bool,
+    indicate: bool,
+}
+
+impl Characteristic {
+    fn from_field(field: &syn::Field) -> syn::Result<Self> {
+        let name = field.ident.clone().expect("named field");
+        let mut uuid = None;
+        let mut chr = Self {
+            name,
+            ty: field.ty.clone(),
+            vis: field.vis.clone(),
+            attrs: Vec::new(),
+            uuid: TokenStream::new(),
+            value: None,
+            read: false,
+            write: false,
+            write_without_response: false,
+            notify: false,
+            indicate: false,
+        };
+
+        let mut found = false;
+        for attr in field.attrs.iter() {
+            if !attr.path().is_ident("characteristic") {
+                chr.attrs.push(attr.clone());
+                continue;
+            }
+            found = true;
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("uuid") {
+                    let lit: Lit = meta.value()?.parse()?;
+                    uuid = Some(uuid::parse(&lit)?);
+                } else if meta.path.is_ident("value") {
+                    chr.value = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("read") {
+                    chr.read = true;
+                } else if meta.path.is_ident("write") {
+                    chr.write = true;
+                } else if meta.path.is_ident("write_without_response") {
+                    chr.write_without_response = true;
+                } else if meta.path.is_ident("notify") {
+                    chr.notify = true;
+                } else if meta.path.is_ident("indicate") {
+                    chr.indicate = true;
+                } else {
+                    return Err(meta.error("unsupported characteristic property"));
+                }
+                Ok(())
+            })?;
+        }
+
+        if !found {
+            return Err(syn::Error::new_spanned(
+                field,
+                "field must be annotated with #[characteristic(...)]",
+            ));
+        }
+
+        chr.uuid = uuid.ok_or_else(|| syn::Error::new_spanned(field, "characteristic is missing a uuid"))?;
+        Ok(chr)
+    }
+
+    fn props(&self) -> TokenStream {
+        let mut props = Vec::new();
+        let prop = |name: &str| {
+            let name = Ident::new(name, Span::call_site());
+            quote!(::trouble_host::attribute::CharacteristicProp::#name)
+        };
+        if self.read {
+            props.push(prop("Read"));
+        }
+        if self.write {
+            props.push(prop("Write"));
+        }
+        if self.write_without_response {
+            props.push(prop("WriteWithoutResponse"));
+        }
+        if self.notify {
+            props.push(prop("Notify"));
+        }
+        if self.indicate {
+            props.push(prop("Indicate"));
+        }
+        quote!(&[#(#props),*])
+    }
+
+    fn writable(&self) -> bool {
+        self.write || self.write_without_response
+    }
+
+    fn variant(&self) -> Ident {
+        let camel: String = self
+            .name
+            .to_string()
+            .split('_')
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                let mut c = s.chars();
+                c.next()
+                    .map(|f| f.to_uppercase().chain(c).collect::<String>())
+                    .unwrap_or_default()
+            })
+            .collect();
+        Ident::new(&camel, self.name.span())
+    }
+}
+
+pub(crate) fn expand(args: ServiceArgs, item: ItemStruct) -> syn::Result<TokenStream> {
+    let uuid = args
+        .uuid
+        .ok_or_else(|| syn::Error::new(Span::call_site(), "gatt_service is missing a uuid"))?;
+
+    let Fields::Named(fields) = &item.fields else {
+        return Err(syn::Error::new_spanned(
+            &item,
+            "gatt_service requires a struct with named fields",
+        ));
+    };
+
+    if !item.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &item.generics,
+            "gatt_service does not support generics",
+        ));
+    }
+
+    let chars = fields
+        .named
+        .iter()
+        .map(Characteristic::from_field)
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let attrs = &item.attrs;
+    let vis = &item.vis;
+    let name = &item.ident;
+    let storage = format_ident!("{}Storage", name);
+    let event = format_ident!("{}Event", name);
+
+    let handle_fields = chars.iter().map(|c| {
+        let (attrs, vis, name, ty) = (&c.attrs, &c.vis, &c.name, &c.ty);
+        quote! {
+            #(#attrs)*
+            #vis #name: ::trouble_host::attribute::Characteristic<#ty>
+        }
+    });
+
+    let storage_fields = chars.iter().map(|c| {
+        let (name, ty) = (&c.name, &c.ty);
+        quote!(#name: [u8; <#ty as ::trouble_host::attribute::GattValue>::MAX_SIZE])
+    });
+
+    let storage_init = chars.iter().map(|c| {
+        let (name, ty) = (&c.name, &c.ty);
+        quote!(#name: [0; <#ty as ::trouble_host::attribute::GattValue>::MAX_SIZE])
+    });
+
+    let register = chars.iter().map(|c| {
+        let (name, uuid, ty) = (&c.name, &c.uuid, &c.ty);
+        let props = c.props();
+        let value = c
+            .value
+            .as_ref()
+            .map(|v| quote!(#v))
+            .unwrap_or_else(|| quote!(<#ty as ::core::default::Default>::default()));
+        quote! {
+            let #name = service.add_characteristic_typed(#uuid, #props, &#value, &mut storage.#name[..])?;
+        }
+    });
+    let names: Vec<_> = chars.iter().map(|c| &c.name).collect();
+
+    let accessors = chars.iter().map(|c| {
+        let (name, ty) = (&c.name, &c.ty);
+        let get = format_ident!("get_{}", name);
+        let set = format_ident!("set_{}", name);
+        let notify = format_ident!("notify_{}", name);
+        let indicate = format_ident!("indicate_{}", name);
+
+        let notify = c.notify.then(|| {
+            quote! {
+                /// Write a new value and notify the connection if it has subscribed to this characteristic.
+                pub async fn #notify<
+                    M: ::trouble_host::__macro_support::RawMutex,
+                    T: ::trouble_host::__macro_support::Controller,
+                    const MAX: usize,
+                >(
+                    &self,
+                    server: &::trouble_host::gatt::GattServer<'_, '_, '_, M, T, MAX>,
+                    connection: &::trouble_host::connection::Connection<'_>,
+                    value: #ty,
+                ) -> Result<(), ::trouble_host::AdapterError<T::Error>> {
+                    self.#name.notify(server, connection, &value).await
+                }
+            }
+        });
+
+        let indicate = c.indicate.then(|| {
+            quote! {
+                /// Write a new value and indicate it to the connection if it has subscribed to this characteristic.
+                pub async fn #indicate<
+                    M: ::trouble_host::__macro_support::RawMutex,
+                    T: ::trouble_host::__macro_support::Controller,
+                    const MAX: usize,
+                >(
+                    &self,
+                    server: &::trouble_host::gatt::GattServer<'_, '_, '_, M, T, MAX>,
+                    connection: &::trouble_host::connection::Connection<'_>,
+                    value: #ty,
+                ) -> Result<(), ::trouble_host::AdapterError<T::Error>> {
+                    self.#name.indicate(server, connection, &value).await
+                }
+            }
+        });
+
+        quote! {
+            /// Read the current value of this characteristic.
+            pub fn #get<M: ::trouble_host::__macro_support::RawMutex, const MAX: usize>(
+                &self,
+                table: &::trouble_host::attribute::AttributeTable<'_, M, MAX>,
+            ) -> Result<#ty, ::trouble_host::Error> {
+                self.#name.get(table)
+            }
+
+            /// Set the value of this characteristic.
+            pub fn #set<M: ::trouble_host::__macro_support::RawMutex, const MAX: usize>(
+                &self,
+                table: &::trouble_host::attribute::AttributeTable<'_, M, MAX>,
+                value: #ty,
+            ) -> Result<(), ::trouble_host::Error> {
+                self.#name.set(table, &value)
+            }
+
+            #notify
+
+            #indicate
+        }
+    });
+
+    let writable: Vec<_> = chars.iter().filter(|c| c.writable()).collect();
+    let events = (!writable.is_empty()).then(|| {
+        let variants = writable.iter().map(|c| {
+            let (variant, ty) = (c.variant(), &c.ty);
+            quote! {
+                #variant {
+                    connection: ::trouble_host::connection::Connection<'d>,
+                    value: #ty,
+                }
+            }
+        });
+        let matches = writable.iter().map(|c| {
+            let (variant, name) = (c.variant(), &c.name);
+            let get = format_ident!("get_{}", name);
+            quote! {
+                if *handle == self.#name.handle() {
+                    return self.#get(table).ok().map(|value| #event::#variant {
+                        connection: connection.clone(),
+                        value,
+                    });
+                }
+            }
+        });
+        quote! {
+            /// Events for characteristics written by a client.
+            #vis enum #event<'d> {
+                #(#variants),*
+            }
+
+            impl #name {
+                /// Convert a GATT server event into an event for this service.
+                ///
+                /// Returns `None` if the event does not concern this service.
+                pub fn on_event<'d, M: ::trouble_host::__macro_support::RawMutex, const MAX: usize>(
+                    &self,
+                    table: &::trouble_host::attribute::AttributeTable<'_, M, MAX>,
+                    event: &::trouble_host::gatt::GattEvent<'d>,
+                ) -> Option<#event<'d>> {
+                    match event {
+                        ::trouble_host::gatt::GattEvent::Write { connection, handle } => {
+                            #(#matches)*
+                            None
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis struct #name {
+            #(#handle_fields),*
+        }
+
+        /// Value storage for the characteristics of the service.
+        #vis struct #storage {
+            #(#storage_fields),*
+        }
+
+        impl #storage {
+            pub const fn new() -> Self {
+                Self {
+                    #(#storage_init),*
+                }
+            }
+        }
+
+        impl Default for #storage {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl #name {
+            /// Register the service and its characteristics in the attribute table.
+            pub fn new<'d, M: ::trouble_host::__macro_support::RawMutex, const MAX: usize>(
+                table: &::trouble_host::attribute::AttributeTable<'d, M, MAX>,
+                storage: &'d mut #storage,
+            ) -> Result<Self, ::trouble_host::Error> {
+                let mut service = table.add_service(::trouble_host::attribute::Service::new(#uuid))?;
+                #(#register)*
+                Ok(Self {
+                    #(#names),*
+                })
+            }
+
+            #(#accessors)*
+        }
+
+        #events
+    })
+}
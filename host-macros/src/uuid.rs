@@ -0,0 +1,42 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Lit, LitStr};
+
+/// Parse a UUID literal into an expression constructing a `trouble_host::attribute::Uuid`.
+pub(crate) fn parse(lit: &Lit) -> syn::Result<TokenStream> {
+    match lit {
+        Lit::Int(i) => {
+            let val: u16 = i.base10_parse()?;
+            Ok(short(val))
+        }
+        Lit::Str(s) => parse_str(s),
+        _ => Err(syn::Error::new_spanned(lit, "expected a UUID string or 16-bit integer")),
+    }
+}
+
+fn parse_str(s: &LitStr) -> syn::Result<TokenStream> {
+    let value = s.value();
+    let hex: String = value.chars().filter(|c| *c != '-').collect();
+    let invalid = || syn::Error::new_spanned(s, "invalid UUID, expected 4 or 32 hex digits");
+
+    match hex.len() {
+        4 => {
+            let val = u16::from_str_radix(&hex, 16).map_err(|_| invalid())?;
+            Ok(short(val))
+        }
+        32 => {
+            let mut bytes = [0u8; 16];
+            for (i, b) in bytes.iter_mut().enumerate() {
+                *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+            }
+            // UUIDs are written big endian, but transmitted little endian.
+            bytes.reverse();
+            Ok(quote!(::trouble_host::attribute::Uuid::new_long([#(#bytes),*])))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn short(val: u16) -> TokenStream {
+    quote!(::trouble_host::attribute::Uuid::new_short(#val))
+}
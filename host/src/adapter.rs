@@ -1,12 +1,14 @@
+use core::cell::{Cell, RefCell};
 use core::future::pending;
 use core::task::Poll;
 
 use bt_hci::cmd::controller_baseband::{HostBufferSize, Reset, SetEventMask};
 use bt_hci::cmd::le::{
-    LeAddDeviceToFilterAcceptList, LeClearAdvSets, LeClearFilterAcceptList, LeCreateConn, LeCreateConnCancel,
-    LeExtCreateConn, LeReadBufferSize, LeSetAdvData, LeSetAdvEnable, LeSetAdvParams, LeSetAdvSetRandomAddr,
-    LeSetEventMask, LeSetExtAdvData, LeSetExtAdvEnable, LeSetExtAdvParams, LeSetExtScanEnable, LeSetExtScanParams,
-    LeSetExtScanResponseData, LeSetRandomAddr, LeSetScanEnable, LeSetScanParams, LeSetScanResponseData,
+    LeAddDeviceToFilterAcceptList, LeClearAdvSets, LeClearFilterAcceptList, LeConnUpdate, LeCreateConn,
+    LeCreateConnCancel, LeExtCreateConn, LeReadBufferSize, LeSetAdvData, LeSetAdvEnable, LeSetAdvParams,
+    LeSetAdvSetRandomAddr, LeSetEventMask, LeSetExtAdvData, LeSetExtAdvEnable, LeSetExtAdvParams, LeSetExtScanEnable,
+    LeSetExtScanParams, LeSetExtScanResponseData, LeSetRandomAddr, LeSetScanEnable, LeSetScanParams,
+    LeSetScanResponseData,
 };
 use bt_hci::cmd::link_control::Disconnect;
 use bt_hci::cmd::{AsyncCmd, SyncCmd};
@@ -15,28 +17,38 @@ use bt_hci::data::{AclBroadcastFlag, AclPacket, AclPacketBoundary};
 use bt_hci::event::le::LeEvent;
 use bt_hci::event::{Event, Vendor};
 use bt_hci::param::{
-    AddrKind, AdvChannelMap, AdvHandle, AdvKind, BdAddr, ConnHandle, DisconnectReason, EventMask, FilterDuplicates,
-    InitiatingPhy, LeEventMask, Operation, PhyParams, ScanningPhy,
+    AddrKind, AdvChannelMap, AdvHandle, AdvKind, AdvSet, BdAddr, ConnHandle, DisconnectReason, EncryptionEnabledLevel,
+    EventMask, FilterDuplicates, InitiatingPhy, LeConnRole, LeEventMask, Operation, PhyParams, ScanningPhy,
 };
 use bt_hci::{ControllerToHostPacket, FromHciBytes, WriteHci};
 use embassy_futures::select::{select, Either};
 use embassy_sync::blocking_mutex::raw::{NoopRawMutex, RawMutex};
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::channel::Channel;
 use embassy_sync::once_lock::OnceLock;
 use embassy_sync::semaphore::{GreedySemaphore, Semaphore as _};
+use embassy_time::Duration;
 use futures::pin_mut;
+use heapless::Vec;
 
 use crate::advertise::{Advertisement, AdvertisementConfig, RawAdvertisement};
-use crate::channel_manager::ChannelManager;
-use crate::connection::{ConnectConfig, Connection};
-use crate::connection_manager::ConnectionManager;
+use crate::channel_manager::{ChannelManager, PsmRegistration};
+use crate::codec::Encode;
+use crate::connection::{
+    ConnectConfig, ConnectParams, Connection, ConnectionEvent, ConnectionParamsPolicy, ConnectionParamsUpdate,
+    SecurityLevel,
+};
+use crate::connection_manager::{ConnectionManager, DynamicConnectionManager};
 use crate::cursor::WriteCursor;
 use crate::l2cap::sar::PacketReassembly;
-use crate::packet_pool::{AllocId, DynamicPacketPool, PacketPool, Qos};
+use crate::l2cap::FixedChannelHandler;
+use crate::packet_pool::{AllocId, DynamicPacketPool, PacketPool, Qos, FIXED_ID};
 use crate::pdu::Pdu;
 use crate::scan::{PhySet, ScanConfig, ScanReport};
 use crate::types::l2cap::{
-    L2capHeader, L2capSignal, L2capSignalHeader, L2CAP_CID_ATT, L2CAP_CID_DYN_START, L2CAP_CID_LE_U_SIGNAL,
+    ConnParamUpdateRes, L2capHeader, L2capSignal, L2capSignalHeader, CONN_PARAM_UPDATE_ACCEPTED,
+    CONN_PARAM_UPDATE_REJECTED, L2CAP_CID_ATT, L2CAP_CID_DYN_START, L2CAP_CID_FIXED_CUSTOM_END,
+    L2CAP_CID_FIXED_CUSTOM_START, L2CAP_CID_LE_U_SIGNAL,
 };
 #[cfg(feature = "gatt")]
 use crate::{attribute::AttributeTable, gatt::GattServer};
@@ -56,6 +68,18 @@ impl<M: RawMutex, const CHANNELS: usize, const PACKETS: usize, const L2CAP_MTU:
     }
 }
 
+/// Maximum number of custom fixed channels that can be registered with an adapter.
+pub const MAX_FIXED_CHANNELS: usize = 4;
+
+/// Advertising started with [`Adapter::start_advertising`] or [`Adapter::start_advertising_ext`].
+#[derive(Clone, Copy)]
+struct Advertising {
+    // Advertising set to enable again, if extended advertising is used.
+    set: Option<AdvSet>,
+    // Controllers stop advertising once connected, it is resumed when a connection slot is free.
+    paused: bool,
+}
+
 /// Event handler for vendor-specific events handled outside the adapter.
 pub trait VendorEventHandler {
     fn on_event(&self, event: &Vendor<'_>);
@@ -80,6 +104,8 @@ pub struct Adapter<
     pub(crate) reassembly: PacketReassembly<'d, CONNS>,
     pub(crate) channels: ChannelManager<'d, M, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
     pub(crate) att_inbound: Channel<M, (ConnHandle, Pdu<'d>), L2CAP_RXQ>,
+    pub(crate) fixed_channels: Mutex<M, RefCell<Vec<(u16, &'d dyn FixedChannelHandler), MAX_FIXED_CHANNELS>>>,
+    advertising: Mutex<M, Cell<Option<Advertising>>>,
     pub(crate) pool: &'d dyn DynamicPacketPool<'d>,
     pub(crate) permits: GreedySemaphore<NoopRawMutex>,
 
@@ -117,6 +143,8 @@ where
             channels: ChannelManager::new(&host_resources.pool),
             pool: &host_resources.pool,
             att_inbound: Channel::new(),
+            fixed_channels: Mutex::new(RefCell::new(Vec::new())),
+            advertising: Mutex::new(Cell::new(None)),
             scanner: Channel::new(),
             permits: GreedySemaphore::new(0),
         }
@@ -185,8 +213,138 @@ where
         Ok(())
     }
 
+    /// Register a PSM that peers may open L2CAP channels to.
+    ///
+    /// Connection requests for unregistered PSMs are refused, as are requests on links that do
+    /// not meet the security level of the registration. Up to `CHANNELS` PSMs can be registered.
+    pub fn register_psm(&self, registration: PsmRegistration) -> Result<(), AdapterError<T::Error>> {
+        self.channels.register(registration)?;
+        Ok(())
+    }
+
+    /// Register a handler for a custom fixed channel, replacing any handler registered for it before.
+    ///
+    /// Only channel identifiers between [`L2CAP_CID_FIXED_CUSTOM_START`] and [`L2CAP_CID_FIXED_CUSTOM_END`] can be
+    /// registered. PDUs received on fixed channels without a handler are dropped.
+    ///
+    /// [`L2CAP_CID_FIXED_CUSTOM_START`]: crate::l2cap::L2CAP_CID_FIXED_CUSTOM_START
+    /// [`L2CAP_CID_FIXED_CUSTOM_END`]: crate::l2cap::L2CAP_CID_FIXED_CUSTOM_END
+    pub fn register_fixed_channel(
+        &self,
+        cid: u16,
+        handler: &'d dyn FixedChannelHandler,
+    ) -> Result<(), AdapterError<T::Error>> {
+        if !(L2CAP_CID_FIXED_CUSTOM_START..=L2CAP_CID_FIXED_CUSTOM_END).contains(&cid) {
+            return Err(Error::InvalidChannelId.into());
+        }
+        self.fixed_channels.lock(|channels| {
+            let mut channels = channels.borrow_mut();
+            if let Some(existing) = channels.iter_mut().find(|(c, _)| *c == cid) {
+                existing.1 = handler;
+                return Ok(());
+            }
+            channels
+                .push((cid, handler))
+                .map_err(|_| Error::InsufficientSpace.into())
+        })
+    }
+
+    /// Send a PDU on a custom fixed channel registered with [`Adapter::register_fixed_channel`].
+    ///
+    /// The PDU must fit in a single packet of the adapter packet pool, including the 4 byte L2CAP header.
+    pub async fn send_fixed(
+        &self,
+        connection: &Connection<'_>,
+        cid: u16,
+        pdu: &[u8],
+    ) -> Result<(), AdapterError<T::Error>> {
+        if self.fixed_channel_handler(cid).is_none() {
+            return Err(Error::InvalidChannelId.into());
+        }
+        let Some(mut packet) = self.pool.alloc(FIXED_ID) else {
+            return Err(Error::OutOfMemory.into());
+        };
+        if pdu.len() + 4 > packet.len() {
+            return Err(Error::MtuExceeded.into());
+        }
+        let mut w = WriteCursor::new(packet.as_mut());
+        w.write(pdu.len() as u16)?;
+        w.write(cid)?;
+        w.append(pdu)?;
+        let len = w.len();
+        self.hci()
+            .send(connection.live_handle()?, Pdu::new(packet, len).as_ref())
+            .await
+    }
+
+    fn fixed_channel_handler(&self, cid: u16) -> Option<&'d dyn FixedChannelHandler> {
+        self.fixed_channels.lock(|channels| {
+            channels
+                .borrow()
+                .iter()
+                .find(|(c, _)| *c == cid)
+                .map(|(_, handler)| *handler)
+        })
+    }
+
+    // Whether packets received on a channel have somewhere to go, fixed channels without a handler are dropped.
+    fn accepts_channel(&self, cid: u16) -> bool {
+        match cid {
+            L2CAP_CID_ATT => cfg!(feature = "gatt"),
+            cid if cid >= L2CAP_CID_DYN_START => true,
+            cid => self.fixed_channel_handler(cid).is_some(),
+        }
+    }
+
+    /// Answer the next connection parameter update request sent by a peripheral.
+    ///
+    /// The policy decides whether the requested parameters are accepted, in which case they are
+    /// applied using the LE Connection Update command. Requests arriving while no task is waiting
+    /// here are rejected, and those received while acting as a peripheral get a Command Reject.
+    pub async fn handle_connection_params_request(
+        &self,
+        policy: &dyn ConnectionParamsPolicy,
+    ) -> Result<ConnectionParamsUpdate<'_>, AdapterError<T::Error>>
+    where
+        T: ControllerCmdAsync<LeConnUpdate>,
+    {
+        let (handle, identifier, req) = self.channels.conn_param_request().await;
+        let connection = self.connections.connection(handle).ok_or(Error::Disconnected)?;
+        let params = ConnectParams::from_update_request(&req);
+        let accepted =
+            matches!(self.connections.role(handle), Ok(LeConnRole::Central)) && policy.accept(&connection, &params);
+
+        let mut tx = [0; 16];
+        let res = ConnParamUpdateRes {
+            result: if accepted {
+                CONN_PARAM_UPDATE_ACCEPTED
+            } else {
+                CONN_PARAM_UPDATE_REJECTED
+            },
+        };
+        self.hci().signal(handle, identifier, &res, &mut tx[..]).await?;
+
+        if accepted {
+            self.async_command(LeConnUpdate::new(
+                handle,
+                params.min_connection_interval.into(),
+                params.max_connection_interval.into(),
+                params.max_latency,
+                params.supervision_timeout.into(),
+                bt_hci::param::Duration::from_secs(0),
+                bt_hci::param::Duration::from_secs(0),
+            ))
+            .await?;
+        }
+        Ok(ConnectionParamsUpdate {
+            connection,
+            params,
+            accepted,
+        })
+    }
+
     /// Attempt to create a connection with the provided config.
-    pub async fn connect(&self, config: &ConnectConfig<'_>) -> Result<Connection, AdapterError<T::Error>>
+    pub async fn connect(&self, config: &ConnectConfig<'_>) -> Result<Connection<'_>, AdapterError<T::Error>>
     where
         T: ControllerCmdSync<LeClearFilterAcceptList>
             + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
@@ -220,12 +378,11 @@ where
             config.connect_params.event_length.into(),
         ))
         .await?;
-        let handle = self.connections.accept(config.scan_config.filter_accept_list).await;
-        Ok(Connection::new(handle))
+        Ok(self.connections.accept(config.scan_config.filter_accept_list).await)
     }
 
     /// Attempt to create a connection with the provided config.
-    pub async fn connect_ext(&self, config: &ConnectConfig<'_>) -> Result<Connection, AdapterError<T::Error>>
+    pub async fn connect_ext(&self, config: &ConnectConfig<'_>) -> Result<Connection<'_>, AdapterError<T::Error>>
     where
         T: ControllerCmdSync<LeClearFilterAcceptList>
             + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
@@ -265,8 +422,7 @@ where
             phy_params,
         ))
         .await?;
-        let handle = self.connections.accept(config.scan_config.filter_accept_list).await;
-        Ok(Connection::new(handle))
+        Ok(self.connections.accept(config.scan_config.filter_accept_list).await)
     }
 
     fn create_phy_params<P: Copy>(phy: P, phys: PhySet) -> PhyParams<P> {
@@ -414,7 +570,29 @@ where
         &self,
         config: &AdvertisementConfig,
         params: Advertisement<'k>,
-    ) -> Result<Connection, AdapterError<T::Error>>
+    ) -> Result<Connection<'_>, AdapterError<T::Error>>
+    where
+        T: for<'t> ControllerCmdSync<LeSetAdvData>
+            + ControllerCmdSync<LeSetAdvParams>
+            + for<'t> ControllerCmdSync<LeSetAdvEnable>
+            + for<'t> ControllerCmdSync<LeSetScanResponseData>,
+    {
+        self.start_advertising(config, params).await?;
+        self.advertising.lock(|a| a.set(None));
+        let connection = self.connections.accept(&[]).await;
+        self.command(LeSetAdvEnable::new(false)).await?;
+        Ok(connection)
+    }
+
+    /// Start sending legacy advertisements according to the provided config, without waiting for a connection.
+    ///
+    /// Connections are obtained with [`Adapter::accept`], which resumes advertising after each connection as long
+    /// as a connection slot is free for the next central.
+    pub async fn start_advertising<'k>(
+        &self,
+        config: &AdvertisementConfig,
+        params: Advertisement<'k>,
+    ) -> Result<(), AdapterError<T::Error>>
     where
         T: for<'t> ControllerCmdSync<LeSetAdvData>
             + ControllerCmdSync<LeSetAdvParams>
@@ -423,6 +601,7 @@ where
     {
         // May fail if already disabled
         let _ = self.command(LeSetAdvEnable::new(false)).await;
+        self.advertising.lock(|a| a.set(None));
 
         let mut params: RawAdvertisement = params.into();
         let timeout = config
@@ -476,9 +655,23 @@ where
         }
 
         self.command(LeSetAdvEnable::new(true)).await?;
-        let handle = self.connections.accept(&[]).await;
+        self.advertising.lock(|a| {
+            a.set(Some(Advertising {
+                set: None,
+                paused: false,
+            }))
+        });
+        Ok(())
+    }
+
+    /// Stop advertising started with [`Adapter::start_advertising`].
+    pub async fn stop_advertising(&self) -> Result<(), AdapterError<T::Error>>
+    where
+        T: ControllerCmdSync<LeSetAdvEnable>,
+    {
+        self.advertising.lock(|a| a.set(None));
         self.command(LeSetAdvEnable::new(false)).await?;
-        Ok(Connection::new(handle))
+        Ok(())
     }
 
     /// Starts sending BLE advertisements according to the provided config.
@@ -489,7 +682,31 @@ where
         &self,
         config: &AdvertisementConfig,
         params: impl Into<RawAdvertisement<'k>>,
-    ) -> Result<Connection, AdapterError<T::Error>>
+    ) -> Result<Connection<'_>, AdapterError<T::Error>>
+    where
+        T: for<'t> ControllerCmdSync<LeSetExtAdvData<'t>>
+            + ControllerCmdSync<LeClearAdvSets>
+            + ControllerCmdSync<LeSetExtAdvParams>
+            + ControllerCmdSync<LeSetAdvSetRandomAddr>
+            + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
+            + for<'t> ControllerCmdSync<LeSetExtScanResponseData<'t>>,
+    {
+        self.start_advertising_ext(config, params).await?;
+        self.advertising.lock(|a| a.set(None));
+        let connection = self.connections.accept(&[]).await;
+        self.command(LeSetExtAdvEnable::new(false, &[])).await?;
+        Ok(connection)
+    }
+
+    /// Start sending extended advertisements according to the provided config, without waiting for a connection.
+    ///
+    /// Connections are obtained with [`Adapter::accept`], which resumes advertising after each connection as long
+    /// as a connection slot is free for the next central.
+    pub async fn start_advertising_ext<'k>(
+        &self,
+        config: &AdvertisementConfig,
+        params: impl Into<RawAdvertisement<'k>>,
+    ) -> Result<(), AdapterError<T::Error>>
     where
         T: for<'t> ControllerCmdSync<LeSetExtAdvData<'t>>
             + ControllerCmdSync<LeClearAdvSets>
@@ -500,6 +717,7 @@ where
     {
         // May fail if already disabled
         let _ = self.command(LeSetExtAdvEnable::new(false, &[])).await;
+        self.advertising.lock(|a| a.set(None));
         let _ = self.command(LeClearAdvSets::new()).await;
         let handle = AdvHandle::new(0); // TODO: Configurable?
 
@@ -561,9 +779,99 @@ where
         }
 
         self.command(LeSetExtAdvEnable::new(true, &[params.set])).await?;
-        let handle = self.connections.accept(&[]).await;
+        self.advertising.lock(|a| {
+            a.set(Some(Advertising {
+                set: Some(params.set),
+                paused: false,
+            }))
+        });
+        Ok(())
+    }
+
+    /// Stop advertising started with [`Adapter::start_advertising_ext`].
+    pub async fn stop_advertising_ext(&self) -> Result<(), AdapterError<T::Error>>
+    where
+        T: for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>,
+    {
+        self.advertising.lock(|a| a.set(None));
         self.command(LeSetExtAdvEnable::new(false, &[])).await?;
-        Ok(Connection::new(handle))
+        Ok(())
+    }
+
+    /// Wait for the next connection established by this adapter, as central or peripheral, whichever call
+    /// started the procedure.
+    ///
+    /// Connections beyond the number of connection slots are refused. When a central connects while advertising
+    /// started with [`Adapter::start_advertising`] or [`Adapter::start_advertising_ext`], advertising is resumed
+    /// for the next central once a connection slot is free. If the controller refuses to resume advertising, it
+    /// stays stopped, and the error is returned unless a connection is being returned.
+    pub async fn accept(&self) -> Result<Connection<'_>, AdapterError<T::Error>>
+    where
+        T: ControllerCmdSync<LeSetAdvEnable> + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>,
+    {
+        loop {
+            match self.next_connection().await {
+                Some(connection) => {
+                    if self.accepted(&connection) && self.resume_advertising().await.is_err() {
+                        warn!("[adapter] unable to resume advertising");
+                    }
+                    return Ok(connection);
+                }
+                None => self.resume_advertising().await?,
+            }
+        }
+    }
+
+    // Wait for the next connection, or return None once a connection slot is released while advertising is
+    // paused.
+    async fn next_connection(&self) -> Option<Connection<'_>> {
+        let paused = self.advertising.lock(|a| a.get().is_some_and(|a| a.paused));
+        if !paused {
+            return Some(self.connections.accept(&[]).await);
+        }
+        match select(self.connections.accept(&[]), self.connections.wait_free_slot()).await {
+            Either::First(connection) => Some(connection),
+            Either::Second(_) => None,
+        }
+    }
+
+    // Whether advertising should be resumed after a central connected. Advertising is paused until a connection
+    // slot is released if none is free.
+    fn accepted(&self, connection: &Connection<'_>) -> bool {
+        if !matches!(self.connections.role(connection.handle()), Ok(LeConnRole::Peripheral)) {
+            return false;
+        }
+        if self.connections.has_free_slot() {
+            return true;
+        }
+        self.advertising.lock(|a| {
+            if let Some(mut advertising) = a.get() {
+                advertising.paused = true;
+                a.set(Some(advertising));
+            }
+        });
+        false
+    }
+
+    async fn resume_advertising(&self) -> Result<(), AdapterError<T::Error>>
+    where
+        T: ControllerCmdSync<LeSetAdvEnable> + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>,
+    {
+        let Some(mut advertising) = self.advertising.lock(|a| a.get()) else {
+            return Ok(());
+        };
+        let result = match advertising.set {
+            Some(set) => self.command(LeSetExtAdvEnable::new(true, &[set])).await,
+            None => self.command(LeSetAdvEnable::new(true)).await,
+        };
+        advertising.paused = false;
+        self.advertising.lock(|a| {
+            // Advertising may have been stopped in the meantime, and is given up if the controller refuses.
+            if a.get().is_some() {
+                a.set(result.is_ok().then_some(advertising));
+            }
+        });
+        result
     }
 
     /// Creates a GATT server capable of processing the GATT protocol using the provided table of attributes.
@@ -591,13 +899,35 @@ where
                 // Avoids using the packet buffer for signalling packets
                 if header.channel == L2CAP_CID_LE_U_SIGNAL {
                     assert!(data.len() == header.length as usize);
-                    self.channels.signal(acl.handle(), &data).await?;
+                    let role = self.connections.role(acl.handle()).unwrap_or(LeConnRole::Peripheral);
+                    let security = self
+                        .connections
+                        .security_level(acl.handle())
+                        .unwrap_or(SecurityLevel::NoEncryption);
+                    self.channels
+                        .signal(acl.handle(), &data, role, security, &self.hci())
+                        .await?;
                     return Ok(());
                 }
 
-                let Some(mut p) = self.pool.alloc(AllocId::from_channel(header.channel)) else {
+                if !self.accepts_channel(header.channel) {
+                    warn!("[l2cap] dropping packet for unknown channel {}", header.channel);
+                    return Ok(());
+                }
+
+                let Some(mut p) = self.pool.alloc(AllocId::from_channel(header.channel)?) else {
                     return Err(Error::OutOfMemory);
                 };
+                if header.length as usize > p.len() || data.len() > p.len() {
+                    warn!(
+                        "[l2cap] frame of {} bytes on channel {} too large",
+                        header.length, header.channel
+                    );
+                    if header.channel >= L2CAP_CID_DYN_START {
+                        self.channels.abort(acl.handle(), header.channel, &self.hci());
+                    }
+                    return Err(Error::MtuExceeded);
+                }
                 p.as_mut()[..data.len()].copy_from_slice(data);
 
                 if header.length as usize != data.len() {
@@ -634,14 +964,24 @@ where
             L2CAP_CID_LE_U_SIGNAL => {
                 panic!("le signalling channel was fragmented, impossible!");
             }
-            other if other >= L2CAP_CID_DYN_START => match self.channels.dispatch(header, packet).await {
-                Ok(_) => {}
-                Err(e) => {
-                    warn!("Error dispatching l2cap packet to channel: {:?}", e);
+            other if other >= L2CAP_CID_DYN_START => {
+                match self.channels.dispatch(acl.handle(), header, packet, &self.hci()).await {
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("Error dispatching l2cap packet to channel: {:?}", e);
+                    }
+                }
+            }
+            other => {
+                if let Some(handler) = self.fixed_channel_handler(other) {
+                    let pdu = Pdu::new(packet, header.length as usize);
+                    match self.connections.connection(acl.handle()) {
+                        Some(connection) => handler.on_pdu(&connection, other, pdu.as_ref()),
+                        None => warn!("[l2cap] dropping packet for unknown connection"),
+                    }
+                } else {
+                    warn!("[l2cap] dropping packet for unknown channel {}", other);
                 }
-            },
-            _ => {
-                unimplemented!()
             }
         }
         Ok(())
@@ -704,6 +1044,7 @@ where
                     .enable_conn_request(true)
                     .enable_conn_complete(true)
                     .enable_hardware_error(true)
+                    .enable_encryption_change_v1(true)
                     .enable_disconnection_complete(true),
             )
             .exec(&self.controller)
@@ -712,7 +1053,9 @@ where
             LeSetEventMask::new(
                 LeEventMask::new()
                     .enable_le_conn_complete(true)
-                    //                    .enable_le_conn_update_complete(true)
+                    .enable_le_conn_update_complete(true)
+                    .enable_le_phy_update_complete(true)
+                    .enable_le_data_length_change(true)
                     //                    .enable_le_enhanced_conn_complete(true)
                     //                    .enable_le_conn_iq_report(true)
                     //                    .enable_le_transmit_power_reporting(true)
@@ -780,6 +1123,42 @@ where
                                     .send(Some(ScanReport::new(data.reports.num_reports, &data.reports.bytes)))
                                     .await;
                             }
+                            LeEvent::LeConnectionUpdateComplete(e) => {
+                                if e.status.to_result().is_ok() {
+                                    let _ = self.connections.post_event(
+                                        e.handle,
+                                        ConnectionEvent::ConnectionParamsUpdated {
+                                            interval: Duration::from_micros(e.conn_interval.as_micros()),
+                                            latency: e.peripheral_latency,
+                                            supervision_timeout: Duration::from_micros(
+                                                e.supervision_timeout.as_micros(),
+                                            ),
+                                        },
+                                    );
+                                }
+                            }
+                            LeEvent::LePhyUpdateComplete(e) => {
+                                if e.status.to_result().is_ok() {
+                                    let _ = self.connections.post_event(
+                                        e.handle,
+                                        ConnectionEvent::PhyUpdated {
+                                            tx_phy: e.tx_phy,
+                                            rx_phy: e.rx_phy,
+                                        },
+                                    );
+                                }
+                            }
+                            LeEvent::LeDataLengthChange(e) => {
+                                let _ = self.connections.post_event(
+                                    e.handle,
+                                    ConnectionEvent::DataLengthChanged {
+                                        max_tx_octets: e.max_tx_octets,
+                                        max_tx_time: e.max_tx_time,
+                                        max_rx_octets: e.max_rx_octets,
+                                        max_rx_time: e.max_rx_time,
+                                    },
+                                );
+                            }
                             _ => {
                                 warn!("Unknown LE event!");
                             }
@@ -787,9 +1166,16 @@ where
                         Event::DisconnectionComplete(e) => {
                             disconnects += 1;
                             info!("Disconnected (total {}): {:?}", disconnects, e);
-                            let _ = self.connections.disconnect(e.handle);
+                            let _ = self.connections.disconnect(e.handle, e.reason);
                             let _ = self.channels.disconnected(e.handle);
                         }
+                        Event::EncryptionChangeV1(e) => {
+                            let level = match (e.status.to_result(), e.enabled) {
+                                (Ok(_), EncryptionEnabledLevel::Off) | (Err(_), _) => SecurityLevel::NoEncryption,
+                                (Ok(_), _) => SecurityLevel::Encrypted,
+                            };
+                            let _ = self.connections.set_security_level(e.handle, level);
+                        }
                         Event::NumberOfCompletedPackets(c) => {
                             // info!("Confirmed {} packets sent", c.completed_packets.len());
                             self.permits.release(c.completed_packets.len());
@@ -897,7 +1283,7 @@ impl<'d, T: Controller> HciController<'d, T> {
         Ok(())
     }
 
-    pub(crate) async fn signal<D: L2capSignal>(
+    pub(crate) async fn signal<D: L2capSignal + WriteHci>(
         &self,
         handle: ConnHandle,
         identifier: u8,
@@ -924,6 +1310,57 @@ impl<'d, T: Controller> HciController<'d, T> {
         Ok(())
     }
 
+    /// Send a signal without waiting for ACL buffer space.
+    pub(crate) fn try_signal<D: L2capSignal + WriteHci>(
+        &self,
+        handle: ConnHandle,
+        identifier: u8,
+        signal: &D,
+        p_buf: &mut [u8],
+    ) -> Result<(), AdapterError<T::Error>> {
+        let header = L2capSignalHeader {
+            identifier,
+            code: D::code(),
+            length: signal.size() as u16,
+        };
+        let l2cap = L2capHeader {
+            channel: D::channel(),
+            length: header.size() as u16 + header.length,
+        };
+
+        let mut w = WriteCursor::new(p_buf);
+        w.write_hci(&l2cap)?;
+        w.write_hci(&header)?;
+        w.write_hci(signal)?;
+
+        self.try_send(handle, w.finish())
+    }
+
+    /// Send a signal whose payload has a variable length, such as the enhanced credit based requests.
+    pub(crate) async fn signal_encoded<D: L2capSignal + Encode>(
+        &self,
+        handle: ConnHandle,
+        identifier: u8,
+        signal: &D,
+        p_buf: &mut [u8],
+    ) -> Result<(), AdapterError<T::Error>> {
+        let len = encode_signal(identifier, signal, p_buf)?;
+        self.send(handle, &p_buf[..len]).await?;
+        Ok(())
+    }
+
+    /// Send a variable length signal without waiting for ACL buffer space.
+    pub(crate) fn try_signal_encoded<D: L2capSignal + Encode>(
+        &self,
+        handle: ConnHandle,
+        identifier: u8,
+        signal: &D,
+        p_buf: &mut [u8],
+    ) -> Result<(), AdapterError<T::Error>> {
+        let len = encode_signal(identifier, signal, p_buf)?;
+        self.try_send(handle, &p_buf[..len])
+    }
+
     pub(crate) fn try_command<C>(&self, cmd: C) -> Result<C::Return, AdapterError<T::Error>>
     where
         C: SyncCmd,
@@ -940,3 +1377,93 @@ impl<'d, T: Controller> HciController<'d, T> {
         }
     }
 }
+
+fn encode_signal<D: L2capSignal + Encode>(identifier: u8, signal: &D, p_buf: &mut [u8]) -> Result<usize, Error> {
+    let header = L2capSignalHeader {
+        identifier,
+        code: D::code(),
+        length: signal.size() as u16,
+    };
+    let l2cap = L2capHeader {
+        channel: D::channel(),
+        length: header.size() as u16 + header.length,
+    };
+
+    let mut w = WriteCursor::new(p_buf);
+    w.write_hci(&l2cap)?;
+    w.write_hci(&header)?;
+    w.write_ref(signal)?;
+    Ok(w.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use bt_hci::param::Status;
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_controller::{connect, MockController};
+
+    fn advertise<const CONNS: usize>(adapter: &Adapter<'_, NoopRawMutex, MockController, CONNS, 1, 64>) {
+        adapter.advertising.lock(|a| {
+            a.set(Some(Advertising {
+                set: None,
+                paused: false,
+            }))
+        });
+    }
+
+    fn paused<const CONNS: usize>(adapter: &Adapter<'_, NoopRawMutex, MockController, CONNS, 1, 64>) -> bool {
+        adapter.advertising.lock(|a| a.get().is_some_and(|a| a.paused))
+    }
+
+    #[test]
+    fn test_advertising_resumed_with_free_slot() {
+        let mut resources: HostResources<NoopRawMutex, 1, 4, 64> = HostResources::new(Qos::None);
+        let adapter: Adapter<'_, NoopRawMutex, MockController, 2, 1, 64> =
+            Adapter::new(MockController::new(), &mut resources);
+        advertise(&adapter);
+
+        // Another central may connect, advertising is resumed right away.
+        connect(&adapter.connections, 1, LeConnRole::Peripheral).unwrap();
+        let connection = block_on(adapter.next_connection()).unwrap();
+        assert!(adapter.accepted(&connection));
+        assert!(!paused(&adapter));
+
+        // Connections as central do not affect advertising.
+        connect(&adapter.connections, 2, LeConnRole::Central).unwrap();
+        let central = block_on(adapter.next_connection()).unwrap();
+        assert!(!adapter.accepted(&central));
+        assert!(!paused(&adapter));
+    }
+
+    #[test]
+    fn test_advertising_resumed_when_slot_released() {
+        let mut resources: HostResources<NoopRawMutex, 1, 4, 64> = HostResources::new(Qos::None);
+        let adapter: Adapter<'_, NoopRawMutex, MockController, 1, 1, 64> =
+            Adapter::new(MockController::new(), &mut resources);
+        advertise(&adapter);
+
+        // Advertising is paused while every connection slot is in use.
+        connect(&adapter.connections, 1, LeConnRole::Peripheral).unwrap();
+        let connection = block_on(adapter.next_connection()).unwrap();
+        assert!(!adapter.accepted(&connection));
+        assert!(paused(&adapter));
+        assert!(embassy_futures::poll_once(adapter.next_connection()).is_pending());
+
+        // Once the link is closed and its last handle dropped, advertising is to be resumed.
+        let (next, _) = block_on(embassy_futures::join::join(adapter.next_connection(), async {
+            embassy_futures::yield_now().await;
+            let reason = Status::from_hci_bytes_complete(&[0x13]).unwrap();
+            adapter.connections.disconnect(connection.handle(), reason).unwrap();
+            drop(connection);
+        }));
+        assert!(next.is_none());
+
+        // A new connection while paused is still accepted.
+        connect(&adapter.connections, 2, LeConnRole::Peripheral).unwrap();
+        let connection = block_on(adapter.next_connection()).unwrap();
+        assert_eq!(connection.handle(), ConnHandle::new(2));
+    }
+}
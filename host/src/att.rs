@@ -27,6 +27,8 @@ pub const ATT_READ_MULTIPLE_RES_OPCODE: u8 = 0x21;
 pub const ATT_READ_BLOB_REQ_OPCODE: u8 = 0x0c;
 pub const ATT_READ_BLOB_RESP_OPCODE: u8 = 0x0d;
 pub const ATT_HANDLE_VALUE_NTF_OPTCODE: u8 = 0x1b;
+pub const ATT_HANDLE_VALUE_IND_OPCODE: u8 = 0x1d;
+pub const ATT_HANDLE_VALUE_CFM_OPCODE: u8 = 0x1e;
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(Debug)]
@@ -119,6 +121,7 @@ pub enum Att<'d> {
         handle: u16,
         offset: u16,
     },
+    HandleValueConfirmation,
 }
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -249,6 +252,7 @@ impl<'d> Att<'d> {
                 let offset = (payload[2] as u16) + ((payload[3] as u16) << 8);
                 Ok(Self::ReadBlobReq { handle, offset })
             }
+            ATT_HANDLE_VALUE_CFM_OPCODE => Ok(Self::HandleValueConfirmation),
             _ => Err(AttDecodeError::UnknownOpcode(opcode)),
         }
     }
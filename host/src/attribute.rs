@@ -1,23 +1,43 @@
 use core::cell::RefCell;
 use core::fmt;
+use core::marker::PhantomData;
+use core::ops::RangeInclusive;
+use core::task::{Context, Poll};
 
+use bt_hci::controller::Controller;
+use bt_hci::param::ConnHandle;
 use embassy_sync::blocking_mutex::raw::RawMutex;
 use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::waitqueue::WakerRegistration;
+use heapless::Vec;
 
 use crate::att::AttErrorCode;
+use crate::attribute_server::{NotificationTable, MAX_NOTIFICATIONS};
+use crate::connection::{ConnectParams, Connection};
 use crate::cursor::WriteCursor;
+use crate::gatt::GattServer;
+pub use crate::types::gatt_traits::{FromGattError, GattValue};
 pub use crate::types::uuid::Uuid;
-use crate::Error;
+use crate::{AdapterError, Error};
 
 pub const GENERIC_ACCESS_SERVICE_UUID16: Uuid = Uuid::Uuid16(0x1800u16.to_le_bytes());
 pub const CHARACTERISTIC_DEVICE_NAME_UUID16: Uuid = Uuid::Uuid16(0x2A00u16.to_le_bytes());
-pub const CHARACTERISTIC_APPEARANCE_UUID16: Uuid = Uuid::Uuid16(0x2A03u16.to_le_bytes());
+pub const CHARACTERISTIC_APPEARANCE_UUID16: Uuid = Uuid::Uuid16(0x2A01u16.to_le_bytes());
+pub const CHARACTERISTIC_PPCP_UUID16: Uuid = Uuid::Uuid16(0x2A04u16.to_le_bytes());
+pub const CHARACTERISTIC_CENTRAL_ADDRESS_RESOLUTION_UUID16: Uuid = Uuid::Uuid16(0x2AA6u16.to_le_bytes());
 
 pub const GENERIC_ATTRIBUTE_SERVICE_UUID16: Uuid = Uuid::Uuid16(0x1801u16.to_le_bytes());
+pub const CHARACTERISTIC_SERVICE_CHANGED_UUID16: Uuid = Uuid::Uuid16(0x2A05u16.to_le_bytes());
 
 pub const PRIMARY_SERVICE_UUID16: Uuid = Uuid::Uuid16(0x2800u16.to_le_bytes());
+pub const SECONDARY_SERVICE_UUID16: Uuid = Uuid::Uuid16(0x2801u16.to_le_bytes());
+pub const INCLUDE_UUID16: Uuid = Uuid::Uuid16(0x2802u16.to_le_bytes());
 pub const CHARACTERISTIC_UUID16: Uuid = Uuid::Uuid16(0x2803u16.to_le_bytes());
+pub const CHARACTERISTIC_EXTENDED_PROPERTIES_UUID16: Uuid = Uuid::Uuid16(0x2900u16.to_le_bytes());
+pub const CHARACTERISTIC_USER_DESCRIPTION_UUID16: Uuid = Uuid::Uuid16(0x2901u16.to_le_bytes());
 pub const CHARACTERISTIC_CCCD_UUID16: Uuid = Uuid::Uuid16(0x2902u16.to_le_bytes());
+pub const CHARACTERISTIC_SCCD_UUID16: Uuid = Uuid::Uuid16(0x2903u16.to_le_bytes());
+pub const CHARACTERISTIC_PRESENTATION_FORMAT_UUID16: Uuid = Uuid::Uuid16(0x2904u16.to_le_bytes());
 pub const GENERIC_ATTRIBUTE_UUID16: Uuid = Uuid::Uuid16(0x1801u16.to_le_bytes());
 
 #[derive(Debug, Clone, Copy)]
@@ -44,40 +64,71 @@ impl<'a> Attribute<'a> {
     const EMPTY: Option<Attribute<'a>> = None;
 }
 
+/// Maximum size of values stored inline in the attribute table.
+pub const INLINE_VALUE_MAX: usize = 8;
+
+/// Maximum length of the device name, as defined by the Generic Access service.
+pub const DEVICE_NAME_MAX: usize = 248;
+
 pub enum AttributeData<'d> {
     Service {
         uuid: Uuid,
+        /// Last handle reserved for the service, if it was assigned an explicit handle range.
+        reserved_end: Option<u16>,
     },
     ReadOnlyData {
         props: CharacteristicProps,
         value: &'d [u8],
     },
+    /// Value stored in user provided storage, whose length is the capacity of the value.
+    ///
+    /// For variable length values, `len` tracks the current length of the value, and writes shorter than the
+    /// capacity shrink the value. Fixed length values always have `len` equal to the capacity, and must be
+    /// written as a whole.
     Data {
         props: CharacteristicProps,
         value: &'d mut [u8],
+        len: usize,
+        variable_len: bool,
+    },
+    /// Small read-only value stored in the table itself.
+    Inline {
+        props: CharacteristicProps,
+        value: [u8; INLINE_VALUE_MAX],
+        len: usize,
     },
     Declaration {
         props: CharacteristicProps,
         handle: u16,
         uuid: Uuid,
     },
+    /// Include declaration referring to another service.
+    Include {
+        handle: u16,
+        end_group_handle: u16,
+        uuid: Uuid,
+    },
     Cccd {
         notifications: bool,
         indications: bool,
     },
+    /// Server Characteristic Configuration descriptor.
+    Sccd {
+        broadcasts: bool,
+    },
 }
 
 impl<'d> AttributeData<'d> {
     pub fn readable(&self) -> bool {
         match self {
-            Self::Data { props, value } => props.0 & (CharacteristicProp::Read as u8) != 0,
+            Self::Data { props, .. } => props.0 & (CharacteristicProp::Read as u8) != 0,
             _ => true,
         }
     }
 
     pub fn writable(&self) -> bool {
         match self {
-            Self::Data { props, value } => {
+            Self::Data { props, .. } => {
                 props.0
                     & (CharacteristicProp::Write as u8
                         | CharacteristicProp::WriteWithoutResponse as u8
@@ -88,6 +139,7 @@ impl<'d> AttributeData<'d> {
                 notifications,
                 indications,
             } => true,
+            Self::Sccd { .. } => true,
             _ => false,
         }
     }
@@ -107,7 +159,8 @@ impl<'d> AttributeData<'d> {
                 }
                 Ok(len)
             }
-            Self::Data { props, value } => {
+            Self::Data { value, len, .. } => {
+                let value = &value[..*len];
                 if offset > value.len() {
                     return Ok(0);
                 }
@@ -117,7 +170,18 @@ impl<'d> AttributeData<'d> {
                 }
                 Ok(len)
             }
-            Self::Service { uuid } => {
+            Self::Inline { value, len, .. } => {
+                let value = &value[..*len];
+                if offset > value.len() {
+                    return Ok(0);
+                }
+                let len = data.len().min(value.len() - offset);
+                if len > 0 {
+                    data[..len].copy_from_slice(&value[offset..offset + len]);
+                }
+                Ok(len)
+            }
+            Self::Service { uuid, .. } => {
                 let val = uuid.as_raw();
                 if offset > val.len() {
                     return Ok(0);
@@ -149,6 +213,42 @@ impl<'d> AttributeData<'d> {
                 data[0] = v;
                 Ok(2)
             }
+            Self::Sccd { broadcasts } => {
+                if offset > 0 {
+                    return Err(AttErrorCode::InvalidOffset);
+                }
+                if data.len() < 2 {
+                    return Err(AttErrorCode::UnlikelyError);
+                }
+                data[0] = *broadcasts as u8;
+                data[1] = 0;
+                Ok(2)
+            }
+            Self::Include {
+                handle,
+                end_group_handle,
+                uuid,
+            } => {
+                // The service UUID is only included if it is a 16-bit UUID
+                let mut val = [0; 6];
+                val[..2].copy_from_slice(&handle.to_le_bytes());
+                val[2..4].copy_from_slice(&end_group_handle.to_le_bytes());
+                let val = match uuid {
+                    Uuid::Uuid16(uuid) => {
+                        val[4..].copy_from_slice(uuid);
+                        &val[..]
+                    }
+                    _ => &val[..4],
+                };
+                if offset > val.len() {
+                    return Ok(0);
+                }
+                let len = data.len().min(val.len() - offset);
+                if len > 0 {
+                    data[..len].copy_from_slice(&val[offset..offset + len]);
+                }
+                Ok(len)
+            }
             Self::Declaration { props, handle, uuid } => {
                 let val = uuid.as_raw();
                 if offset > val.len() + 3 {
@@ -174,40 +274,76 @@ impl<'d> AttributeData<'d> {
         }
     }
 
-    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), AttErrorCode> {
-        let writable = self.writable();
-
+    /// Check whether a value of `len` bytes can be written at `offset`, without writing it.
+    pub fn check_write(&self, offset: usize, len: usize) -> Result<(), AttErrorCode> {
         match self {
-            Self::Data { value, props } => {
-                if !writable {
+            Self::Data {
+                value,
+                len: current,
+                variable_len,
+                ..
+            } => {
+                if !self.writable() {
                     return Err(AttErrorCode::WriteNotPermitted);
                 }
 
-                if offset + data.len() < value.len() {
-                    value[offset..offset + data.len()].copy_from_slice(data);
-                    Ok(())
-                } else {
-                    Err(AttErrorCode::InvalidOffset)
+                if offset > *current {
+                    return Err(AttErrorCode::InvalidOffset);
                 }
+
+                if offset + len > value.len() {
+                    return Err(AttErrorCode::InvalidAttributeValueLength);
+                }
+
+                // Fixed length values are only written as a whole, so that a short write can't leave a value
+                // which is partly old and partly new.
+                if !*variable_len && (offset != 0 || len != value.len()) {
+                    return Err(AttErrorCode::InvalidAttributeValueLength);
+                }
+                Ok(())
             }
-            Self::Cccd {
-                notifications,
-                indications,
-            } => {
+            Self::Cccd { .. } | Self::Sccd { .. } => {
                 if offset > 0 {
                     return Err(AttErrorCode::InvalidOffset);
                 }
 
-                if data.is_empty() {
+                if len == 0 {
                     return Err(AttErrorCode::UnlikelyError);
                 }
+                Ok(())
+            }
+            _ => Err(AttErrorCode::WriteNotPermitted),
+        }
+    }
 
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), AttErrorCode> {
+        self.check_write(offset, data.len())?;
+
+        match self {
+            Self::Data {
+                value,
+                len,
+                variable_len,
+                ..
+            } => {
+                value[offset..offset + data.len()].copy_from_slice(data);
+                if *variable_len {
+                    *len = offset + data.len();
+                }
+            }
+            Self::Cccd {
+                notifications,
+                indications,
+            } => {
                 *notifications = data[0] & 0x01 != 0;
                 *indications = data[0] & 0x02 != 0;
-                Ok(())
             }
-            _ => Err(AttErrorCode::WriteNotPermitted),
+            Self::Sccd { broadcasts } => {
+                *broadcasts = data[0] & 0x01 != 0;
+            }
+            _ => {}
         }
+        Ok(())
     }
 }
 
@@ -241,23 +377,176 @@ impl<'a> Attribute<'a> {
     }
 }
 
+/// Table of attributes exposed by the GATT server.
+///
+/// Services can be added and removed at any time, also while the table is in use by a server. New services are
+/// assigned handles after the highest handle in use, so the handles of a removed service are only reused if no
+/// service with higher handles remains. Handles freed in the middle of the table are not reclaimed.
+///
+/// If the Generic Attribute service has been added, connected clients which have subscribed to the Service Changed
+/// characteristic are sent an indication by the GATT server whenever services are added or removed.
+///
+/// Services are assigned handles in the order they are added, unless they are given an explicit handle range
+/// using [`Service::with_handles`], which keeps their handles stable regardless of other services.
 pub struct AttributeTable<'d, M: RawMutex, const MAX: usize> {
     inner: Mutex<M, RefCell<InnerTable<'d, MAX>>>,
-    handle: u16,
 }
 
 pub struct InnerTable<'d, const MAX: usize> {
     attributes: [Option<Attribute<'d>>; MAX],
     len: usize,
+    /// Next handle to assign, which exceeds the handle range once handle 0xFFFF has been assigned.
+    handle: u32,
+    /// Set while a service builder is adding attributes.
+    building: bool,
+    /// Index at which the next attribute is inserted, keeping the attributes ordered by handle.
+    insert: usize,
+    /// Last handle which can be assigned to the service being built.
+    limit: u16,
+    /// Handle of the Service Changed characteristic, if the Generic Attribute service was added.
+    service_changed: Option<CharacteristicHandle>,
+    /// Number of times services were changed since the Generic Attribute service was added.
+    changes: u32,
+    changes_waker: WakerRegistration,
+    notifications: NotificationTable<MAX_NOTIFICATIONS>,
 }
 
 impl<'d, const MAX: usize> InnerTable<'d, MAX> {
-    fn push(&mut self, attribute: Attribute<'d>) {
-        if self.len == MAX {
-            panic!("no space for more attributes")
+    fn push(&mut self, mut attribute: Attribute<'d>) -> Result<u16, Error> {
+        if self.len == MAX || self.handle > self.limit as u32 {
+            return Err(Error::InsufficientSpace);
         }
-        self.attributes[self.len].replace(attribute);
+        let handle = self.handle as u16;
+        attribute.handle = handle;
+        let (insert, len) = (self.insert, self.len);
+        self.attributes[insert..=len].rotate_right(1);
+        self.attributes[insert].replace(attribute);
+        self.insert += 1;
         self.len += 1;
+        self.handle += 1;
+        Ok(handle)
+    }
+
+    /// Prepare for adding a service with the given handle range, checking that it does not overlap other services.
+    fn reserve(&mut self, range: RangeInclusive<u16>) -> Result<(), Error> {
+        let (start, end) = (*range.start(), *range.end());
+        if start == 0 || start > end {
+            return Err(Error::InvalidValue);
+        }
+        for att in self.attributes[..self.len].iter().flatten() {
+            let last = match att.data {
+                AttributeData::Service {
+                    reserved_end: Some(reserved_end),
+                    ..
+                } => reserved_end.max(att.last_handle_in_group),
+                _ => att.handle,
+            };
+            if att.handle <= end && last >= start {
+                return Err(Error::InvalidValue);
+            }
+        }
+        self.handle = start as u32;
+        self.limit = end;
+        self.insert = self.lower_bound(start);
+        Ok(())
+    }
+
+    fn get(&self, index: usize) -> &Attribute<'d> {
+        self.attributes[index].as_ref().unwrap()
+    }
+
+    fn get_mut(&mut self, index: usize) -> &mut Attribute<'d> {
+        self.attributes[index].as_mut().unwrap()
+    }
+
+    /// Index of the attribute with the given handle, using the attributes being ordered by handle.
+    fn find(&self, handle: u16) -> Option<usize> {
+        self.attributes[..self.len]
+            .binary_search_by_key(&handle, |att| att.as_ref().unwrap().handle)
+            .ok()
+    }
+
+    /// Index of the first attribute with a handle equal to or greater than the given handle.
+    fn lower_bound(&self, handle: u16) -> usize {
+        self.attributes[..self.len].partition_point(|att| att.as_ref().unwrap().handle < handle)
+    }
+
+    /// Index of the attribute following the service starting at `index`.
+    fn service_end(&self, index: usize) -> usize {
+        let mut end = index + 1;
+        while end < self.len && !matches!(self.get(end).data, AttributeData::Service { .. }) {
+            end += 1;
+        }
+        end
+    }
+
+    /// Recompute the last handle of each service group.
+    fn update_groups(&mut self) {
+        let mut start = 0;
+        while start < self.len {
+            let end = self.service_end(start);
+            let last_handle = self.get(end - 1).handle;
+            for item in self.attributes[start..end].iter_mut() {
+                item.as_mut().unwrap().last_handle_in_group = last_handle;
+            }
+            start = end;
+        }
+
+        // Include declarations refer to the end of the included service, which changes as services are built
+        for index in 0..self.len {
+            if let AttributeData::Include { handle, .. } = self.get(index).data {
+                let end = self.find(handle).map(|service| self.get(service).last_handle_in_group);
+                if let (Some(end), AttributeData::Include { end_group_handle, .. }) =
+                    (end, &mut self.get_mut(index).data)
+                {
+                    *end_group_handle = end;
+                }
+            }
+        }
+    }
+
+    /// Whether a service is included by another service.
+    fn is_included(&self, service: u16) -> bool {
+        self.attributes[..self.len]
+            .iter()
+            .flatten()
+            .any(|att| matches!(att.data, AttributeData::Include { handle, .. } if handle == service))
+    }
+
+    /// Compute the handle of the next service, which starts at the next 16-aligned handle after the last attribute
+    /// or reserved handle range. Once handle 0xFFFF is in use, no further services can be assigned handles.
+    fn update_next_handle(&mut self) {
+        let last = self.attributes[..self.len]
+            .iter()
+            .flatten()
+            .map(|att| match att.data {
+                AttributeData::Service {
+                    reserved_end: Some(reserved_end),
+                    ..
+                } => reserved_end,
+                _ => att.handle,
+            })
+            .max();
+        self.handle = match last {
+            None => 1,
+            Some(last) => (last as u32 + 1 + 0xf) & !0xf,
+        };
+        self.limit = u16::MAX;
+        self.insert = self.len;
+    }
+
+    /// Update the Service Changed characteristic value with the affected handle range.
+    fn set_service_changed(&mut self, start: u16, end: u16) {
+        let Some(index) = self.service_changed.and_then(|handle| self.find(handle.handle)) else {
+            return;
+        };
+        if let AttributeData::Inline { value, len, .. } = &mut self.get_mut(index).data {
+            value[..2].copy_from_slice(&start.to_le_bytes());
+            value[2..4].copy_from_slice(&end.to_le_bytes());
+            *len = 4;
+        }
+        self.changes = self.changes.wrapping_add(1);
+        self.changes_waker.wake();
     }
 }
 
@@ -270,10 +559,17 @@ impl<'d, M: RawMutex, const MAX: usize> Default for AttributeTable<'d, M, MAX> {
 impl<'d, M: RawMutex, const MAX: usize> AttributeTable<'d, M, MAX> {
     pub fn new() -> Self {
         Self {
-            handle: 1,
             inner: Mutex::new(RefCell::new(InnerTable {
                 len: 0,
                 attributes: [Attribute::EMPTY; MAX],
+                handle: 1,
+                building: false,
+                insert: 0,
+                limit: u16::MAX,
+                service_changed: None,
+                changes: 0,
+                changes_waker: WakerRegistration::new(),
+                notifications: NotificationTable::new(),
             })),
         }
     }
@@ -285,63 +581,352 @@ impl<'d, M: RawMutex, const MAX: usize> AttributeTable<'d, M, MAX> {
         })
     }
 
-    pub fn iterate<F: FnMut(AttributeIterator<'_, 'd>) -> R, R>(&self, mut f: F) -> R {
+    pub fn iterate<F: FnMut(AttributeIterator<'_, 'd>) -> R, R>(&self, f: F) -> R {
+        self.iterate_from(0, f)
+    }
+
+    /// Iterate over the attributes in handle order, starting at the first attribute with a handle equal to or
+    /// greater than `start`.
+    pub fn iterate_from<F: FnMut(AttributeIterator<'_, 'd>) -> R, R>(&self, start: u16, mut f: F) -> R {
         self.inner.lock(|inner| {
             let mut table = inner.borrow_mut();
             let len = table.len;
+            let pos = table.lower_bound(start);
             let it = AttributeIterator {
                 attributes: &mut table.attributes[..],
-                pos: 0,
+                pos,
                 len,
             };
             f(it)
         })
     }
 
-    fn push(&mut self, mut attribute: Attribute<'d>) -> u16 {
-        let handle = self.handle;
-        attribute.handle = handle;
+    /// Access the attribute with the given handle, if it exists.
+    pub(crate) fn with_attribute<F: FnOnce(&mut Attribute<'d>) -> R, R>(&self, handle: u16, f: F) -> Option<R> {
         self.inner.lock(|inner| {
-            let mut inner = inner.borrow_mut();
-            inner.push(attribute);
-        });
-        self.handle += 1;
-        handle
+            let mut table = inner.borrow_mut();
+            let index = table.find(handle)?;
+            Some(f(table.get_mut(index)))
+        })
     }
 
-    pub fn add_service(&mut self, service: Service) -> ServiceBuilder<'_, 'd, M, MAX> {
-        let len = self.inner.lock(|i| i.borrow().len);
-        self.push(Attribute {
-            uuid: PRIMARY_SERVICE_UUID16,
-            handle: 0,
-            last_handle_in_group: 0,
-            data: AttributeData::Service { uuid: service.uuid },
-        });
-        ServiceBuilder {
-            start: len,
+    fn push(&self, attribute: Attribute<'d>) -> Result<u16, Error> {
+        self.inner.lock(|inner| inner.borrow_mut().push(attribute))
+    }
+
+    /// Check that the table has room for `count` more attributes, and that the service being built has handles
+    /// left for them.
+    fn check_space(&self, count: usize) -> Result<(), Error> {
+        self.inner.lock(|inner| {
+            let inner = inner.borrow();
+            if inner.len + count > MAX || inner.handle + count as u32 > inner.limit as u32 + 1 {
+                return Err(Error::InsufficientSpace);
+            }
+            Ok(())
+        })
+    }
+
+    fn next_handle(&self) -> u16 {
+        self.inner.lock(|inner| inner.borrow().handle as u16)
+    }
+
+    /// Add a service to the table.
+    ///
+    /// The service is complete once the returned builder is dropped. Only one service can be built at a time, so
+    /// [`Error::Busy`] is returned while another service is being built. If the service has an explicit handle
+    /// range which is invalid or overlaps another service, [`Error::InvalidValue`] is returned.
+    ///
+    /// If the table is full, or no handles are left for the service, [`Error::InsufficientSpace`] is returned. This
+    /// is also returned when adding attributes to the service once the table is full, or once the service has used
+    /// up its handle range.
+    pub fn add_service(&self, service: Service) -> Result<ServiceBuilder<'_, 'd, M, MAX>, Error> {
+        let start = self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            if inner.building {
+                return Err(Error::Busy);
+            }
+            if inner.len == MAX {
+                return Err(Error::InsufficientSpace);
+            }
+            let reserved_end = service.handles.as_ref().map(|range| *range.end());
+            if let Some(range) = service.handles {
+                inner.reserve(range)?;
+            }
+            let start = inner.push(Attribute {
+                uuid: if service.secondary {
+                    SECONDARY_SERVICE_UUID16
+                } else {
+                    PRIMARY_SERVICE_UUID16
+                },
+                handle: 0,
+                last_handle_in_group: 0,
+                data: AttributeData::Service {
+                    uuid: service.uuid,
+                    reserved_end,
+                },
+            })?;
+            inner.building = true;
+            Ok(start)
+        })?;
+        Ok(ServiceBuilder {
+            start,
             table: self,
+            last: None,
+        })
+    }
+
+    /// Remove a service and all of its attributes from the table.
+    ///
+    /// Subscriptions of connections to the CCCDs of the service are removed. If the Generic Attribute service has
+    /// been added, the Service Changed characteristic is updated with the handle range of the removed service, and
+    /// subscribed clients are informed by the GATT server.
+    ///
+    /// The handles of the service are only reused by services added later if no service with higher handles remains.
+    ///
+    /// If the service is included by another service, [`Error::InvalidState`] is returned.
+    pub fn remove_service(&self, service: ServiceHandle) -> Result<(), Error> {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            if inner.building {
+                return Err(Error::Busy);
+            }
+            let start = inner
+                .find(service.handle)
+                .filter(|index| matches!(inner.get(*index).data, AttributeData::Service { .. }))
+                .ok_or(Error::NotFound)?;
+            if inner.is_included(service.handle) {
+                return Err(Error::InvalidState);
+            }
+            let end = inner.service_end(start);
+            let last_handle = inner.get(end - 1).handle;
+
+            let len = inner.len;
+            inner.attributes[start..len].rotate_left(end - start);
+            for item in inner.attributes[len - (end - start)..len].iter_mut() {
+                item.take();
+            }
+            inner.len -= end - start;
+
+            if inner
+                .service_changed
+                .map(|h| h.handle >= service.handle && h.handle <= last_handle)
+                .unwrap_or(false)
+            {
+                inner.service_changed = None;
+            }
+
+            inner.notifications.remove_range(service.handle..=last_handle);
+            inner.update_groups();
+            inner.update_next_handle();
+            inner.set_service_changed(service.handle, last_handle);
+            Ok(())
+        })
+    }
+
+    /// Add the Generic Attribute service (0x1801) with the Service Changed characteristic.
+    ///
+    /// Once added, the Service Changed characteristic is updated whenever services are added or removed.
+    pub fn add_gatt_service(&self) -> Result<CharacteristicHandle, Error> {
+        let mut svc = self.add_service(Service::new(GENERIC_ATTRIBUTE_SERVICE_UUID16))?;
+        let props = [CharacteristicProp::Indicate].into();
+        let handle = svc.add_characteristic_internal(
+            CHARACTERISTIC_SERVICE_CHANGED_UUID16,
+            props,
+            AttributeData::Inline {
+                props,
+                value: [0; INLINE_VALUE_MAX],
+                len: 4,
+            },
+        )?;
+        drop(svc);
+        self.inner
+            .lock(|inner| inner.borrow_mut().service_changed.replace(handle));
+        Ok(handle)
+    }
+
+    /// The Service Changed characteristic, if the Generic Attribute service has been added.
+    pub fn service_changed_handle(&self) -> Option<CharacteristicHandle> {
+        self.inner.lock(|inner| inner.borrow().service_changed)
+    }
+
+    /// Number of times services were changed.
+    pub(crate) fn service_changes(&self) -> u32 {
+        self.inner.lock(|inner| inner.borrow().changes)
+    }
+
+    /// Poll for services being changed after `seen` changes, returning the current number of changes.
+    pub(crate) fn poll_service_changes(&self, seen: u32, cx: &mut Context<'_>) -> Poll<u32> {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            if inner.changes != seen {
+                Poll::Ready(inner.changes)
+            } else {
+                inner.changes_waker.register(cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+
+    /// Connections which have subscribed to indications of the Service Changed characteristic.
+    pub(crate) fn service_changed_subscribers(&self) -> Vec<ConnHandle, MAX_NOTIFICATIONS> {
+        let Some(cccd_handle) = self.service_changed_handle().and_then(|h| h.cccd_handle) else {
+            return Vec::new();
+        };
+        if !self.subscribed(cccd_handle) {
+            return Vec::new();
         }
+        self.with_notifications(|n| n.subscribers(cccd_handle).collect())
+    }
+
+    /// Access the subscriptions of connections to the CCCDs of the table.
+    pub(crate) fn with_notifications<F: FnOnce(&mut NotificationTable<MAX_NOTIFICATIONS>) -> R, R>(&self, f: F) -> R {
+        self.inner.lock(|inner| f(&mut inner.borrow_mut().notifications))
+    }
+
+    /// Whether the CCCD at the given handle has notifications or indications enabled.
+    fn subscribed(&self, cccd_handle: u16) -> bool {
+        self.with_attribute(cccd_handle, |att| {
+            matches!(
+                att.data,
+                AttributeData::Cccd {
+                    notifications: true,
+                    ..
+                } | AttributeData::Cccd { indications: true, .. }
+            )
+        }) == Some(true)
+    }
+
+    /// Whether the connection has subscribed to the CCCD at the given handle.
+    pub(crate) fn is_subscribed(&self, conn: ConnHandle, cccd_handle: u16) -> bool {
+        self.subscribed(cccd_handle) && self.with_notifications(|n| n.contains(conn, cccd_handle))
+    }
+
+    /// Add the Generic Access service (0x1800) populated from the provided configuration.
+    ///
+    /// The returned handle refers to the Device Name characteristic. Use it with [`AttributeTable::get`] when
+    /// encoding the advertised `CompleteLocalName`, so that the advertised name stays in sync with the name
+    /// exposed over GATT even after a peer has written it.
+    pub fn add_gap_service(&self, config: GapConfig<'d>) -> Result<CharacteristicHandle, Error> {
+        let mut svc = self.add_service(Service::new(GENERIC_ACCESS_SERVICE_UUID16))?;
+        let name = match config.name {
+            DeviceName::ReadOnly(name) => svc.add_characteristic_ro(CHARACTERISTIC_DEVICE_NAME_UUID16, name)?,
+            DeviceName::Writable { storage, len } => {
+                let max = storage.len().min(DEVICE_NAME_MAX);
+                svc.add_characteristic_variable(
+                    CHARACTERISTIC_DEVICE_NAME_UUID16,
+                    &[CharacteristicProp::Read, CharacteristicProp::Write],
+                    &mut storage[..max],
+                    len,
+                )?
+            }
+        };
+
+        svc.add_characteristic_inline(CHARACTERISTIC_APPEARANCE_UUID16, &config.appearance.to_le_bytes())?;
+
+        if let Some(params) = config.preferred_connection_params {
+            let mut value = [0; 8];
+            let mut w = WriteCursor::new(&mut value[..]);
+            // Connection intervals are in units of 1.25 ms, supervision timeout in units of 10 ms
+            let _ = w.write((params.min_connection_interval.as_micros() / 1250) as u16);
+            let _ = w.write((params.max_connection_interval.as_micros() / 1250) as u16);
+            let _ = w.write(params.max_latency);
+            let _ = w.write((params.supervision_timeout.as_millis() / 10) as u16);
+            svc.add_characteristic_inline(CHARACTERISTIC_PPCP_UUID16, &value)?;
+        }
+
+        if let Some(supported) = config.central_address_resolution {
+            svc.add_characteristic_inline(CHARACTERISTIC_CENTRAL_ADDRESS_RESOLUTION_UUID16, &[supported as u8])?;
+        }
+        Ok(name)
+    }
+
+    /// Find the characteristic whose value is stored at the given attribute handle.
+    pub(crate) fn find_characteristic(&self, handle: u16) -> Option<CharacteristicHandle> {
+        self.iterate_from(handle, |mut it| {
+            let att = it.next().filter(|att| att.handle == handle)?;
+            if !matches!(att.data, AttributeData::Data { .. }) {
+                return None;
+            }
+            let cccd_handle = match it.next() {
+                Some(next) if next.uuid == CHARACTERISTIC_CCCD_UUID16 => Some(next.handle),
+                _ => None,
+            };
+            Some(CharacteristicHandle { handle, cccd_handle })
+        })
     }
 
     /// Set the value of a characteristic
     ///
-    /// The provided data must exactly match the size of the storage for the characteristic,
-    /// otherwise this function will panic.
+    /// For fixed length characteristics, the provided data must exactly match the size of the storage for the
+    /// characteristic, otherwise [`Error::InvalidValue`] is returned. Variable length characteristics accept any
+    /// value up to the size of the storage, and return an error if the value does not fit.
     ///
     /// If the characteristic for the handle cannot be found, an error is returned.
     pub fn set(&self, handle: CharacteristicHandle, input: &[u8]) -> Result<(), Error> {
-        self.iterate(|mut it| {
-            while let Some(att) = it.next() {
-                if att.handle == handle.handle {
-                    if let AttributeData::Data { props, value } = &mut att.data {
-                        assert_eq!(value.len(), input.len());
-                        value.copy_from_slice(input);
-                        return Ok(());
-                    }
+        self.set_at(handle.handle, input)
+    }
+
+    /// Set the value of a descriptor.
+    ///
+    /// The same length rules as for [`AttributeTable::set`] apply. If the descriptor cannot be found or its value
+    /// is not stored in the table, an error is returned.
+    pub fn set_descriptor(&self, handle: DescriptorHandle, input: &[u8]) -> Result<(), Error> {
+        self.set_at(handle.handle, input)
+    }
+
+    fn set_at(&self, handle: u16, input: &[u8]) -> Result<(), Error> {
+        self.with_attribute(handle, |att| match &mut att.data {
+            AttributeData::Data {
+                value,
+                variable_len: false,
+                ..
+            } => {
+                if value.len() != input.len() {
+                    return Err(Error::InvalidValue);
                 }
+                value.copy_from_slice(input);
+                Ok(())
             }
-            Err(Error::NotFound)
+            AttributeData::Data {
+                value,
+                len,
+                variable_len: true,
+                ..
+            } => {
+                if input.len() > value.len() {
+                    return Err(Error::InsufficientSpace);
+                }
+                value[..input.len()].copy_from_slice(input);
+                *len = input.len();
+                Ok(())
+            }
+            _ => Err(Error::NotFound),
+        })
+        .unwrap_or(Err(Error::NotFound))
+    }
+
+    /// Encode a typed value into the storage of a characteristic.
+    fn set_value<T: GattValue>(&self, handle: CharacteristicHandle, input: &T) -> Result<(), Error> {
+        self.with_attribute(handle.handle, |att| {
+            let AttributeData::Data {
+                value,
+                len,
+                variable_len,
+                ..
+            } = &mut att.data
+            else {
+                return Err(Error::NotFound);
+            };
+            if value.len() < T::MAX_SIZE {
+                return Err(Error::InsufficientSpace);
+            }
+            let written = input.to_gatt(value);
+            if *variable_len {
+                *len = written;
+            }
+            Ok(())
         })
+        .unwrap_or(Err(Error::NotFound))
     }
 
     /// Read the value of the characteristic and pass the value to the provided closure.
@@ -349,36 +934,92 @@ impl<'d, M: RawMutex, const MAX: usize> AttributeTable<'d, M, MAX> {
     /// The return value of the closure is returned in this function and is assumed to be infallible.
     ///
     /// If the characteristic for the handle cannot be found, an error is returned.
-    pub fn get<F: FnMut(&[u8]) -> T, T>(&self, handle: CharacteristicHandle, mut f: F) -> Result<T, Error> {
-        self.iterate(|mut it| {
-            while let Some(att) = it.next() {
-                if att.handle == handle.handle {
-                    if let AttributeData::Data { props, value } = &mut att.data {
-                        let v = f(value);
-                        return Ok(v);
-                    }
-                }
-            }
-            Err(Error::NotFound)
+    pub fn get<F: FnMut(&[u8]) -> T, T>(&self, handle: CharacteristicHandle, f: F) -> Result<T, Error> {
+        self.get_at(handle.handle, f)
+    }
+
+    /// Read the value of a descriptor and pass the value to the provided closure.
+    ///
+    /// If the descriptor for the handle cannot be found, an error is returned.
+    pub fn get_descriptor<F: FnMut(&[u8]) -> T, T>(&self, handle: DescriptorHandle, f: F) -> Result<T, Error> {
+        self.get_at(handle.handle, f)
+    }
+
+    fn get_at<F: FnMut(&[u8]) -> T, T>(&self, handle: u16, mut f: F) -> Result<T, Error> {
+        self.with_attribute(handle, |att| match &att.data {
+            AttributeData::Data { value, len, .. } => Ok(f(&value[..*len])),
+            AttributeData::Inline { value, len, .. } => Ok(f(&value[..*len])),
+            AttributeData::ReadOnlyData { value, .. } => Ok(f(value)),
+            _ => Err(Error::NotFound),
         })
+        .unwrap_or(Err(Error::NotFound))
     }
 }
 
 pub struct ServiceBuilder<'r, 'd, M: RawMutex, const MAX: usize> {
-    start: usize,
-    table: &'r mut AttributeTable<'d, M, MAX>,
+    start: u16,
+    table: &'r AttributeTable<'d, M, MAX>,
+    /// The characteristic added last, which descriptors can be added to.
+    last: Option<CharacteristicHandle>,
 }
 
 impl<'r, 'd, M: RawMutex, const MAX: usize> ServiceBuilder<'r, 'd, M, MAX> {
+    /// The handle of the service being built.
+    pub fn handle(&self) -> ServiceHandle {
+        ServiceHandle { handle: self.start }
+    }
+
+    /// Finish the service, returning its handle.
+    pub fn build(self) -> ServiceHandle {
+        self.handle()
+    }
+
+    /// Include another service in this service.
+    ///
+    /// Included services must be added before any characteristics of this service, otherwise
+    /// [`Error::InvalidState`] is returned. If the included service cannot be found, an error is returned.
+    ///
+    /// A service can't be removed while it is included by another service.
+    pub fn include(&mut self, service: ServiceHandle) -> Result<(), Error> {
+        if self.last.is_some() {
+            return Err(Error::InvalidState);
+        }
+        if service.handle == self.start {
+            return Err(Error::InvalidValue);
+        }
+        let (end_group_handle, uuid) = self
+            .table
+            .with_attribute(service.handle, |att| match &att.data {
+                AttributeData::Service { uuid, .. } => Some((att.last_handle_in_group, *uuid)),
+                _ => None,
+            })
+            .flatten()
+            .ok_or(Error::NotFound)?;
+        self.table.push(Attribute {
+            uuid: INCLUDE_UUID16,
+            handle: 0,
+            last_handle_in_group: 0,
+            data: AttributeData::Include {
+                handle: service.handle,
+                end_group_handle,
+                uuid,
+            },
+        })?;
+        Ok(())
+    }
+
     fn add_characteristic_internal(
         &mut self,
         uuid: Uuid,
         props: CharacteristicProps,
         data: AttributeData<'d>,
-    ) -> CharacteristicHandle {
+    ) -> Result<CharacteristicHandle, Error> {
+        // Check for room up front, so that a full table or handle range doesn't leave a partial characteristic behind
+        let has_cccd = props.any(&[CharacteristicProp::Notify, CharacteristicProp::Indicate]);
+        self.table.check_space(if has_cccd { 3 } else { 2 })?;
+
         // First the characteristic declaration
-        let next = self.table.handle + 1;
-        let cccd = self.table.handle + 2;
+        let next = self.table.next_handle() + 1;
         self.table.push(Attribute {
             uuid: CHARACTERISTIC_UUID16,
             handle: 0,
@@ -388,7 +1029,7 @@ impl<'r, 'd, M: RawMutex, const MAX: usize> ServiceBuilder<'r, 'd, M, MAX> {
                 handle: next,
                 uuid,
             },
-        });
+        })?;
 
         // Then the value declaration
         self.table.push(Attribute {
@@ -396,11 +1037,11 @@ impl<'r, 'd, M: RawMutex, const MAX: usize> ServiceBuilder<'r, 'd, M, MAX> {
             handle: 0,
             last_handle_in_group: 0,
             data,
-        });
+        })?;
 
         // Add optional CCCD handle
-        let cccd_handle = if props.any(&[CharacteristicProp::Notify, CharacteristicProp::Indicate]) {
-            self.table.push(Attribute {
+        let cccd_handle = if has_cccd {
+            let cccd = self.table.push(Attribute {
                 uuid: CHARACTERISTIC_CCCD_UUID16,
                 handle: 0,
                 last_handle_in_group: 0,
@@ -408,55 +1049,361 @@ impl<'r, 'd, M: RawMutex, const MAX: usize> ServiceBuilder<'r, 'd, M, MAX> {
                     notifications: false,
                     indications: false,
                 },
-            });
+            })?;
             Some(cccd)
         } else {
             None
         };
 
-        CharacteristicHandle {
+        let handle = CharacteristicHandle {
             handle: next,
             cccd_handle,
+        };
+        self.last.replace(handle);
+        Ok(handle)
+    }
+
+    /// Add descriptors to a characteristic.
+    ///
+    /// Descriptors are placed right after the characteristic, so they can only be added to the characteristic
+    /// which was added last. Otherwise [`Error::InvalidValue`] is returned.
+    pub fn descriptors(
+        &mut self,
+        characteristic: CharacteristicHandle,
+    ) -> Result<CharacteristicBuilder<'_, 'd, M, MAX>, Error> {
+        if self.last != Some(characteristic) {
+            return Err(Error::InvalidValue);
         }
+        Ok(CharacteristicBuilder {
+            handle: characteristic,
+            table: self.table,
+        })
     }
 
+    /// Add a characteristic with a fixed length value.
+    ///
+    /// Use [`ServiceBuilder::descriptors`] to add descriptors to the characteristic.
     pub fn add_characteristic<U: Into<Uuid>>(
         &mut self,
         uuid: U,
         props: &[CharacteristicProp],
         storage: &'d mut [u8],
-    ) -> CharacteristicHandle {
+    ) -> Result<CharacteristicHandle, Error> {
         let props = props.into();
-        self.add_characteristic_internal(uuid.into(), props, AttributeData::Data { props, value: storage })
+        let len = storage.len();
+        self.add_characteristic_internal(
+            uuid.into(),
+            props,
+            AttributeData::Data {
+                props,
+                value: storage,
+                len,
+                variable_len: false,
+            },
+        )
     }
 
-    pub fn add_characteristic_ro<U: Into<Uuid>>(&mut self, uuid: U, value: &'d [u8]) -> CharacteristicHandle {
+    /// Add a characteristic with a variable length value.
+    ///
+    /// The length of the storage is the maximum length of the value, of which the first `len` bytes
+    /// hold the initial value. If `len` exceeds the length of the storage, [`Error::InvalidValue`] is returned.
+    pub fn add_characteristic_variable<U: Into<Uuid>>(
+        &mut self,
+        uuid: U,
+        props: &[CharacteristicProp],
+        storage: &'d mut [u8],
+        len: usize,
+    ) -> Result<CharacteristicHandle, Error> {
+        if len > storage.len() {
+            return Err(Error::InvalidValue);
+        }
+        let props = props.into();
+        self.add_characteristic_internal(
+            uuid.into(),
+            props,
+            AttributeData::Data {
+                props,
+                value: storage,
+                len,
+                variable_len: true,
+            },
+        )
+    }
+
+    /// Add a characteristic holding a value of type `T`.
+    ///
+    /// The storage must be at least `T::MAX_SIZE` bytes long, otherwise [`Error::InvalidValue`] is returned. The
+    /// storage is initialized with the provided value. Types with a variable size are stored as variable length
+    /// values, whose current length is the length of the encoded value.
+    pub fn add_characteristic_typed<T: GattValue, U: Into<Uuid>>(
+        &mut self,
+        uuid: U,
+        props: &[CharacteristicProp],
+        value: &T,
+        storage: &'d mut [u8],
+    ) -> Result<Characteristic<T>, Error> {
+        if storage.len() < T::MAX_SIZE {
+            return Err(Error::InvalidValue);
+        }
+        let storage = &mut storage[..T::MAX_SIZE];
+        let len = value.to_gatt(storage);
+        let handle = if T::MIN_SIZE == T::MAX_SIZE {
+            self.add_characteristic(uuid, props, storage)?
+        } else {
+            self.add_characteristic_variable(uuid, props, storage, len)?
+        };
+        Ok(Characteristic {
+            handle,
+            _value: PhantomData,
+        })
+    }
+
+    pub fn add_characteristic_ro<U: Into<Uuid>>(
+        &mut self,
+        uuid: U,
+        value: &'d [u8],
+    ) -> Result<CharacteristicHandle, Error> {
         let props = [CharacteristicProp::Read].into();
         self.add_characteristic_internal(uuid.into(), props, AttributeData::ReadOnlyData { props, value })
     }
+
+    fn add_characteristic_inline(&mut self, uuid: Uuid, data: &[u8]) -> Result<CharacteristicHandle, Error> {
+        let props = [CharacteristicProp::Read].into();
+        let mut value = [0; INLINE_VALUE_MAX];
+        value[..data.len()].copy_from_slice(data);
+        self.add_characteristic_internal(
+            uuid,
+            props,
+            AttributeData::Inline {
+                props,
+                value,
+                len: data.len(),
+            },
+        )
+    }
 }
 
 impl<'r, 'd, M: RawMutex, const MAX: usize> Drop for ServiceBuilder<'r, 'd, M, MAX> {
     fn drop(&mut self) {
-        let last_handle = self.table.handle + 1;
-        self.table.with_inner(|inner| {
-            for item in inner.attributes[self.start..inner.len].iter_mut() {
-                item.as_mut().unwrap().last_handle_in_group = last_handle;
+        self.table.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            let last_handle = (inner.handle - 1) as u16;
+            inner.building = false;
+            inner.update_groups();
+
+            // Jump to next 16-aligned
+            inner.update_next_handle();
+            inner.set_service_changed(self.start, last_handle);
+        });
+    }
+}
+
+/// Builder for the descriptors of a characteristic.
+///
+/// Descriptors are placed after the characteristic value and its CCCD, in the order they are added.
+pub struct CharacteristicBuilder<'r, 'd, M: RawMutex, const MAX: usize> {
+    handle: CharacteristicHandle,
+    table: &'r AttributeTable<'d, M, MAX>,
+}
+
+impl<'r, 'd, M: RawMutex, const MAX: usize> CharacteristicBuilder<'r, 'd, M, MAX> {
+    fn push_descriptor(&mut self, uuid: Uuid, data: AttributeData<'d>) -> Result<DescriptorHandle, Error> {
+        let handle = self.table.push(Attribute {
+            uuid,
+            handle: 0,
+            last_handle_in_group: 0,
+            data,
+        })?;
+        Ok(DescriptorHandle { handle })
+    }
+
+    /// Add a descriptor with a custom UUID, stored in the provided storage.
+    pub fn add_descriptor<U: Into<Uuid>>(
+        &mut self,
+        uuid: U,
+        props: &[CharacteristicProp],
+        storage: &'d mut [u8],
+    ) -> Result<DescriptorHandle, Error> {
+        let props = props.into();
+        let len = storage.len();
+        self.push_descriptor(
+            uuid.into(),
+            AttributeData::Data {
+                props,
+                value: storage,
+                len,
+                variable_len: false,
+            },
+        )
+    }
+
+    /// Add a read-only descriptor with a custom UUID.
+    pub fn add_descriptor_ro<U: Into<Uuid>>(&mut self, uuid: U, value: &'d [u8]) -> Result<DescriptorHandle, Error> {
+        let props = [CharacteristicProp::Read].into();
+        self.push_descriptor(uuid.into(), AttributeData::ReadOnlyData { props, value })
+    }
+
+    /// Add a Characteristic User Description descriptor (0x2901).
+    pub fn add_user_description(&mut self, description: &'d str) -> Result<DescriptorHandle, Error> {
+        self.add_descriptor_ro(CHARACTERISTIC_USER_DESCRIPTION_UUID16, description.as_bytes())
+    }
+
+    /// Add a Characteristic Presentation Format descriptor (0x2904).
+    pub fn add_presentation_format(&mut self, format: PresentationFormat) -> Result<DescriptorHandle, Error> {
+        let mut value = [0; INLINE_VALUE_MAX];
+        let mut w = WriteCursor::new(&mut value[..]);
+        let _ = w.write(format.format);
+        let _ = w.write(format.exponent as u8);
+        let _ = w.write(format.unit);
+        let _ = w.write(format.namespace);
+        let _ = w.write(format.description);
+        let props = [CharacteristicProp::Read].into();
+        self.push_descriptor(
+            CHARACTERISTIC_PRESENTATION_FORMAT_UUID16,
+            AttributeData::Inline { props, value, len: 7 },
+        )
+    }
+
+    /// Add a Characteristic Extended Properties descriptor (0x2900).
+    ///
+    /// This also sets the extended properties bit in the characteristic declaration.
+    pub fn add_extended_properties(
+        &mut self,
+        reliable_write: bool,
+        writable_auxiliaries: bool,
+    ) -> Result<DescriptorHandle, Error> {
+        let declaration = self.handle.handle - 1;
+        self.table.with_attribute(declaration, |att| {
+            if let AttributeData::Declaration { props, .. } = &mut att.data {
+                props.0 |= CharacteristicProp::Extended as u8;
             }
         });
 
-        // Jump to next 16-aligned
-        self.table.handle = self.table.handle + (0x10 - (self.table.handle % 0x10));
+        let flags = (reliable_write as u16) | (writable_auxiliaries as u16) << 1;
+        let mut value = [0; INLINE_VALUE_MAX];
+        value[..2].copy_from_slice(&flags.to_le_bytes());
+        let props = [CharacteristicProp::Read].into();
+        self.push_descriptor(
+            CHARACTERISTIC_EXTENDED_PROPERTIES_UUID16,
+            AttributeData::Inline { props, value, len: 2 },
+        )
+    }
+
+    /// Add a Server Characteristic Configuration descriptor (0x2903), allowing clients to enable broadcasts
+    /// of the characteristic value.
+    pub fn add_server_config(&mut self) -> Result<DescriptorHandle, Error> {
+        self.push_descriptor(CHARACTERISTIC_SCCD_UUID16, AttributeData::Sccd { broadcasts: false })
     }
+
+    /// Finish the characteristic, returning its handle.
+    pub fn build(self) -> CharacteristicHandle {
+        self.handle
+    }
+}
+
+/// Value of a Characteristic Presentation Format descriptor.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PresentationFormat {
+    /// Format of the value, as defined in the Bluetooth SIG assigned numbers.
+    pub format: u8,
+    /// Base 10 exponent applied to the value.
+    pub exponent: i8,
+    /// Unit of the value, as defined in the Bluetooth SIG assigned numbers.
+    pub unit: u16,
+    /// Namespace of the description.
+    pub namespace: u8,
+    /// Description of the value within the namespace.
+    pub description: u16,
+}
+
+/// Handle to a characteristic descriptor.
+///
+/// Descriptors stored in the table can be accessed with [`AttributeTable::get_descriptor`] and
+/// [`AttributeTable::set_descriptor`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DescriptorHandle {
+    pub(crate) handle: u16,
+}
+
+/// Handle to a service in the attribute table.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ServiceHandle {
+    pub(crate) handle: u16,
 }
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct CharacteristicHandle {
     pub(crate) cccd_handle: Option<u16>,
     pub(crate) handle: u16,
 }
 
+/// Handle to a characteristic holding a value of type `T`.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug)]
+pub struct Characteristic<T: GattValue> {
+    handle: CharacteristicHandle,
+    _value: PhantomData<T>,
+}
+
+impl<T: GattValue> Clone for Characteristic<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: GattValue> Copy for Characteristic<T> {}
+
+impl<T: GattValue> Characteristic<T> {
+    /// The untyped handle of this characteristic.
+    pub fn handle(&self) -> CharacteristicHandle {
+        self.handle
+    }
+
+    /// Set the value of the characteristic in the table.
+    pub fn set<M: RawMutex, const MAX: usize>(
+        &self,
+        table: &AttributeTable<'_, M, MAX>,
+        value: &T,
+    ) -> Result<(), Error> {
+        table.set_value(self.handle, value)
+    }
+
+    /// Read the value of the characteristic from the table.
+    pub fn get<M: RawMutex, const MAX: usize>(&self, table: &AttributeTable<'_, M, MAX>) -> Result<T, Error> {
+        table
+            .get(self.handle, |value| T::from_gatt(value))?
+            .map_err(|_| Error::InvalidValue)
+    }
+
+    /// Set the value of the characteristic, and notify the connection if it has subscribed to it.
+    pub async fn notify<M: RawMutex, C: Controller, const MAX: usize>(
+        &self,
+        server: &GattServer<'_, '_, '_, M, C, MAX>,
+        connection: &Connection<'_>,
+        value: &T,
+    ) -> Result<(), AdapterError<C::Error>> {
+        self.set(server.server.table, value)?;
+        server.send_notification(self.handle, connection).await
+    }
+
+    /// Set the value of the characteristic, and indicate it to the connection if it has subscribed to it.
+    ///
+    /// Returns [`Error::Busy`] while a previous indication to the connection awaits confirmation.
+    pub async fn indicate<M: RawMutex, C: Controller, const MAX: usize>(
+        &self,
+        server: &GattServer<'_, '_, '_, M, C, MAX>,
+        connection: &Connection<'_>,
+        value: &T,
+    ) -> Result<(), AdapterError<C::Error>> {
+        self.set(server.server.table, value)?;
+        server.send_indication(self.handle, connection).await
+    }
+}
+
 pub struct AttributeIterator<'a, 'd> {
     attributes: &'a mut [Option<Attribute<'d>>],
     pos: usize,
@@ -477,11 +1424,74 @@ impl<'a, 'd> AttributeIterator<'a, 'd> {
 
 pub struct Service {
     pub uuid: Uuid,
+    /// Whether this is a secondary service, which is only meant to be included by other services.
+    pub secondary: bool,
+    /// Explicit handle range of the service, if any.
+    pub handles: Option<RangeInclusive<u16>>,
+}
+
+/// Configuration for the Generic Access service added by [`AttributeTable::add_gap_service`].
+pub struct GapConfig<'d> {
+    /// The device name exposed in the Device Name characteristic.
+    pub name: DeviceName<'d>,
+    /// The appearance of the device, as defined in the Bluetooth SIG assigned numbers.
+    pub appearance: u16,
+    /// Peripheral Preferred Connection Parameters to expose, if any.
+    pub preferred_connection_params: Option<ConnectParams>,
+    /// Expose the Central Address Resolution characteristic with the given support flag, if any.
+    pub central_address_resolution: Option<bool>,
+}
+
+impl<'d> GapConfig<'d> {
+    /// Create a configuration with a read-only device name and appearance.
+    pub fn new(name: &'d [u8], appearance: u16) -> Self {
+        Self {
+            name: DeviceName::ReadOnly(name),
+            appearance,
+            preferred_connection_params: None,
+            central_address_resolution: None,
+        }
+    }
+}
+
+/// Device name storage for the Generic Access service.
+pub enum DeviceName<'d> {
+    /// A fixed device name.
+    ReadOnly(&'d [u8]),
+    /// A device name which can be written by peers.
+    ///
+    /// The length of `storage` is the maximum name length, of which the first `len` bytes hold the initial
+    /// name. Names are at most [`DEVICE_NAME_MAX`] bytes long. Writes by peers are reported by the GATT server
+    /// like writes of any other characteristic.
+    Writable { storage: &'d mut [u8], len: usize },
 }
 
 impl Service {
     pub fn new<U: Into<Uuid>>(uuid: U) -> Self {
-        Self { uuid: uuid.into() }
+        Self {
+            uuid: uuid.into(),
+            secondary: false,
+            handles: None,
+        }
+    }
+
+    /// Create a secondary service.
+    pub fn secondary<U: Into<Uuid>>(uuid: U) -> Self {
+        Self {
+            uuid: uuid.into(),
+            secondary: true,
+            handles: None,
+        }
+    }
+
+    /// Assign an explicit handle range to the service.
+    ///
+    /// The service declaration is placed at the start of the range, and its attributes must fit within the range.
+    /// The whole range is reserved for the service, so attributes can be added in later firmware versions without
+    /// changing the handles of other services.
+    pub fn with_handles(mut self, handles: RangeInclusive<u16>) -> Self {
+        self.handles = Some(handles);
+        self
     }
 }
 
@@ -524,3 +1534,436 @@ pub struct AttributeValue<'d, M: RawMutex> {
 }
 
 impl<'d, M: RawMutex> AttributeValue<'d, M> {}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+
+    fn last_handle_in_group<M: RawMutex, const MAX: usize>(table: &AttributeTable<'_, M, MAX>, handle: u16) -> u16 {
+        table.iterate(|mut it| {
+            while let Some(att) = it.next() {
+                if att.handle == handle {
+                    return att.last_handle_in_group;
+                }
+            }
+            panic!("attribute not found")
+        })
+    }
+
+    #[test]
+    fn test_remove_service() {
+        let mut a = [0; 1];
+        let mut b = [0; 1];
+        let mut c = [0; 1];
+        let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+        let service_changed = table.add_gatt_service().unwrap();
+
+        let mut svc = table.add_service(Service::new(0x180f)).unwrap();
+        svc.add_characteristic(0x2a19, &[CharacteristicProp::Read], &mut a)
+            .unwrap();
+        let first = svc.build();
+
+        let mut svc = table.add_service(Service::new(0x180a)).unwrap();
+        svc.add_characteristic(0x2a29, &[CharacteristicProp::Read], &mut b)
+            .unwrap();
+        let second = svc.build();
+
+        assert_eq!(first.handle, 0x10);
+        assert_eq!(second.handle, 0x20);
+        assert_eq!(last_handle_in_group(&table, 0x10), 0x12);
+        assert_eq!(last_handle_in_group(&table, 0x20), 0x22);
+
+        table.remove_service(second).unwrap();
+        assert!(matches!(table.remove_service(second), Err(Error::NotFound)));
+        assert!(table.get(service_changed, |v| v == [0x20, 0x00, 0x22, 0x00]).unwrap());
+
+        // Handles of the removed service are reused
+        let mut svc = table.add_service(Service::new(0x180d)).unwrap();
+        let handle = svc
+            .add_characteristic(0x2a37, &[CharacteristicProp::Read], &mut c)
+            .unwrap();
+        let third = svc.build();
+        assert_eq!(third.handle, 0x20);
+        assert_eq!(handle.handle, 0x22);
+
+        table.remove_service(first).unwrap();
+        assert!(table.get(service_changed, |v| v == [0x10, 0x00, 0x12, 0x00]).unwrap());
+        assert_eq!(last_handle_in_group(&table, 0x20), 0x22);
+    }
+
+    #[test]
+    fn test_remove_service_subscriptions() {
+        let mut level = [0; 1];
+        let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+        table.add_gatt_service().unwrap();
+        let changes = table.service_changes();
+
+        let mut svc = table.add_service(Service::new(0x180f)).unwrap();
+        let level = svc
+            .add_characteristic(
+                0x2a19,
+                &[CharacteristicProp::Read, CharacteristicProp::Notify],
+                &mut level,
+            )
+            .unwrap();
+        let battery = svc.build();
+        assert_eq!(table.service_changes(), changes.wrapping_add(1));
+
+        let conn = ConnHandle::new(1);
+        let cccd = level.cccd_handle.unwrap();
+        table.with_notifications(|n| n.set(conn, cccd, true));
+        assert!(table.with_notifications(|n| n.contains(conn, cccd)));
+
+        // Subscriptions must not carry over to a service reusing the handles
+        table.remove_service(battery).unwrap();
+        assert!(!table.with_notifications(|n| n.contains(conn, cccd)));
+        assert_eq!(table.service_changes(), changes.wrapping_add(2));
+    }
+
+    #[test]
+    fn test_typed_values() {
+        let mut name = [0; 8];
+        let mut level = [0; 1];
+        let mut small = [0; 1];
+        let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+
+        let mut svc = table.add_service(Service::new(0x180f)).unwrap();
+        let initial: heapless::String<8> = heapless::String::try_from("abc").unwrap();
+        let name = svc
+            .add_characteristic_typed(0x2a00, &[CharacteristicProp::Read], &initial, &mut name)
+            .unwrap();
+        let level = svc
+            .add_characteristic_typed(0x2a19, &[CharacteristicProp::Read], &50u8, &mut level)
+            .unwrap();
+        assert!(matches!(
+            svc.add_characteristic_typed(0x2a1a, &[CharacteristicProp::Read], &0u16, &mut small),
+            Err(Error::InvalidValue)
+        ));
+        drop(svc);
+
+        // Variable length values read back with their current length, not padded to the storage size
+        assert_eq!(name.get(&table).unwrap(), "abc");
+        assert!(table.get(name.handle(), |v| v.len() == 3).unwrap());
+        name.set(&table, &heapless::String::try_from("de").unwrap()).unwrap();
+        assert_eq!(name.get(&table).unwrap(), "de");
+
+        assert_eq!(level.get(&table).unwrap(), 50);
+        assert!(matches!(table.set(level.handle(), &[1, 2]), Err(Error::InvalidValue)));
+        table.set(level.handle(), &[75]).unwrap();
+        assert_eq!(level.get(&table).unwrap(), 75);
+    }
+
+    #[test]
+    fn test_write_lengths() {
+        let mut fixed = [0; 4];
+        let mut variable = [0; 4];
+        let mut short = [0; 2];
+        let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+
+        let mut svc = table.add_service(Service::new(0x180f)).unwrap();
+        let props = &[CharacteristicProp::Read, CharacteristicProp::Write];
+        let fixed = svc.add_characteristic(0x2a19, props, &mut fixed).unwrap();
+        let variable = svc
+            .add_characteristic_variable(0x2a1a, props, &mut variable, 0)
+            .unwrap();
+        // The initial value must fit in the storage
+        assert!(matches!(
+            svc.add_characteristic_variable(0x2a1b, props, &mut short, 3),
+            Err(Error::InvalidValue)
+        ));
+        drop(svc);
+
+        let write = |handle: CharacteristicHandle, offset, data: &[u8]| {
+            table
+                .with_attribute(handle.handle, |att| att.data.write(offset, data))
+                .unwrap()
+        };
+
+        // Fixed length values must be written as a whole
+        assert!(matches!(
+            write(fixed, 0, &[1, 2]),
+            Err(AttErrorCode::InvalidAttributeValueLength)
+        ));
+        assert!(matches!(
+            write(fixed, 2, &[1, 2]),
+            Err(AttErrorCode::InvalidAttributeValueLength)
+        ));
+        assert!(write(fixed, 0, &[1, 2, 3, 4]).is_ok());
+        assert!(table.get(fixed, |v| v == [1, 2, 3, 4]).unwrap());
+
+        assert!(write(variable, 0, &[1, 2]).is_ok());
+        assert!(write(variable, 2, &[3]).is_ok());
+        assert!(matches!(
+            write(variable, 0, &[1, 2, 3, 4, 5]),
+            Err(AttErrorCode::InvalidAttributeValueLength)
+        ));
+        assert!(table.get(variable, |v| v == [1, 2, 3]).unwrap());
+    }
+
+    #[test]
+    fn test_descriptors() {
+        let mut level = [0; 1];
+        let mut valid_range = [0; 2];
+        let mut other = [0; 1];
+        let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+
+        let mut svc = table.add_service(Service::new(0x180f)).unwrap();
+        let first = svc
+            .add_characteristic(0x2a19, &[CharacteristicProp::Read], &mut level)
+            .unwrap();
+        let mut descriptors = svc.descriptors(first).unwrap();
+        let description = descriptors.add_user_description("Level").unwrap();
+        let range = descriptors
+            .add_descriptor(0x2906, &[CharacteristicProp::Read], &mut valid_range)
+            .unwrap();
+        assert_eq!(descriptors.build(), first);
+
+        // Descriptors can only be added to the characteristic added last
+        svc.add_characteristic(0x2a1a, &[CharacteristicProp::Read], &mut other)
+            .unwrap();
+        assert!(matches!(svc.descriptors(first), Err(Error::InvalidValue)));
+        drop(svc);
+
+        assert_eq!(description.handle, 0x13);
+        assert!(table.get_descriptor(description, |v| v == b"Level").unwrap());
+        table.set_descriptor(range, &[1, 100]).unwrap();
+        assert!(table.get_descriptor(range, |v| v == [1, 100]).unwrap());
+        assert!(matches!(
+            table.set_descriptor(description, b"Other"),
+            Err(Error::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_include_service() {
+        let mut level = [0; 1];
+        let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+
+        let mut svc = table.add_service(Service::secondary(0x180f)).unwrap();
+        svc.add_characteristic(0x2a19, &[CharacteristicProp::Read], &mut level)
+            .unwrap();
+        let battery = svc.build();
+
+        let mut report = [0; 1];
+        let mut svc = table.add_service(Service::new(0x1812)).unwrap();
+        svc.include(battery).unwrap();
+        svc.add_characteristic(0x2a4d, &[CharacteristicProp::Read], &mut report)
+            .unwrap();
+        // Includes must come before the characteristics of the service
+        assert!(matches!(svc.include(battery), Err(Error::InvalidState)));
+        let hid = svc.build();
+
+        // Included services can't be removed while the including service exists
+        assert!(matches!(table.remove_service(battery), Err(Error::InvalidState)));
+
+        let mut value = [0; 8];
+        let (uuid, len) = table.iterate(|mut it| {
+            while let Some(att) = it.next() {
+                if att.handle == 0x11 {
+                    return (att.uuid, att.data.read(0, &mut value).unwrap());
+                }
+            }
+            panic!("include not found")
+        });
+        assert_eq!(uuid, INCLUDE_UUID16);
+        assert_eq!(value[..len], [0x01, 0x00, 0x03, 0x00, 0x0f, 0x18]);
+
+        table.remove_service(hid).unwrap();
+        table.remove_service(battery).unwrap();
+    }
+
+    #[test]
+    fn test_fixed_handles() {
+        let mut a = [0; 1];
+        let mut b = [0; 1];
+        let mut c = [0; 1];
+        let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+
+        let mut svc = table
+            .add_service(Service::new(0x180f).with_handles(0x40..=0x4f))
+            .unwrap();
+        let level = svc
+            .add_characteristic(0x2a19, &[CharacteristicProp::Read], &mut a)
+            .unwrap();
+        drop(svc);
+        assert_eq!(level.handle, 0x42);
+
+        // Services without a handle range are placed after reserved ranges
+        let auto = table.add_service(Service::new(0x180a)).unwrap().build();
+        assert_eq!(auto.handle, 0x50);
+
+        let mut svc = table
+            .add_service(Service::new(0x1812).with_handles(0x20..=0x2f))
+            .unwrap();
+        let report = svc
+            .add_characteristic(0x2a4d, &[CharacteristicProp::Read], &mut b)
+            .unwrap();
+        drop(svc);
+        assert_eq!(report.handle, 0x22);
+        assert_eq!(last_handle_in_group(&table, 0x20), 0x22);
+        assert_eq!(last_handle_in_group(&table, 0x40), 0x42);
+
+        let mut svc = table.add_service(Service::new(0x180d)).unwrap();
+        let rate = svc
+            .add_characteristic(0x2a37, &[CharacteristicProp::Read], &mut c)
+            .unwrap();
+        drop(svc);
+        assert_eq!(rate.handle, 0x62);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut a = [0; 1];
+        let mut b = [0; 1];
+        let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+
+        // Services with explicit handles are inserted before existing services, keeping the table ordered
+        let mut svc = table
+            .add_service(Service::new(0x180f).with_handles(0x40..=0x4f))
+            .unwrap();
+        svc.add_characteristic(0x2a19, &[CharacteristicProp::Read], &mut a)
+            .unwrap();
+        drop(svc);
+        let mut svc = table
+            .add_service(Service::new(0x180a).with_handles(0x10..=0x1f))
+            .unwrap();
+        svc.add_characteristic(0x2a29, &[CharacteristicProp::Read], &mut b)
+            .unwrap();
+        drop(svc);
+
+        let handles = table.iterate(|mut it| {
+            let mut handles = heapless::Vec::<u16, 16>::new();
+            while let Some(att) = it.next() {
+                handles.push(att.handle).unwrap();
+            }
+            handles
+        });
+        assert_eq!(handles, [0x10, 0x11, 0x12, 0x40, 0x41, 0x42]);
+
+        for handle in handles {
+            assert_eq!(table.with_attribute(handle, |att| att.handle), Some(handle));
+        }
+        assert!(table.with_attribute(0x13, |_| ()).is_none());
+        assert!(table.with_attribute(0x00, |_| ()).is_none());
+        assert!(table.with_attribute(0xffff, |_| ()).is_none());
+
+        // Iteration starts at the first attribute at or after the requested handle
+        assert_eq!(
+            table.iterate_from(0x13, |mut it| it.next().map(|att| att.handle)),
+            Some(0x40)
+        );
+        assert_eq!(table.iterate_from(0x43, |mut it| it.next().map(|att| att.handle)), None);
+        assert_eq!(table.find_characteristic(0x42).map(|c| c.handle), Some(0x42));
+        assert_eq!(table.find_characteristic(0x41), None);
+    }
+
+    #[test]
+    fn test_fixed_handles_overlap() {
+        let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+        table
+            .add_service(Service::new(0x180f).with_handles(0x10..=0x1f))
+            .unwrap();
+        assert!(matches!(
+            table.add_service(Service::new(0x180a).with_handles(0x18..=0x20)),
+            Err(Error::InvalidValue)
+        ));
+        assert!(matches!(
+            table.add_service(Service::new(0x180a).with_handles(0x30..=0x2f)),
+            Err(Error::InvalidValue)
+        ));
+        assert!(matches!(
+            table.add_service(Service::new(0x180a).with_handles(0..=0x0f)),
+            Err(Error::InvalidValue)
+        ));
+
+        // Failed services leave the table usable
+        let svc = table
+            .add_service(Service::new(0x180a).with_handles(0x20..=0x2f))
+            .unwrap();
+        assert!(matches!(table.add_service(Service::new(0x1812)), Err(Error::Busy)));
+        assert_eq!(svc.build().handle, 0x20);
+    }
+
+    #[test]
+    fn test_table_full() {
+        let mut a = [0; 1];
+        let mut b = [0; 1];
+        let table: AttributeTable<'_, NoopRawMutex, 4> = AttributeTable::new();
+
+        let mut svc = table.add_service(Service::new(0x180f)).unwrap();
+        let level = svc
+            .add_characteristic(0x2a19, &[CharacteristicProp::Read], &mut a)
+            .unwrap();
+        // A characteristic takes two attributes, so it is rejected as a whole
+        assert!(matches!(
+            svc.add_characteristic(0x2a1a, &[CharacteristicProp::Read], &mut b),
+            Err(Error::InsufficientSpace)
+        ));
+        let mut descriptors = svc.descriptors(level).unwrap();
+        descriptors.add_user_description("Level").unwrap();
+        assert!(matches!(
+            descriptors.add_user_description("Level"),
+            Err(Error::InsufficientSpace)
+        ));
+        drop(svc);
+
+        let handles = table.iterate(|mut it| {
+            let mut handles = heapless::Vec::<u16, 4>::new();
+            while let Some(att) = it.next() {
+                handles.push(att.handle).unwrap();
+            }
+            handles
+        });
+        assert_eq!(handles, [0x01, 0x02, 0x03, 0x04]);
+        assert!(matches!(
+            table.add_service(Service::new(0x180a)),
+            Err(Error::InsufficientSpace)
+        ));
+    }
+
+    #[test]
+    fn test_handles_exhausted() {
+        let mut a = [0; 1];
+        let mut b = [0; 1];
+        let mut c = [0; 1];
+        let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+
+        // A characteristic with a CCCD does not fit in the remaining two handles of the range
+        let mut svc = table
+            .add_service(Service::new(0x180f).with_handles(0xfffd..=0xffff))
+            .unwrap();
+        let props = &[CharacteristicProp::Read, CharacteristicProp::Notify];
+        assert!(matches!(
+            svc.add_characteristic(0x2a19, props, &mut a),
+            Err(Error::InsufficientSpace)
+        ));
+        let level = svc
+            .add_characteristic(0x2a19, &[CharacteristicProp::Read], &mut b)
+            .unwrap();
+        assert_eq!(level.handle, 0xffff);
+        let mut descriptors = svc.descriptors(level).unwrap();
+        assert!(matches!(
+            descriptors.add_user_description("Level"),
+            Err(Error::InsufficientSpace)
+        ));
+        drop(svc);
+
+        // Once handle 0xFFFF is in use, only services with an explicit handle range can be added
+        assert!(matches!(
+            table.add_service(Service::new(0x180a)),
+            Err(Error::InsufficientSpace)
+        ));
+        let mut svc = table
+            .add_service(Service::new(0x180a).with_handles(0x10..=0x1f))
+            .unwrap();
+        svc.add_characteristic(0x2a29, &[CharacteristicProp::Read], &mut c)
+            .unwrap();
+        drop(svc);
+        assert!(matches!(
+            table.add_service(Service::new(0x1812)),
+            Err(Error::InsufficientSpace)
+        ));
+    }
+}
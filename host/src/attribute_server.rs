@@ -1,14 +1,19 @@
 use core::cell::RefCell;
+use core::ops::RangeInclusive;
+use core::task::{Context, Poll};
 
 use bt_hci::param::ConnHandle;
 use embassy_sync::blocking_mutex::raw::RawMutex;
 use embassy_sync::blocking_mutex::Mutex;
+use heapless::Vec;
 
 use crate::att::{self, Att, AttDecodeError, AttErrorCode};
 use crate::attribute::{AttributeData, AttributeTable};
-use crate::codec;
+use crate::connection::Connection;
+use crate::connection_manager::DynamicConnectionManager;
 use crate::cursor::WriteCursor;
 use crate::types::uuid::Uuid;
+use crate::{codec, Error};
 
 #[derive(Debug, PartialEq)]
 pub enum WorkResult {
@@ -34,14 +39,187 @@ impl From<codec::Error> for AttributeServerError {
     }
 }
 
-const MAX_NOTIFICATIONS: usize = 4;
+pub(crate) const MAX_NOTIFICATIONS: usize = 4;
+
+/// Subscriptions of connections to the CCCDs of an attribute table.
 pub struct NotificationTable<const ENTRIES: usize> {
     state: [(u16, ConnHandle); ENTRIES],
 }
 
+impl<const ENTRIES: usize> NotificationTable<ENTRIES> {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: [(0, ConnHandle::new(0)); ENTRIES],
+        }
+    }
+
+    pub(crate) fn contains(&self, conn: ConnHandle, cccd_handle: u16) -> bool {
+        self.state.iter().any(|entry| entry.0 == cccd_handle && entry.1 == conn)
+    }
+
+    pub(crate) fn set(&mut self, conn: ConnHandle, cccd_handle: u16, enable: bool) {
+        if enable {
+            if self.contains(conn, cccd_handle) {
+                return;
+            }
+            for entry in self.state.iter_mut() {
+                if entry.0 == 0 {
+                    entry.0 = cccd_handle;
+                    entry.1 = conn;
+                    return;
+                }
+            }
+        } else {
+            for entry in self.state.iter_mut() {
+                if entry.0 == cccd_handle && entry.1 == conn {
+                    entry.0 = 0;
+                    entry.1 = ConnHandle::new(0);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Connections subscribed to the given CCCD.
+    pub(crate) fn subscribers(&self, cccd_handle: u16) -> impl Iterator<Item = ConnHandle> + '_ {
+        self.state
+            .iter()
+            .filter(move |entry| entry.0 == cccd_handle && cccd_handle != 0)
+            .map(|entry| entry.1)
+    }
+
+    /// Remove the subscriptions to CCCDs within the given handle range.
+    pub(crate) fn remove_range(&mut self, range: RangeInclusive<u16>) {
+        for entry in self.state.iter_mut() {
+            if range.contains(&entry.0) {
+                entry.0 = 0;
+                entry.1 = ConnHandle::new(0);
+            }
+        }
+    }
+}
+
+/// State of the indications sent to connections.
+struct Indications {
+    /// Number of service changes which have been indicated.
+    seen: u32,
+    /// Indications awaiting confirmation.
+    outstanding: Vec<OutstandingIndication, MAX_NOTIFICATIONS>,
+}
+
+/// An indication awaiting confirmation by a connection.
+struct OutstandingIndication {
+    /// Index and generation of the connection slot, so that indications to disconnected links can be forgotten.
+    slot: (u8, u16),
+    /// Whether services changed again since the indication was sent.
+    changed: bool,
+}
+
+impl Indications {
+    /// Start an indication to a connection, after forgetting the indications to links which were disconnected.
+    ///
+    /// If an indication to the connection is outstanding, `outstanding` is applied to it and `false` is returned.
+    fn start(
+        &mut self,
+        connection: &Connection<'_>,
+        connections: &dyn DynamicConnectionManager,
+        outstanding: impl FnOnce(&mut OutstandingIndication),
+    ) -> Result<bool, Error> {
+        self.outstanding
+            .retain(|entry| connections.handle(entry.slot.0, entry.slot.1).is_ok());
+        let slot = connection.slot();
+        if let Some(entry) = self.outstanding.iter_mut().find(|entry| entry.slot == slot) {
+            outstanding(entry);
+            return Ok(false);
+        }
+        self.outstanding
+            .push(OutstandingIndication { slot, changed: false })
+            .map_err(|_| Error::InsufficientSpace)?;
+        Ok(true)
+    }
+}
+
+/// Number of bytes of prepared write values which can be queued.
+const PREPARE_QUEUE_SIZE: usize = 512;
+/// Number of prepared writes which can be queued.
+const PREPARE_QUEUE_ENTRIES: usize = 8;
+
+/// A prepared write, whose value is stored in the queue buffer.
+struct PreparedWrite {
+    conn: ConnHandle,
+    handle: u16,
+    offset: usize,
+    start: usize,
+    len: usize,
+}
+
+/// Queue of prepared writes, which are applied when the client executes them.
+///
+/// Consecutive parts of a long write are merged into a single write, so that values are written as a whole.
+pub(crate) struct PrepareQueue {
+    data: [u8; PREPARE_QUEUE_SIZE],
+    writes: Vec<PreparedWrite, PREPARE_QUEUE_ENTRIES>,
+}
+
+impl PrepareQueue {
+    const fn new() -> Self {
+        Self {
+            data: [0; PREPARE_QUEUE_SIZE],
+            writes: Vec::new(),
+        }
+    }
+
+    fn used(&self) -> usize {
+        self.writes.last().map(|w| w.start + w.len).unwrap_or(0)
+    }
+
+    fn push(&mut self, conn: ConnHandle, handle: u16, offset: usize, value: &[u8]) -> Result<(), AttErrorCode> {
+        let used = self.used();
+        if used + value.len() > PREPARE_QUEUE_SIZE {
+            return Err(AttErrorCode::PrepareQueueFull);
+        }
+        match self.writes.last_mut() {
+            Some(last) if last.conn == conn && last.handle == handle && last.offset + last.len == offset => {
+                last.len += value.len();
+            }
+            _ => {
+                self.writes
+                    .push(PreparedWrite {
+                        conn,
+                        handle,
+                        offset,
+                        start: used,
+                        len: value.len(),
+                    })
+                    .map_err(|_| AttErrorCode::PrepareQueueFull)?;
+            }
+        }
+        self.data[used..used + value.len()].copy_from_slice(value);
+        Ok(())
+    }
+
+    /// Remove the prepared writes of a connection, compacting the values of the remaining writes.
+    fn clear(&mut self, conn: ConnHandle) {
+        let mut used = 0;
+        let mut i = 0;
+        while i < self.writes.len() {
+            if self.writes[i].conn == conn {
+                self.writes.remove(i);
+            } else {
+                let w = &mut self.writes[i];
+                self.data.copy_within(w.start..w.start + w.len, used);
+                w.start = used;
+                used += w.len;
+                i += 1;
+            }
+        }
+    }
+}
+
 pub struct AttributeServer<'c, 'd, M: RawMutex, const MAX: usize> {
     pub(crate) table: &'c AttributeTable<'d, M, MAX>,
-    pub(crate) notification: Mutex<M, RefCell<NotificationTable<MAX_NOTIFICATIONS>>>,
+    prepared: Mutex<M, RefCell<PrepareQueue>>,
+    indications: Mutex<M, RefCell<Indications>>,
 }
 
 impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
@@ -49,43 +227,71 @@ impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
     pub fn new(table: &'c AttributeTable<'d, M, MAX>) -> AttributeServer<'c, 'd, M, MAX> {
         AttributeServer {
             table,
-            notification: Mutex::new(RefCell::new(NotificationTable {
-                state: [(0, ConnHandle::new(0)); 4],
+            prepared: Mutex::new(RefCell::new(PrepareQueue::new())),
+            indications: Mutex::new(RefCell::new(Indications {
+                seen: table.service_changes(),
+                outstanding: Vec::new(),
             })),
         }
     }
 
     pub(crate) fn should_notify(&self, conn: ConnHandle, cccd_handle: u16) -> bool {
-        self.notification.lock(|n| {
-            let n = n.borrow();
-            for entry in n.state.iter() {
-                if entry.0 == cccd_handle && entry.1 == conn {
-                    return true;
+        self.table.is_subscribed(conn, cccd_handle)
+    }
+
+    /// Poll for services being added or removed since the last poll, returning the number of changes.
+    pub(crate) fn poll_service_changed(&self, cx: &mut Context<'_>) -> Poll<u32> {
+        self.indications.lock(|state| {
+            let mut state = state.borrow_mut();
+            match self.table.poll_service_changes(state.seen, cx) {
+                Poll::Ready(changes) => {
+                    let count = changes.wrapping_sub(state.seen);
+                    state.seen = changes;
+                    Poll::Ready(count)
                 }
+                Poll::Pending => Poll::Pending,
             }
-            false
         })
     }
 
-    fn set_notify(&self, conn: ConnHandle, cccd_handle: u16, enable: bool) {
-        self.notification.lock(|n| {
-            let mut n = n.borrow_mut();
-            if enable {
-                for entry in n.state.iter_mut() {
-                    if entry.0 == 0 {
-                        entry.0 = cccd_handle;
-                        entry.1 = conn;
-                        return;
-                    }
-                }
-            } else {
-                for entry in n.state.iter_mut() {
-                    if entry.0 == cccd_handle && entry.1 == conn {
-                        entry.0 = 0;
-                        entry.1 = ConnHandle::new(0);
-                        return;
-                    }
-                }
+    /// Start a Service Changed indication to a connection.
+    ///
+    /// Only one indication may await confirmation at a time, so if an indication is outstanding, the change is
+    /// recorded and `false` is returned. The change is indicated once the outstanding indication is confirmed.
+    /// If too many indications are outstanding, [`Error::InsufficientSpace`] is returned.
+    pub(crate) fn start_indication(
+        &self,
+        connection: &Connection<'_>,
+        connections: &dyn DynamicConnectionManager,
+    ) -> Result<bool, Error> {
+        self.indications.lock(|state| {
+            state
+                .borrow_mut()
+                .start(connection, connections, |entry| entry.changed = true)
+        })
+    }
+
+    /// Start an indication of a characteristic value to a connection.
+    ///
+    /// Returns `false` if an indication to the connection already awaits confirmation, and
+    /// [`Error::InsufficientSpace`] if too many indications are outstanding.
+    pub(crate) fn start_value_indication(
+        &self,
+        connection: &Connection<'_>,
+        connections: &dyn DynamicConnectionManager,
+    ) -> Result<bool, Error> {
+        self.indications
+            .lock(|state| state.borrow_mut().start(connection, connections, |_| {}))
+    }
+
+    /// Handle a confirmation of an indication, returning whether services changed while it was outstanding.
+    pub(crate) fn confirm_indication(&self, connection: &Connection<'_>) -> bool {
+        self.indications.lock(|state| {
+            let mut state = state.borrow_mut();
+            let slot = connection.slot();
+            match state.outstanding.iter().position(|entry| entry.slot == slot) {
+                Some(index) => state.outstanding.swap_remove(index).changed,
+                None => false,
             }
         })
     }
@@ -101,11 +307,14 @@ impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
         let mut data = WriteCursor::new(buf);
 
         let (mut header, mut body) = data.split(2)?;
-        let err = self.table.iterate(|mut it| {
+        let err = self.table.iterate_from(start, |mut it| {
             let mut err = Err(AttErrorCode::AttributeNotFound);
             while let Some(att) = it.next() {
+                if att.handle > end {
+                    break;
+                }
                 //            trace!("Check attribute {:x} {}", att.uuid, att.handle);
-                if att.uuid == attribute_type && att.handle >= start && att.handle <= end {
+                if att.uuid == attribute_type {
                     body.write(att.handle)?;
                     handle = att.handle;
 
@@ -150,11 +359,14 @@ impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
         let mut data = WriteCursor::new(buf);
 
         let (mut header, mut body) = data.split(2)?;
-        let err = self.table.iterate(|mut it| {
+        let err = self.table.iterate_from(start, |mut it| {
             let mut err = Err(AttErrorCode::AttributeNotFound);
             while let Some(att) = it.next() {
+                if att.handle > end {
+                    break;
+                }
                 //            trace!("Check attribute {:x} {}", att.uuid, att.handle);
-                if att.uuid == group_type && att.handle >= start && att.handle <= end {
+                if att.uuid == group_type {
                     //debug!("found! {:x} {}", att.uuid, att.handle);
                     handle = att.handle;
 
@@ -193,21 +405,19 @@ impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
 
         data.write(att::ATT_READ_RESPONSE_OPCODE)?;
 
-        let err = self.table.iterate(|mut it| {
-            let mut err = Err(AttErrorCode::AttributeNotFound);
-            while let Some(att) = it.next() {
-                if att.handle == handle {
-                    if att.data.readable() {
-                        err = att.data.read(0, data.write_buf());
-                        if let Ok(len) = err {
-                            data.commit(len)?;
-                        }
+        let err = self
+            .table
+            .with_attribute(handle, |att| {
+                let mut err = Err(AttErrorCode::AttributeNotFound);
+                if att.data.readable() {
+                    err = att.data.read(0, data.write_buf());
+                    if let Ok(len) = err {
+                        data.commit(len)?;
                     }
-                    break;
                 }
-            }
-            err
-        });
+                err
+            })
+            .unwrap_or(Err(AttErrorCode::AttributeNotFound));
 
         match err {
             Ok(_) => Ok(data.len()),
@@ -215,20 +425,16 @@ impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
         }
     }
 
-    fn handle_write_cmd(&self, buf: &mut [u8], handle: u16, data: &[u8]) -> Result<usize, AttributeServerError> {
-        // TODO: Generate event
-        self.table.iterate(|mut it| {
-            while let Some(att) = it.next() {
-                if att.handle == handle {
-                    if att.data.writable() {
-                        // Write commands can't respond with an error.
-                        att.data.write(0, data).unwrap();
-                    }
-                    break;
-                }
-            }
-            Ok(0)
-        })
+    /// Write the value of an attribute for a write command, returning whether the value was written.
+    pub(crate) fn handle_write_cmd(&self, handle: u16, data: &[u8]) -> bool {
+        // Write commands can't respond with an error, so failed writes are only logged.
+        let written = self
+            .table
+            .with_attribute(handle, |att| att.data.writable() && att.data.write(0, data).is_ok());
+        if written != Some(true) {
+            debug!("Ignoring write command for handle {}", handle);
+        }
+        written == Some(true)
     }
 
     fn handle_write_req(
@@ -238,27 +444,29 @@ impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
         handle: u16,
         data: &[u8],
     ) -> Result<usize, AttributeServerError> {
-        let err = self.table.iterate(|mut it| {
-            let mut err = Err(AttErrorCode::AttributeNotFound);
-            while let Some(att) = it.next() {
-                if att.handle == handle {
-                    if att.data.writable() {
-                        err = att.data.write(0, data);
-                        if err.is_ok() {
-                            if let AttributeData::Cccd {
-                                notifications,
-                                indications,
-                            } = att.data
-                            {
-                                self.set_notify(conn, handle, notifications);
-                            }
+        let mut subscribe = None;
+        let err = self
+            .table
+            .with_attribute(handle, |att| {
+                let mut err = Err(AttErrorCode::AttributeNotFound);
+                if att.data.writable() {
+                    err = att.data.write(0, data);
+                    if err.is_ok() {
+                        if let AttributeData::Cccd {
+                            notifications,
+                            indications,
+                        } = att.data
+                        {
+                            subscribe.replace(notifications || indications);
                         }
                     }
-                    break;
                 }
-            }
-            err
-        });
+                err
+            })
+            .unwrap_or(Err(AttErrorCode::AttributeNotFound));
+        if let Some(enable) = subscribe {
+            self.table.with_notifications(|n| n.set(conn, handle, enable));
+        }
 
         let mut w = WriteCursor::new(buf);
         match err {
@@ -297,17 +505,18 @@ impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
         header.write(att::ATT_FIND_INFORMATION_RSP_OPCODE)?;
         let mut t = 0;
 
-        self.table.iterate(|mut it| {
+        self.table.iterate_from(start, |mut it| {
             while let Some(att) = it.next() {
-                if att.handle >= start && att.handle <= end {
-                    if t == 0 {
-                        t = att.uuid.get_type();
-                    } else if t != att.uuid.get_type() {
-                        break;
-                    }
-                    body.write(att.handle)?;
-                    body.append(att.uuid.as_raw())?;
+                if att.handle > end {
+                    break;
                 }
+                if t == 0 {
+                    t = att.uuid.get_type();
+                } else if t != att.uuid.get_type() {
+                    break;
+                }
+                body.write(att.handle)?;
+                body.append(att.uuid.as_raw())?;
             }
             Ok::<(), AttributeServerError>(())
         })?;
@@ -325,7 +534,7 @@ impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
         }
     }
 
-    fn error_response(
+    pub(crate) fn error_response(
         mut w: WriteCursor<'_>,
         opcode: u8,
         handle: u16,
@@ -341,6 +550,7 @@ impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
 
     fn handle_prepare_write(
         &self,
+        conn: ConnHandle,
         buf: &mut [u8],
         handle: u16,
         offset: u16,
@@ -350,20 +560,24 @@ impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
         w.write(att::ATT_PREPARE_WRITE_RESP_OPCODE)?;
         w.write(handle)?;
         w.write(offset)?;
-
-        let err = self.table.iterate(|mut it| {
-            let mut err = Err(AttErrorCode::AttributeNotFound);
-            while let Some(att) = it.next() {
-                if att.handle == handle {
-                    if att.data.writable() {
-                        err = att.data.write(offset as usize, value);
-                    }
-                    w.append(value)?;
-                    break;
+        w.append(value)?;
+
+        // Permissions are checked when the write is prepared, while the offset and length are checked when the
+        // write is executed.
+        let err = self
+            .table
+            .with_attribute(handle, |att| {
+                if att.data.writable() {
+                    Ok(())
+                } else {
+                    Err(AttErrorCode::WriteNotPermitted)
                 }
-            }
-            err
-        });
+            })
+            .unwrap_or(Err(AttErrorCode::InvalidHandle))
+            .and_then(|_| {
+                self.prepared
+                    .lock(|q| q.borrow_mut().push(conn, handle, offset as usize, value))
+            });
 
         match err {
             Ok(()) => Ok(w.len()),
@@ -371,31 +585,62 @@ impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
         }
     }
 
-    fn handle_execute_write(&self, buf: &mut [u8], _flags: u8) -> Result<usize, AttributeServerError> {
+    fn handle_execute_write(&self, conn: ConnHandle, buf: &mut [u8], flags: u8) -> Result<usize, AttributeServerError> {
+        let mut err = Ok(());
+        self.prepared.lock(|q| {
+            let mut q = q.borrow_mut();
+            // Flags 0x00 cancels all prepared writes, 0x01 writes all pending values
+            if flags == 0x01 {
+                // All writes are checked before any is applied, so that either all values are written or none
+                for write in q.writes.iter().filter(|w| w.conn == conn) {
+                    let result = self
+                        .table
+                        .with_attribute(write.handle, |att| att.data.check_write(write.offset, write.len))
+                        .unwrap_or(Err(AttErrorCode::InvalidHandle));
+                    if let Err(e) = result {
+                        err = Err((write.handle, e));
+                        break;
+                    }
+                }
+                if err.is_ok() {
+                    for write in q.writes.iter().filter(|w| w.conn == conn) {
+                        let value = &q.data[write.start..write.start + write.len];
+                        let _ = self
+                            .table
+                            .with_attribute(write.handle, |att| att.data.write(write.offset, value));
+                    }
+                }
+            }
+            q.clear(conn);
+        });
+
         let mut w = WriteCursor::new(buf);
-        w.write(att::ATT_EXECUTE_WRITE_RESP_OPCODE)?;
-        Ok(w.len())
+        match err {
+            Ok(()) => {
+                w.write(att::ATT_EXECUTE_WRITE_RESP_OPCODE)?;
+                Ok(w.len())
+            }
+            Err((handle, e)) => Ok(Self::error_response(w, att::ATT_EXECUTE_WRITE_REQ_OPCODE, handle, e)?),
+        }
     }
 
     fn handle_read_blob(&self, buf: &mut [u8], handle: u16, offset: u16) -> Result<usize, AttributeServerError> {
         let mut w = WriteCursor::new(buf);
         w.write(att::ATT_READ_BLOB_RESP_OPCODE)?;
 
-        let err = self.table.iterate(|mut it| {
-            let mut err = Err(AttErrorCode::AttributeNotFound);
-            while let Some(att) = it.next() {
-                if att.handle == handle {
-                    if att.data.readable() {
-                        err = att.data.read(offset as usize, w.write_buf());
-                        if let Ok(n) = &err {
-                            w.commit(*n)?;
-                        }
+        let err = self
+            .table
+            .with_attribute(handle, |att| {
+                let mut err = Err(AttErrorCode::AttributeNotFound);
+                if att.data.readable() {
+                    err = att.data.read(offset as usize, w.write_buf());
+                    if let Ok(n) = &err {
+                        w.commit(*n)?;
                     }
-                    break;
                 }
-            }
-            err
-        });
+                err
+            })
+            .unwrap_or(Err(AttErrorCode::AttributeNotFound));
 
         match err {
             Ok(_) => Ok(w.len()),
@@ -433,7 +678,7 @@ impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
             Att::ReadReq { handle } => self.handle_read_req(rx, handle)?,
 
             Att::WriteCmd { handle, data } => {
-                self.handle_write_cmd(rx, handle, data)?;
+                self.handle_write_cmd(handle, data);
                 0
             }
 
@@ -448,13 +693,17 @@ impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
                 att_value,
             } => self.handle_find_type_value(rx, start_handle, end_handle, att_type, att_value)?,
 
-            Att::PrepareWriteReq { handle, offset, value } => self.handle_prepare_write(rx, handle, offset, value)?,
+            Att::PrepareWriteReq { handle, offset, value } => {
+                self.handle_prepare_write(conn, rx, handle, offset, value)?
+            }
 
-            Att::ExecuteWriteReq { flags } => self.handle_execute_write(rx, flags)?,
+            Att::ExecuteWriteReq { flags } => self.handle_execute_write(conn, rx, flags)?,
 
             Att::ReadBlobReq { handle, offset } => self.handle_read_blob(rx, handle, offset)?,
 
             Att::ReadMultipleReq { handles } => self.handle_read_multiple(rx, handles)?,
+
+            Att::HandleValueConfirmation => 0,
         };
         if len > 0 {
             Ok(Some(len))
@@ -463,3 +712,203 @@ impl<'c, 'd, M: RawMutex, const MAX: usize> AttributeServer<'c, 'd, M, MAX> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bt_hci::param::{LeConnRole, Status};
+    use bt_hci::FromHciBytes;
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::attribute::{CharacteristicProp, Service, INCLUDE_UUID16};
+    use crate::connection_manager::ConnectionManager;
+    use crate::mock_controller::connect;
+
+    #[test]
+    fn test_discover_includes() {
+        let mut level = [0; 1];
+        let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+        let mut svc = table.add_service(Service::secondary(0x180f)).unwrap();
+        svc.add_characteristic(0x2a19, &[CharacteristicProp::Read], &mut level)
+            .unwrap();
+        let battery = svc.build();
+        let mut svc = table.add_service(Service::new(0x1812)).unwrap();
+        svc.include(battery).unwrap();
+        drop(svc);
+
+        let server = AttributeServer::new(&table);
+        let conn = ConnHandle::new(1);
+        let mut rx = [0; 32];
+
+        // Find included services of the primary service
+        let att = Att::ReadByTypeReq {
+            start: 0x10,
+            end: 0x1f,
+            attribute_type: INCLUDE_UUID16,
+        };
+        let len = server.process(conn, att, &mut rx).unwrap().unwrap();
+        assert_eq!(
+            rx[..len],
+            [
+                att::ATT_READ_BY_TYPE_RESPONSE_OPCODE,
+                8,
+                0x11,
+                0x00,
+                0x01,
+                0x00,
+                0x03,
+                0x00,
+                0x0f,
+                0x18
+            ]
+        );
+
+        // No more included services after the include declaration
+        let att = Att::ReadByTypeReq {
+            start: 0x12,
+            end: 0x1f,
+            attribute_type: INCLUDE_UUID16,
+        };
+        let len = server.process(conn, att, &mut rx).unwrap().unwrap();
+        assert_eq!(rx[0], att::ATT_ERROR_RESPONSE_OPCODE);
+        assert_eq!(rx[len - 1], AttErrorCode::AttributeNotFound as u8);
+    }
+
+    #[test]
+    fn test_reliable_write() {
+        let mut value = [0; 6];
+        let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+        let mut svc = table.add_service(Service::new(0x180f)).unwrap();
+        let handle = svc
+            .add_characteristic(
+                0x2a19,
+                &[CharacteristicProp::Read, CharacteristicProp::Write],
+                &mut value,
+            )
+            .unwrap();
+        drop(svc);
+
+        let server = AttributeServer::new(&table);
+        let conn = ConnHandle::new(1);
+        let mut rx = [0; 32];
+
+        // Prepared writes echo the request, but are not applied until executed
+        for (offset, part) in [(0, &[1, 2, 3][..]), (3, &[4, 5, 6][..])] {
+            let att = Att::PrepareWriteReq {
+                handle: handle.handle,
+                offset,
+                value: part,
+            };
+            let len = server.process(conn, att, &mut rx).unwrap().unwrap();
+            assert_eq!(rx[0], att::ATT_PREPARE_WRITE_RESP_OPCODE);
+            assert_eq!(&rx[5..len], part);
+        }
+        assert!(table.get(handle, |v| v == [0; 6]).unwrap());
+
+        // Parts of a long write are applied as a whole, which fixed length values require
+        server
+            .process(conn, Att::ExecuteWriteReq { flags: 0x01 }, &mut rx)
+            .unwrap();
+        assert_eq!(rx[0], att::ATT_EXECUTE_WRITE_RESP_OPCODE);
+        assert!(table.get(handle, |v| v == [1, 2, 3, 4, 5, 6]).unwrap());
+
+        // Cancelled writes are discarded
+        let att = Att::PrepareWriteReq {
+            handle: handle.handle,
+            offset: 0,
+            value: &[9; 6],
+        };
+        server.process(conn, att, &mut rx).unwrap();
+        server
+            .process(conn, Att::ExecuteWriteReq { flags: 0x00 }, &mut rx)
+            .unwrap();
+        assert_eq!(rx[0], att::ATT_EXECUTE_WRITE_RESP_OPCODE);
+        assert!(table.get(handle, |v| v == [1, 2, 3, 4, 5, 6]).unwrap());
+
+        // Invalid writes are reported when executed
+        let att = Att::PrepareWriteReq {
+            handle: handle.handle,
+            offset: 0,
+            value: &[9; 2],
+        };
+        server.process(conn, att, &mut rx).unwrap();
+        server
+            .process(conn, Att::ExecuteWriteReq { flags: 0x01 }, &mut rx)
+            .unwrap();
+        assert_eq!(rx[0], att::ATT_ERROR_RESPONSE_OPCODE);
+        assert_eq!(rx[4], AttErrorCode::InvalidAttributeValueLength as u8);
+    }
+
+    #[test]
+    fn test_execute_write_atomic() {
+        let mut first = [0; 2];
+        let mut second = [0; 2];
+        let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+        let mut svc = table.add_service(Service::new(0x180f)).unwrap();
+        let props = &[CharacteristicProp::Read, CharacteristicProp::Write];
+        let first = svc.add_characteristic(0x2a19, props, &mut first).unwrap();
+        let second = svc.add_characteristic(0x2a1a, props, &mut second).unwrap();
+        drop(svc);
+
+        let server = AttributeServer::new(&table);
+        let conn = ConnHandle::new(1);
+        let mut rx = [0; 32];
+
+        // The write of the second value is too short, so neither value is written
+        for (handle, value) in [(first, &[1, 2][..]), (second, &[3][..])] {
+            let att = Att::PrepareWriteReq {
+                handle: handle.handle,
+                offset: 0,
+                value,
+            };
+            server.process(conn, att, &mut rx).unwrap();
+        }
+        server
+            .process(conn, Att::ExecuteWriteReq { flags: 0x01 }, &mut rx)
+            .unwrap();
+        assert_eq!(rx[0], att::ATT_ERROR_RESPONSE_OPCODE);
+        assert_eq!(u16::from_le_bytes([rx[2], rx[3]]), second.handle);
+        assert!(table.get(first, |v| v == [0, 0]).unwrap());
+        assert!(table.get(second, |v| v == [0, 0]).unwrap());
+    }
+
+    #[test]
+    fn test_outstanding_indications() {
+        let table: AttributeTable<'_, NoopRawMutex, 4> = AttributeTable::new();
+        let server = AttributeServer::new(&table);
+        let manager: ConnectionManager<NoopRawMutex, 5> = ConnectionManager::new();
+
+        connect(&manager, 1, LeConnRole::Peripheral).unwrap();
+        let first = block_on(manager.accept(&[]));
+        assert!(server.start_value_indication(&first, &manager).unwrap());
+        assert!(!server.start_value_indication(&first, &manager).unwrap());
+
+        // The indication to a disconnected link is forgotten, also when its handle is reused
+        let reason = Status::from_hci_bytes_complete(&[0x13]).unwrap();
+        manager.disconnect(first.handle(), reason).unwrap();
+        drop(first);
+        connect(&manager, 1, LeConnRole::Peripheral).unwrap();
+        let reused = block_on(manager.accept(&[]));
+        assert!(server.start_value_indication(&reused, &manager).unwrap());
+
+        // Indications which can't be tracked are reported
+        let mut connections = heapless::Vec::<_, 4>::new();
+        for handle in 2..=4 {
+            connect(&manager, handle, LeConnRole::Peripheral).unwrap();
+            let connection = block_on(manager.accept(&[]));
+            assert!(server.start_indication(&connection, &manager).unwrap());
+            let _ = connections.push(connection);
+        }
+        connect(&manager, 5, LeConnRole::Peripheral).unwrap();
+        let last = block_on(manager.accept(&[]));
+        assert!(matches!(
+            server.start_indication(&last, &manager),
+            Err(Error::InsufficientSpace)
+        ));
+
+        // Once confirmed, indications can be sent again
+        assert!(!server.confirm_indication(&reused));
+        assert!(server.start_indication(&last, &manager).unwrap());
+    }
+}
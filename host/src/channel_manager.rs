@@ -3,31 +3,127 @@ use core::future::poll_fn;
 use core::task::{Context, Poll};
 
 use bt_hci::controller::Controller;
-use bt_hci::param::ConnHandle;
+use bt_hci::param::{ConnHandle, LeConnRole};
 use bt_hci::FromHciBytes;
+use embassy_futures::select::{select, Either};
 use embassy_sync::blocking_mutex::raw::RawMutex;
 use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::channel::Channel;
 use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::{with_timeout, Duration, Instant};
+use heapless::Vec;
 
 use crate::adapter::HciController;
+use crate::codec::Decode;
+use crate::connection::SecurityLevel;
 use crate::cursor::{ReadCursor, WriteCursor};
-use crate::packet_pool::{AllocId, DynamicPacketPool, Packet};
+use crate::packet_pool::{AllocId, DynamicPacketPool, Packet, SEND_ID};
 use crate::pdu::Pdu;
 use crate::types::l2cap::{
-    CommandRejectRes, DisconnectionReq, DisconnectionRes, L2capHeader, L2capSignalCode, L2capSignalHeader,
-    LeCreditConnReq, LeCreditConnRes, LeCreditConnResultCode, LeCreditFlowInd,
+    ChannelIds, CommandRejectReason, CommandRejectRes, ConnParamUpdateReq, ConnParamUpdateRes, CreditConnReconfigReq,
+    CreditConnReconfigRes, CreditConnReconfigResultCode, CreditConnReq, CreditConnRes, DisconnectionReq,
+    DisconnectionRes, L2capHeader, L2capSignalCode, L2capSignalHeader, LeCreditConnReq, LeCreditConnRes,
+    LeCreditConnResultCode, LeCreditFlowInd, CONN_PARAM_UPDATE_ACCEPTED, CONN_PARAM_UPDATE_REJECTED, L2CAP_CID_DYN_END,
+    L2CAP_CID_DYN_START, L2CAP_ECFC_MAX_CHANNELS, L2CAP_ECFC_MIN_MTU, L2CAP_RTX_TIMEOUT_DEFAULT, L2CAP_RTX_TIMEOUT_MAX,
+    L2CAP_RTX_TIMEOUT_MIN, L2CAP_SIGNAL_MTU,
 };
 use crate::{AdapterError, Error};
 
 const BASE_ID: u16 = 0x40;
 
+// Number of times a disconnection request is sent before the channel is released anyway.
+const DISCONNECT_ATTEMPTS: usize = 2;
+
 struct State<const CHANNELS: usize> {
     next_req_id: u8,
     channels: [ChannelStorage; CHANNELS],
     accept_waker: WakerRegistration,
     create_waker: WakerRegistration,
+    disconnect_waker: WakerRegistration,
     credit_wakers: [WakerRegistration; CHANNELS],
+    release_wakers: [WakerRegistration; CHANNELS],
+    closed_wakers: [WakerRegistration; CHANNELS],
+    reconfigure: Option<(u8, Option<CreditConnReconfigResultCode>)>,
+    reconfigure_waker: WakerRegistration,
+    conn_param_updates: Vec<ConnParamUpdate, CHANNELS>,
+    conn_param_update_waker: WakerRegistration,
+    conn_param_handlers: usize,
+    psms: Vec<PsmRegistration, CHANNELS>,
+}
+
+impl<const CHANNELS: usize> State<CHANNELS> {
+    // Outcome of a connection request for the given channels, once the peer has answered.
+    //
+    // Refused channels are released, and the result code is returned when no channel was accepted.
+    fn connect_result(&mut self, conn: ConnHandle, req_id: u8, cids: &[u16]) -> Poll<Result<ChannelIds, Error>> {
+        let mut connected = ChannelIds::new();
+        let mut refused = None;
+        for storage in self.channels.iter_mut() {
+            if storage.conn != conn.raw() || !cids.contains(&storage.cid) {
+                continue;
+            }
+            match storage.state {
+                ChannelState::Connecting(id) if id == req_id => return Poll::Pending,
+                ChannelState::Disconnecting => return Poll::Ready(Err(Error::Disconnected)),
+                ChannelState::Connected => {
+                    let _ = connected.push(storage.cid);
+                }
+                ChannelState::Refused(id, result) if id == req_id => {
+                    storage.state = ChannelState::Disconnected;
+                    refused = Some(result);
+                }
+                _ => {}
+            }
+        }
+        if !connected.is_empty() {
+            return Poll::Ready(Ok(connected));
+        }
+        Poll::Ready(Err(match refused {
+            Some(Some(result)) => Error::ChannelRefused(result),
+            // The peer refused with a result code this host does not know.
+            Some(None) => Error::InvalidValue,
+            // Channels released without a response were rejected by a peer not supporting the request.
+            None => Error::NotSupported,
+        }))
+    }
+
+    // Result of the connection parameter update request sent on a connection.
+    //
+    // The request is dropped when the connection is lost.
+    fn conn_param_update_result(&self, conn: ConnHandle, req_id: u8) -> Poll<Result<u16, Error>> {
+        match self
+            .conn_param_updates
+            .iter()
+            .find(|update| update.conn == conn.raw() && update.req_id == req_id)
+        {
+            Some(ConnParamUpdate {
+                result: Some(result), ..
+            }) => Poll::Ready(Ok(*result)),
+            Some(_) => Poll::Pending,
+            None => Poll::Ready(Err(Error::Disconnected)),
+        }
+    }
+}
+
+// Connection parameter update request sent on a connection, waiting for the response of the central.
+struct ConnParamUpdate {
+    conn: u16,
+    req_id: u8,
+    result: Option<u16>,
+}
+
+// Counts a task waiting for connection parameter update requests while it is alive.
+struct ConnParamHandler<'a, M: RawMutex, const CHANNELS: usize> {
+    state: &'a Mutex<M, RefCell<State<CHANNELS>>>,
+}
+
+impl<'a, M: RawMutex, const CHANNELS: usize> Drop for ConnParamHandler<'a, M, CHANNELS> {
+    fn drop(&mut self) {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            state.conn_param_handlers -= 1;
+        });
+    }
 }
 
 /// Channel manager for L2CAP channels used directly by clients.
@@ -42,6 +138,7 @@ pub struct ChannelManager<
     pool: &'d dyn DynamicPacketPool<'d>,
     state: Mutex<M, RefCell<State<CHANNELS>>>,
     inbound: [Channel<M, Option<Pdu<'d>>, L2CAP_RXQ>; CHANNELS],
+    conn_param_requests: Channel<M, (ConnHandle, u8, ConnParamUpdateReq), 1>,
 }
 
 impl<
@@ -64,9 +161,19 @@ impl<
                 channels: [ChannelStorage::DISCONNECTED; CHANNELS],
                 accept_waker: WakerRegistration::new(),
                 create_waker: WakerRegistration::new(),
+                disconnect_waker: WakerRegistration::new(),
                 credit_wakers: [Self::CREDIT_WAKER; CHANNELS],
+                release_wakers: [Self::CREDIT_WAKER; CHANNELS],
+                closed_wakers: [Self::CREDIT_WAKER; CHANNELS],
+                reconfigure: None,
+                reconfigure_waker: WakerRegistration::new(),
+                conn_param_updates: Vec::new(),
+                conn_param_update_waker: WakerRegistration::new(),
+                conn_param_handlers: 0,
+                psms: Vec::new(),
             })),
             inbound: [Self::RX_CHANNEL; CHANNELS],
+            conn_param_requests: Channel::new(),
         }
     }
 
@@ -86,35 +193,147 @@ impl<
     pub(crate) fn disconnect(&self, cid: u16) -> Result<ConnHandle, Error> {
         let handle = self.state.lock(|state| {
             let mut state = state.borrow_mut();
+            let mut found = None;
             for (idx, storage) in state.channels.iter_mut().enumerate() {
+                if cid != storage.cid {
+                    continue;
+                }
                 match storage.state {
-                    ChannelState::Disconnecting if cid == storage.cid => {
+                    ChannelState::Disconnecting => {
                         storage.state = ChannelState::Disconnected;
                         storage.cid = 0;
                         let _ = self.inbound[idx].try_send(None);
-                        return Ok(storage.conn);
                     }
-                    ChannelState::PeerConnecting(_) if cid == storage.cid => {
+                    ChannelState::DisconnectRequested(_) => {
+                        storage.state = ChannelState::Disconnected;
+                    }
+                    ChannelState::PeerConnecting(_) | ChannelState::Connecting(_) | ChannelState::Connected => {
                         storage.state = ChannelState::Disconnecting;
                         let _ = self.inbound[idx].try_send(None);
-                        return Ok(storage.conn);
                     }
-                    ChannelState::Connecting(_) if cid == storage.cid => {
-                        storage.state = ChannelState::Disconnecting;
+                    ChannelState::Disconnected | ChannelState::Refused(..) => continue,
+                }
+                found = Some((idx, storage.conn));
+                break;
+            }
+            let (idx, conn) = found.ok_or(Error::NotFound)?;
+            // Both the receiving and sending side of the channel observe the disconnect.
+            state.credit_wakers[idx].wake();
+            state.closed_wakers[idx].wake();
+            state.disconnect_waker.wake();
+            Ok(conn)
+        })?;
+        Ok(ConnHandle::new(handle))
+    }
+
+    /// Ask the peer to close a channel, sending the request again if the peer does not answer in time.
+    ///
+    /// The channel is released once the peer confirms, or when the last attempt times out.
+    pub(crate) async fn close<T: Controller>(
+        &self,
+        cid: u16,
+        controller: &HciController<'_, T>,
+    ) -> Result<ConnHandle, AdapterError<T::Error>> {
+        let req_id = self.next_request_id();
+        let (conn, pending) = self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            for (idx, storage) in state.channels.iter_mut().enumerate() {
+                if storage.cid != cid {
+                    continue;
+                }
+                match storage.state {
+                    ChannelState::Connected => {
+                        storage.state = ChannelState::DisconnectRequested(req_id);
                         let _ = self.inbound[idx].try_send(None);
-                        return Ok(storage.conn);
+                        let pending = (storage.conn, Some((storage.peer_cid, storage.rtx_timeout)));
+                        state.credit_wakers[idx].wake();
+                        state.closed_wakers[idx].wake();
+                        return Ok(pending);
                     }
-                    ChannelState::Connected if cid == storage.cid => {
-                        storage.state = ChannelState::Disconnecting;
+                    // Already closed by the peer, only the slot is left to release.
+                    ChannelState::Disconnecting => {
+                        storage.state = ChannelState::Disconnected;
+                        storage.cid = 0;
                         let _ = self.inbound[idx].try_send(None);
-                        return Ok(storage.conn);
+                        return Ok((storage.conn, None));
                     }
+                    // Already being closed, the slot is released when the peer answers.
+                    ChannelState::DisconnectRequested(_) => return Ok((storage.conn, None)),
                     _ => {}
                 }
             }
             Err(Error::NotFound)
         })?;
-        Ok(ConnHandle::new(handle))
+        let conn = ConnHandle::new(conn);
+        let Some((peer_cid, timeout)) = pending else {
+            return Ok(conn);
+        };
+
+        let mut tx = [0; 18];
+        let request = DisconnectionReq {
+            dcid: peer_cid,
+            scid: cid,
+        };
+        let mut result = Err(Error::Timeout.into());
+        for _ in 0..DISCONNECT_ATTEMPTS {
+            // Retransmissions reuse the identifier, so a late response to an earlier attempt is still accepted.
+            if let Err(e) = controller.signal(conn, req_id, &request, &mut tx[..]).await {
+                result = Err(e);
+                break;
+            }
+            let confirmed = poll_fn(|cx| {
+                self.state.lock(|state| {
+                    let mut state = state.borrow_mut();
+                    let waiting = state.channels.iter().any(|storage| {
+                        storage.cid == cid && storage.state == ChannelState::DisconnectRequested(req_id)
+                    });
+                    if waiting {
+                        state.disconnect_waker.register(cx.waker());
+                        Poll::Pending
+                    } else {
+                        Poll::Ready(())
+                    }
+                })
+            });
+            if with_timeout(timeout, confirmed).await.is_ok() {
+                result = Ok(conn);
+                break;
+            }
+            warn!("[l2cap] no disconnection response for channel {}", cid);
+        }
+
+        // The channel is released whether or not the peer confirmed.
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            for storage in state.channels.iter_mut() {
+                if storage.cid == cid && storage.state == ChannelState::DisconnectRequested(req_id) {
+                    storage.state = ChannelState::Disconnected;
+                    storage.cid = 0;
+                }
+            }
+        });
+        result
+    }
+
+    // Release channels of a request that will not complete, including those closed while connecting.
+    fn release_pending(&self, cids: &[u16], req_id: u8) {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            for storage in state.channels.iter_mut() {
+                if !cids.contains(&storage.cid) {
+                    continue;
+                }
+                match storage.state {
+                    ChannelState::Connecting(id) | ChannelState::Refused(id, _) if id == req_id => {
+                        storage.state = ChannelState::Disconnected;
+                    }
+                    ChannelState::Disconnecting => {
+                        storage.state = ChannelState::Disconnected;
+                    }
+                    _ => {}
+                }
+            }
+        });
     }
 
     pub(crate) fn disconnected(&self, conn: ConnHandle) -> Result<(), Error> {
@@ -134,14 +353,24 @@ impl<
                         storage.state = ChannelState::Disconnecting;
                         let _ = self.inbound[idx].try_send(None);
                     }
+                    ChannelState::DisconnectRequested(_) if conn.raw() == storage.conn => {
+                        storage.state = ChannelState::Disconnected;
+                    }
                     _ => {}
                 }
             }
+            state.conn_param_updates.retain(|update| update.conn != conn.raw());
             state.accept_waker.wake();
             state.create_waker.wake();
+            state.disconnect_waker.wake();
+            state.reconfigure_waker.wake();
+            state.conn_param_update_waker.wake();
             for w in state.credit_wakers.iter_mut() {
                 w.wake();
             }
+            for w in state.closed_wakers.iter_mut() {
+                w.wake();
+            }
         });
         Ok(())
     }
@@ -150,8 +379,9 @@ impl<
         self.state.lock(|state| {
             let mut state = state.borrow_mut();
             for (idx, storage) in state.channels.iter_mut().enumerate() {
-                if let ChannelState::Disconnected = storage.state {
+                if storage.is_free() {
                     let cid: u16 = BASE_ID + idx as u16;
+                    *storage = ChannelStorage::DISCONNECTED;
                     storage.cid = cid;
                     f(storage);
                     return Ok(());
@@ -161,6 +391,23 @@ impl<
         })
     }
 
+    /// Register a PSM that peers may open channels to, replacing any previous registration.
+    pub(crate) fn register(&self, registration: PsmRegistration) -> Result<(), Error> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            if let Some(existing) = state.psms.iter_mut().find(|r| r.psm == registration.psm) {
+                *existing = registration;
+                return Ok(());
+            }
+            state.psms.push(registration).map_err(|_| Error::InsufficientSpace)
+        })
+    }
+
+    /// Accept an incoming connection request matching one of the PSMs, which must all be registered.
+    ///
+    /// For enhanced credit based requests, up to `max` of the requested channels are accepted and
+    /// the remaining ones are refused. Such requests can only be accepted with an MTU and MPS of at least
+    /// [`L2CAP_ECFC_MIN_MTU`], otherwise [`Error::InvalidValue`] is returned and the request is left pending.
     pub(crate) async fn accept<T: Controller>(
         &self,
         conn: ConnHandle,
@@ -168,62 +415,124 @@ impl<
         mtu: u16,
         credit_flow: CreditFlowPolicy,
         initial_credits: Option<u16>,
+        max: usize,
+        timeout: Duration,
         controller: &HciController<'_, T>,
-    ) -> Result<u16, AdapterError<T::Error>> {
+    ) -> Result<ChannelIds, AdapterError<T::Error>> {
+        check_rtx_timeout(timeout)?;
+        // Requests for PSMs that are not registered are refused, so they would never be accepted.
+        let registered = self.state.lock(|state| {
+            let state = state.borrow();
+            psm.iter().all(|p| state.psms.iter().any(|r| r.psm == *p))
+        });
+        if !registered {
+            return Err(Error::NotFound.into());
+        }
+        let mps = self.pool.mtu() as u16 - 4;
         // Wait until we find a channel for our connection in the connecting state matching our PSM.
-        let (req_id, mps, mtu, cid, credits) = poll_fn(|cx| {
+        let (req_id, group, credits, mtu, accepted) = poll_fn(|cx| {
             self.state.lock(|state| {
                 let mut state = state.borrow_mut();
-                for chan in state.channels.iter_mut() {
+                let mut request = None;
+                for chan in state.channels.iter() {
                     match chan.state {
                         ChannelState::PeerConnecting(req_id) if chan.conn == conn.raw() && psm.contains(&chan.psm) => {
-                            chan.mps = chan.mps.min(self.pool.mtu() as u16 - 4);
-                            chan.mtu = chan.mtu.min(mtu);
-                            chan.mtu = mtu;
-                            chan.flow_control = CreditFlowControl::new(
-                                credit_flow,
-                                initial_credits
-                                    .unwrap_or(self.pool.min_available(AllocId::from_channel(chan.cid)) as u16),
-                            );
-                            chan.state = ChannelState::Connected;
-
-                            return Poll::Ready((req_id, chan.mps, chan.mtu, chan.cid, chan.flow_control.available()));
+                            let credits = initial_credits.unwrap_or(self.initial_credits(credit_flow, chan.cid));
+                            let mtu = state
+                                .psms
+                                .iter()
+                                .find(|r| r.psm == chan.psm)
+                                .map(|r| r.mtu.min(mtu))
+                                .unwrap_or(mtu);
+                            request = Some((req_id, chan.ecfc_group, credits, mtu));
+                            break;
                         }
                         _ => {}
                     }
                 }
-                state.accept_waker.register(cx.waker());
-                Poll::Pending
+
+                let Some((req_id, group, credits, mtu)) = request else {
+                    state.accept_waker.register(cx.waker());
+                    return Poll::Pending;
+                };
+                if group != 0 && (mtu < L2CAP_ECFC_MIN_MTU || mps < L2CAP_ECFC_MIN_MTU) {
+                    return Poll::Ready(Err(Error::InvalidValue));
+                }
+
+                let mut accepted = ChannelIds::new();
+                for chan in state.channels.iter_mut() {
+                    match chan.state {
+                        ChannelState::PeerConnecting(id) if id == req_id && chan.conn == conn.raw() => {
+                            if accepted.len() < max && accepted.push(chan.cid).is_ok() {
+                                chan.mps = mps;
+                                chan.mtu = mtu;
+                                chan.flow_control = CreditFlowControl::new(credit_flow, credits);
+                                chan.rtx_timeout = timeout;
+                                chan.state = ChannelState::Connected;
+                            } else {
+                                chan.state = ChannelState::Disconnected;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Poll::Ready(Ok((req_id, group, credits, mtu, accepted)))
             })
         })
-        .await;
+        .await?;
 
-        let mut tx = [0; 18];
-        // Respond that we accept the channel.
+        let mut tx = [0; 32];
+        if group == 0 {
+            // Respond that we accept the channel.
+            controller
+                .signal(
+                    conn,
+                    req_id,
+                    &LeCreditConnRes {
+                        mps,
+                        dcid: accepted[0],
+                        mtu,
+                        credits,
+                        result: LeCreditConnResultCode::Success as u16,
+                    },
+                    &mut tx[..],
+                )
+                .await?;
+
+            // NOTE: This code is disabled as we send the credits in the response request. For some reason the nrf-softdevice doesn't do that,
+            // so lets keep this around in case we need it.
+            // Send initial credits
+            //        let next_req_id = self.next_request_id();
+            //        controller
+            //            .signal(conn, next_req_id, &LeCreditFlowInd { cid, credits }, &mut tx[..])
+            //            .await?;
+            //
+            return Ok(accepted);
+        }
+
+        // Refused channels are reported with a destination channel id of 0, in request order.
+        let mut dcids = accepted.clone();
+        while dcids.len() < group as usize && dcids.push(0).is_ok() {}
+        let result = if accepted.len() < group as usize {
+            LeCreditConnResultCode::NoResources
+        } else {
+            LeCreditConnResultCode::Success
+        };
         controller
-            .signal(
+            .signal_encoded(
                 conn,
                 req_id,
-                &LeCreditConnRes {
-                    mps,
-                    dcid: cid,
+                &CreditConnRes {
                     mtu,
+                    mps,
                     credits,
-                    result: LeCreditConnResultCode::Success,
+                    result,
+                    dcids,
                 },
                 &mut tx[..],
             )
             .await?;
-
-        // NOTE: This code is disabled as we send the credits in the response request. For some reason the nrf-softdevice doesn't do that,
-        // so lets keep this around in case we need it.
-        // Send initial credits
-        //        let next_req_id = self.next_request_id();
-        //        controller
-        //            .signal(conn, next_req_id, &LeCreditFlowInd { cid, credits }, &mut tx[..])
-        //            .await?;
-        //
-        Ok(cid)
+        Ok(accepted)
     }
 
     pub(crate) async fn create<T: Controller>(
@@ -233,8 +542,10 @@ impl<
         mtu: u16,
         credit_flow: CreditFlowPolicy,
         initial_credits: Option<u16>,
+        timeout: Duration,
         controller: &HciController<'_, T>,
     ) -> Result<u16, AdapterError<T::Error>> {
+        check_rtx_timeout(timeout)?;
         let req_id = self.next_request_id();
         let mut credits = 0;
         let mut cid: u16 = 0;
@@ -243,10 +554,14 @@ impl<
         // Allocate space for our new channel.
         self.alloc(|storage| {
             cid = storage.cid;
-            credits = initial_credits.unwrap_or(self.pool.min_available(AllocId::from_channel(storage.cid)) as u16);
+            credits = initial_credits.unwrap_or(self.initial_credits(credit_flow, storage.cid));
+            storage.conn = conn.raw();
+            storage.psm = psm;
+            storage.ecfc_group = 0;
             storage.mps = mps;
             storage.mtu = mtu;
             storage.flow_control = CreditFlowControl::new(credit_flow, credits);
+            storage.rtx_timeout = timeout;
 
             storage.state = ChannelState::Connecting(req_id);
         })?;
@@ -260,28 +575,33 @@ impl<
             mtu,
             credits,
         };
-        controller.signal(conn, req_id, &command, &mut tx[..]).await?;
+        let result: Result<(), AdapterError<T::Error>> = async {
+            controller.signal(conn, req_id, &command, &mut tx[..]).await?;
 
-        // Wait until a response is accepted.
-        poll_fn(|cx| {
-            self.state.lock(|state| {
-                let mut state = state.borrow_mut();
-                for storage in state.channels.iter_mut() {
-                    match storage.state {
-                        ChannelState::Disconnecting if storage.conn == conn.raw() && storage.cid == cid => {
-                            return Poll::Ready(Err(Error::Disconnected));
-                        }
-                        ChannelState::Connected if storage.conn == conn.raw() && storage.cid == cid => {
-                            return Poll::Ready(Ok(()));
-                        }
-                        _ => {}
+            // Wait until a response is accepted, or give up when the signalling timer expires.
+            let response = poll_fn(|cx| {
+                self.state.lock(|state| {
+                    let mut state = state.borrow_mut();
+                    let result = state.connect_result(conn, req_id, &[cid]);
+                    if result.is_pending() {
+                        state.create_waker.register(cx.waker());
                     }
+                    result
+                })
+            });
+            match with_timeout(timeout, response).await {
+                Ok(result) => {
+                    result?;
+                    Ok(())
                 }
-                state.create_waker.register(cx.waker());
-                Poll::Pending
-            })
-        })
-        .await?;
+                Err(_) => Err(Error::Timeout.into()),
+            }
+        }
+        .await;
+        if result.is_err() {
+            self.release_pending(&[cid], req_id);
+        }
+        result?;
 
         // NOTE: This code is disabled as we send the credits in the response request. For some reason the nrf-softdevice doesn't do that,
         // so lets keep this around in case we need it.
@@ -293,127 +613,876 @@ impl<
         Ok(cid)
     }
 
-    /// Dispatch an incoming L2CAP packet to the appropriate channel.
-    pub(crate) async fn dispatch(&self, header: L2capHeader, packet: Packet<'d>) -> Result<(), Error> {
-        if header.channel < BASE_ID {
-            return Err(Error::InvalidChannelId);
-        }
-
-        let chan = (header.channel - BASE_ID) as usize;
-        if chan > self.inbound.len() {
-            return Err(Error::InvalidChannelId);
+    /// Open up to five channels using a single enhanced credit based connection request.
+    ///
+    /// Returns the channels accepted by the peer, which may be fewer than requested. If the peer
+    /// refuses all of them, the result code of the response is returned as [`Error::ChannelRefused`].
+    ///
+    /// The MTU and MPS must be at least [`L2CAP_ECFC_MIN_MTU`], otherwise [`Error::InvalidValue`] is returned.
+    pub(crate) async fn create_enhanced<T: Controller>(
+        &self,
+        conn: ConnHandle,
+        psm: u16,
+        count: usize,
+        mtu: u16,
+        credit_flow: CreditFlowPolicy,
+        initial_credits: Option<u16>,
+        timeout: Duration,
+        controller: &HciController<'_, T>,
+    ) -> Result<ChannelIds, AdapterError<T::Error>> {
+        let mps = self.pool.mtu() as u16 - 4;
+        if count == 0 || count > L2CAP_ECFC_MAX_CHANNELS || mtu < L2CAP_ECFC_MIN_MTU || mps < L2CAP_ECFC_MIN_MTU {
+            return Err(Error::InvalidValue.into());
         }
-        trace!("[l2cap] inbound data packet for {}", header.channel);
+        check_rtx_timeout(timeout)?;
+        let req_id = self.next_request_id();
 
-        self.state.lock(|state| {
+        // Allocate space for all channels of the request, or none of them.
+        let (scids, credits) = self.state.lock(|state| {
             let mut state = state.borrow_mut();
+            let free = state.channels.iter().filter(|storage| storage.is_free()).count();
+            if free < count {
+                return Err(Error::NoChannelAvailable);
+            }
+
+            let mut scids = ChannelIds::new();
+            let mut credits = None;
             for (idx, storage) in state.channels.iter_mut().enumerate() {
-                match storage.state {
-                    ChannelState::Connected if header.channel == storage.cid => {
-                        if storage.flow_control.available() == 0 {
-                            return Err(Error::OutOfMemory);
-                        }
-                        storage.flow_control.received(1);
-                    }
-                    _ => {}
+                if scids.len() == count {
+                    break;
+                }
+                if storage.is_free() {
+                    let cid: u16 = BASE_ID + idx as u16;
+                    let credits = *credits
+                        .get_or_insert_with(|| initial_credits.unwrap_or(self.initial_credits(credit_flow, cid)));
+                    *storage = ChannelStorage::DISCONNECTED;
+                    storage.cid = cid;
+                    storage.conn = conn.raw();
+                    storage.psm = psm;
+                    storage.ecfc_group = count as u8;
+                    storage.mps = mps;
+                    storage.mtu = mtu;
+                    storage.flow_control = CreditFlowControl::new(credit_flow, credits);
+                    storage.rtx_timeout = timeout;
+                    storage.state = ChannelState::Connecting(req_id);
+                    let _ = scids.push(cid);
                 }
             }
-            Ok(())
+            Ok((scids, credits.unwrap_or(0)))
         })?;
 
-        self.inbound[chan]
-            .send(Some(Pdu::new(packet, header.length as usize)))
-            .await;
-        Ok(())
-    }
-
-    /// Handle incoming L2CAP signal
-    pub(crate) async fn signal(&self, conn: ConnHandle, data: &[u8]) -> Result<(), Error> {
-        let (header, data) = L2capSignalHeader::from_hci_bytes(data)?;
-        trace!("[l2cap] inbound signal code {:?}", header.code);
-        match header.code {
-            L2capSignalCode::LeCreditConnReq => {
-                let req = LeCreditConnReq::from_hci_bytes_complete(data)?;
-                self.handle_connect_request(conn, header.identifier, &req)
-            }
-            L2capSignalCode::LeCreditConnRes => {
-                let res = LeCreditConnRes::from_hci_bytes_complete(data)?;
-                self.handle_connect_response(conn, header.identifier, &res)
-            }
-            L2capSignalCode::LeCreditFlowInd => {
-                let req = LeCreditFlowInd::from_hci_bytes_complete(data)?;
-                self.handle_credit_flow(&req)?;
-                Ok(())
-            }
-            L2capSignalCode::CommandRejectRes => {
-                let (reject, _) = CommandRejectRes::from_hci_bytes(data)?;
-                warn!("Rejected: {:?}", reject);
-                Ok(())
-            }
-            L2capSignalCode::DisconnectionReq => {
-                let req = DisconnectionReq::from_hci_bytes_complete(data)?;
-                self.disconnect(req.dcid)?;
-                Ok(())
-            }
-            L2capSignalCode::DisconnectionRes => {
-                let res = DisconnectionRes::from_hci_bytes_complete(data)?;
-                self.handle_disconnect_response(&res)
-            }
-            _ => Err(Error::NotSupported),
-        }
-    }
-
-    fn handle_connect_request(&self, conn: ConnHandle, identifier: u8, req: &LeCreditConnReq) -> Result<(), Error> {
-        self.alloc(|storage| {
-            storage.conn = conn.raw();
-            storage.psm = req.psm;
-            storage.peer_cid = req.scid;
-            storage.peer_credits = req.credits;
-            storage.mps = req.mps;
-            storage.mtu = req.mtu;
-            storage.state = ChannelState::PeerConnecting(identifier);
-        })?;
-        self.state.lock(|state| {
-            state.borrow_mut().accept_waker.wake();
-        });
-        Ok(())
-    }
+        let mut tx = [0; 32];
+        let command = CreditConnReq {
+            psm,
+            mtu,
+            mps,
+            credits,
+            scids: scids.clone(),
+        };
+        let result: Result<ChannelIds, AdapterError<T::Error>> = async {
+            controller.signal_encoded(conn, req_id, &command, &mut tx[..]).await?;
 
-    fn handle_connect_response(&self, conn: ConnHandle, identifier: u8, res: &LeCreditConnRes) -> Result<(), Error> {
-        match res.result {
-            LeCreditConnResultCode::Success => {
-                // Must be a response of a previous request which should already by allocated a channel for
+            // Wait until a response is accepted, or give up when the signalling timer expires.
+            let response = poll_fn(|cx| {
                 self.state.lock(|state| {
                     let mut state = state.borrow_mut();
-                    for storage in state.channels.iter_mut() {
-                        match storage.state {
-                            ChannelState::Connecting(req_id) if identifier == req_id && conn.raw() == storage.conn => {
-                                storage.peer_cid = res.dcid;
-                                storage.peer_credits = res.credits;
-                                storage.mps = storage.mps.min(res.mps);
-                                storage.mtu = storage.mtu.min(res.mtu);
-                                storage.state = ChannelState::Connected;
-                                state.create_waker.wake();
-                                return Ok(());
-                            }
-                            _ => {}
-                        }
+                    let result = state.connect_result(conn, req_id, &scids);
+                    if result.is_pending() {
+                        state.create_waker.register(cx.waker());
                     }
-                    Err(Error::NotFound)
+                    result
                 })
-            }
-            other => {
-                warn!("Channel open request failed: {:?}", other);
-                Err(Error::NotSupported)
+            });
+            match with_timeout(timeout, response).await {
+                Ok(result) => Ok(result?),
+                Err(_) => Err(Error::Timeout.into()),
             }
         }
+        .await;
+        if result.is_err() {
+            self.release_pending(&scids, req_id);
+        }
+        result
     }
 
-    fn handle_credit_flow(&self, req: &LeCreditFlowInd) -> Result<(), Error> {
-        self.state.lock(|state| {
-            let mut state = state.borrow_mut();
-            for (idx, storage) in state.channels.iter_mut().enumerate() {
-                match storage.state {
+    /// Change the MTU and MPS used to receive data on a set of enhanced credit based channels.
+    ///
+    /// The MTU may only grow, and the MPS may only shrink when reconfiguring a single channel.
+    pub(crate) async fn reconfigure<T: Controller>(
+        &self,
+        cids: &[u16],
+        mtu: u16,
+        mps: u16,
+        controller: &HciController<'_, T>,
+    ) -> Result<(), AdapterError<T::Error>> {
+        if cids.is_empty()
+            || cids.len() > L2CAP_ECFC_MAX_CHANNELS
+            || mtu < L2CAP_ECFC_MIN_MTU
+            || mps < L2CAP_ECFC_MIN_MTU
+            || mps > self.pool.mtu() as u16 - 4
+        {
+            return Err(Error::InvalidValue.into());
+        }
+        let req_id = self.next_request_id();
+
+        let (conn, timeout) = self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            if state.reconfigure.is_some() {
+                return Err(Error::Busy);
+            }
+            let mut conn = None;
+            let mut timeout = L2CAP_RTX_TIMEOUT_MIN;
+            for cid in cids {
+                let storage = state
+                    .channels
+                    .iter()
+                    .find(|storage| storage.cid == *cid && storage.state == ChannelState::Connected)
+                    .ok_or(Error::NotFound)?;
+                if storage.ecfc_group == 0 || *conn.get_or_insert(storage.conn) != storage.conn {
+                    return Err(Error::InvalidValue);
+                }
+                if mtu < storage.mtu || (cids.len() > 1 && mps < storage.mps) {
+                    return Err(Error::InvalidValue);
+                }
+                timeout = timeout.max(storage.rtx_timeout);
+            }
+            state.reconfigure = Some((req_id, None));
+            Ok((ConnHandle::new(conn.unwrap_or(0)), timeout))
+        })?;
+
+        let result: Result<(), AdapterError<T::Error>> = async {
+            let mut tx = [0; 32];
+            let mut dcids = ChannelIds::new();
+            for cid in cids {
+                let _ = dcids.push(*cid);
+            }
+            controller
+                .signal_encoded(conn, req_id, &CreditConnReconfigReq { mtu, mps, dcids }, &mut tx[..])
+                .await?;
+
+            let response = poll_fn(|cx| {
+                self.state.lock(|state| {
+                    let mut state = state.borrow_mut();
+                    for cid in cids {
+                        if !state
+                            .channels
+                            .iter()
+                            .any(|storage| storage.cid == *cid && storage.state == ChannelState::Connected)
+                        {
+                            return Poll::Ready(Err(Error::Disconnected));
+                        }
+                    }
+                    match state.reconfigure {
+                        Some((_, Some(result))) => Poll::Ready(Ok(result)),
+                        _ => {
+                            state.reconfigure_waker.register(cx.waker());
+                            Poll::Pending
+                        }
+                    }
+                })
+            });
+            let result = with_timeout(timeout, response).await.map_err(|_| Error::Timeout)??;
+
+            match result {
+                CreditConnReconfigResultCode::Success => {
+                    self.state.lock(|state| {
+                        let mut state = state.borrow_mut();
+                        for storage in state.channels.iter_mut() {
+                            if storage.state == ChannelState::Connected && cids.contains(&storage.cid) {
+                                storage.mtu = mtu;
+                                storage.mps = mps;
+                            }
+                        }
+                    });
+                    Ok(())
+                }
+                other => {
+                    warn!("[l2cap] channel reconfiguration failed: {:?}", other);
+                    Err(Error::NotSupported.into())
+                }
+            }
+        }
+        .await;
+
+        self.state.lock(|state| {
+            state.borrow_mut().reconfigure = None;
+        });
+        result
+    }
+
+    /// Ask the central to update the connection parameters.
+    ///
+    /// One request may be outstanding per connection. Returns an error if the central rejected
+    /// the request, or the connection is lost before the central answers.
+    pub(crate) async fn request_conn_params<T: Controller>(
+        &self,
+        conn: ConnHandle,
+        req: &ConnParamUpdateReq,
+        timeout: Duration,
+        controller: &HciController<'_, T>,
+    ) -> Result<(), AdapterError<T::Error>> {
+        check_rtx_timeout(timeout)?;
+        let req_id = self.next_request_id();
+        self.start_conn_param_update(conn, req_id)?;
+
+        let result: Result<u16, AdapterError<T::Error>> = async {
+            let mut tx = [0; 16];
+            controller.signal(conn, req_id, req, &mut tx[..]).await?;
+            let response = poll_fn(|cx| {
+                self.state.lock(|state| {
+                    let mut state = state.borrow_mut();
+                    let result = state.conn_param_update_result(conn, req_id);
+                    if result.is_pending() {
+                        state.conn_param_update_waker.register(cx.waker());
+                    }
+                    result
+                })
+            });
+            match with_timeout(timeout, response).await {
+                Ok(result) => Ok(result?),
+                Err(_) => Err(Error::Timeout.into()),
+            }
+        }
+        .await;
+
+        self.state.lock(|state| {
+            state
+                .borrow_mut()
+                .conn_param_updates
+                .retain(|update| update.conn != conn.raw() || update.req_id != req_id);
+        });
+        match result? {
+            CONN_PARAM_UPDATE_ACCEPTED => Ok(()),
+            _ => Err(Error::NotSupported.into()),
+        }
+    }
+
+    fn start_conn_param_update(&self, conn: ConnHandle, req_id: u8) -> Result<(), Error> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            if state.conn_param_updates.iter().any(|update| update.conn == conn.raw()) {
+                return Err(Error::Busy);
+            }
+            state
+                .conn_param_updates
+                .push(ConnParamUpdate {
+                    conn: conn.raw(),
+                    req_id,
+                    result: None,
+                })
+                .map_err(|_| Error::Busy)
+        })
+    }
+
+    /// Wait for a connection parameter update request from a peripheral.
+    ///
+    /// Requests received while no task is waiting here are rejected.
+    pub(crate) async fn conn_param_request(&self) -> (ConnHandle, u8, ConnParamUpdateReq) {
+        self.state.lock(|state| {
+            state.borrow_mut().conn_param_handlers += 1;
+        });
+        let _handler = ConnParamHandler { state: &self.state };
+        self.conn_param_requests.receive().await
+    }
+
+    // Queue a connection parameter update request for the application, returns false if it must be rejected.
+    fn queue_conn_param_request(&self, conn: ConnHandle, identifier: u8, req: ConnParamUpdateReq) -> bool {
+        let handled = self.state.lock(|state| state.borrow().conn_param_handlers > 0);
+        handled && req.within_limits() && self.conn_param_requests.try_send((conn, identifier, req)).is_ok()
+    }
+
+    /// Dispatch an incoming L2CAP packet to the appropriate channel.
+    ///
+    /// Each K-frame is checked against the SDU being received on the channel. The channel is
+    /// closed if the peer exceeds the MTU or MPS we advertised, or sends without credits.
+    pub(crate) async fn dispatch<T: Controller>(
+        &self,
+        conn: ConnHandle,
+        header: L2capHeader,
+        packet: Packet<'d>,
+        controller: &HciController<'_, T>,
+    ) -> Result<(), Error> {
+        if header.channel < BASE_ID {
+            return Err(Error::InvalidChannelId);
+        }
+
+        let chan = (header.channel - BASE_ID) as usize;
+        if chan >= self.inbound.len() {
+            return Err(Error::InvalidChannelId);
+        }
+        trace!("[l2cap] inbound data packet for {}", header.channel);
+
+        let checked = self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let storage = &mut state.channels[chan];
+            if storage.state != ChannelState::Connected || storage.cid != header.channel || storage.conn != conn.raw() {
+                return Err(Error::InvalidChannelId);
+            }
+            if storage.flow_control.available() == 0 {
+                return Ok(Err(Error::OutOfCredits));
+            }
+            storage.flow_control.received(1);
+            let payload = packet
+                .as_ref()
+                .get(..header.length as usize)
+                .ok_or(Error::InsufficientSpace)?;
+            Ok(storage.receive_kframe(payload))
+        })?;
+        if let Err(e) = checked {
+            warn!("[l2cap] closing channel {}: {:?}", header.channel, e);
+            self.abort(conn, header.channel, controller);
+            return Err(e);
+        }
+
+        self.inbound[chan]
+            .send(Some(Pdu::new(packet, header.length as usize)))
+            .await;
+        Ok(())
+    }
+
+    /// Close a channel on which the peer violated the protocol.
+    ///
+    /// Pending receivers and senders observe the channel as closed, and the peer is asked to disconnect.
+    /// The channel is released when the peer answers, or may be reused once the RTX timeout expired.
+    pub(crate) fn abort<T: Controller>(&self, conn: ConnHandle, cid: u16, controller: &HciController<'_, T>) {
+        let req_id = self.next_request_id();
+        let peer_cid = self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            for (idx, storage) in state.channels.iter_mut().enumerate() {
+                if storage.cid == cid && storage.conn == conn.raw() && storage.state == ChannelState::Connected {
+                    storage.state = ChannelState::DisconnectRequested(req_id);
+                    storage.expires = Some(Instant::now() + storage.rtx_timeout);
+                    let peer_cid = storage.peer_cid;
+                    let _ = self.inbound[idx].try_send(None);
+                    state.credit_wakers[idx].wake();
+                    state.closed_wakers[idx].wake();
+                    return Some(peer_cid);
+                }
+            }
+            None
+        });
+
+        if let Some(peer_cid) = peer_cid {
+            let mut tx = [0; 16];
+            let req = DisconnectionReq {
+                dcid: peer_cid,
+                scid: cid,
+            };
+            if controller.try_signal(conn, req_id, &req, &mut tx[..]).is_err() {
+                warn!("[l2cap] unable to send disconnection request");
+            }
+        }
+    }
+
+    /// Handle incoming L2CAP signal
+    pub(crate) async fn signal<T: Controller>(
+        &self,
+        conn: ConnHandle,
+        data: &[u8],
+        role: LeConnRole,
+        security: SecurityLevel,
+        controller: &HciController<'_, T>,
+    ) -> Result<(), Error> {
+        let (header, payload) = match L2capSignalHeader::from_hci_bytes(data) {
+            Ok(result) => result,
+            Err(e) => {
+                // Unknown command codes still carry an identifier we can reject.
+                if let Some(identifier) = data.get(1).filter(|identifier| **identifier != 0) {
+                    self.reject(conn, *identifier, CommandRejectReason::NotUnderstood, controller);
+                }
+                return Err(e.into());
+            }
+        };
+        trace!("[l2cap] inbound signal code {:?}", header.code);
+
+        // Commands with identifier 0 are invalid and silently discarded.
+        if header.identifier == 0 {
+            return Err(Error::InvalidValue);
+        }
+        if data.len() > L2CAP_SIGNAL_MTU as usize {
+            self.reject(
+                conn,
+                header.identifier,
+                CommandRejectReason::MtuExceeded(L2CAP_SIGNAL_MTU),
+                controller,
+            );
+            return Err(Error::InsufficientSpace);
+        }
+
+        let data = payload;
+        match header.code {
+            L2capSignalCode::LeCreditConnReq => {
+                let req = LeCreditConnReq::from_hci_bytes_complete(data)?;
+                if let Err(result) = self.handle_connect_request(conn, header.identifier, &req, security) {
+                    warn!("[l2cap] refusing channel for psm {}: {:?}", req.psm, result);
+                    let mut tx = [0; 24];
+                    let res = LeCreditConnRes {
+                        dcid: 0,
+                        mtu: 0,
+                        mps: 0,
+                        credits: 0,
+                        result: result as u16,
+                    };
+                    if controller
+                        .try_signal(conn, header.identifier, &res, &mut tx[..])
+                        .is_err()
+                    {
+                        warn!("[l2cap] unable to send connection response");
+                    }
+                }
+                Ok(())
+            }
+            L2capSignalCode::LeCreditConnRes => {
+                let res = LeCreditConnRes::from_hci_bytes_complete(data)?;
+                self.handle_connect_response(conn, header.identifier, &res)
+            }
+            L2capSignalCode::CreditConnReq => {
+                let req = CreditConnReq::decode(data)?;
+                if let Err(result) = self.handle_credit_conn_request(conn, header.identifier, &req, security) {
+                    warn!("[l2cap] refusing channels for psm {}: {:?}", req.psm, result);
+                    let mut dcids = ChannelIds::new();
+                    while dcids.len() < req.scids.len() && dcids.push(0).is_ok() {}
+                    let mut tx = [0; 32];
+                    let res = CreditConnRes {
+                        mtu: 0,
+                        mps: 0,
+                        credits: 0,
+                        result,
+                        dcids,
+                    };
+                    if controller
+                        .try_signal_encoded(conn, header.identifier, &res, &mut tx[..])
+                        .is_err()
+                    {
+                        warn!("[l2cap] unable to send connection response");
+                    }
+                }
+                Ok(())
+            }
+            L2capSignalCode::CreditConnRes => {
+                let res = CreditConnRes::decode(data)?;
+                self.handle_credit_conn_response(conn, header.identifier, &res)
+            }
+            L2capSignalCode::CreditConnReconfigReq => {
+                let req = CreditConnReconfigReq::decode(data)?;
+                let result = self.handle_reconfigure_request(conn, &req);
+                let mut tx = [0; 16];
+                // Sending from the inbound path must not wait for ACL buffers to be released.
+                if controller
+                    .try_signal_encoded(conn, header.identifier, &CreditConnReconfigRes { result }, &mut tx[..])
+                    .is_err()
+                {
+                    warn!("[l2cap] unable to send reconfigure response");
+                }
+                Ok(())
+            }
+            L2capSignalCode::CreditConnReconfigRes => {
+                let res = CreditConnReconfigRes::decode(data)?;
+                self.handle_reconfigure_response(header.identifier, &res)
+            }
+            L2capSignalCode::LeCreditFlowInd => {
+                let req = LeCreditFlowInd::from_hci_bytes_complete(data)?;
+                self.handle_credit_flow(&req)?;
+                Ok(())
+            }
+            L2capSignalCode::CommandRejectRes => {
+                let reject = CommandRejectRes::decode(data)?;
+                warn!("Rejected: {:?}", reject);
+                self.handle_command_reject(conn, header.identifier);
+                Ok(())
+            }
+            L2capSignalCode::ConnParamUpdateReq => {
+                let req = ConnParamUpdateReq::from_hci_bytes_complete(data)?;
+                // Only a central may be asked to update the connection parameters.
+                if !matches!(role, LeConnRole::Central) {
+                    self.reject(conn, header.identifier, CommandRejectReason::NotUnderstood, controller);
+                    return Ok(());
+                }
+                // Requests are answered by the application, refuse those nobody is waiting for.
+                if !self.queue_conn_param_request(conn, header.identifier, req) {
+                    let mut tx = [0; 16];
+                    let res = ConnParamUpdateRes {
+                        result: CONN_PARAM_UPDATE_REJECTED,
+                    };
+                    if controller
+                        .try_signal(conn, header.identifier, &res, &mut tx[..])
+                        .is_err()
+                    {
+                        warn!("[l2cap] unable to send connection parameter update response");
+                    }
+                }
+                Ok(())
+            }
+            L2capSignalCode::ConnParamUpdateRes => {
+                let res = ConnParamUpdateRes::from_hci_bytes_complete(data)?;
+                self.handle_conn_param_update_response(conn, header.identifier, &res)
+            }
+            L2capSignalCode::DisconnectionReq => {
+                let req = DisconnectionReq::from_hci_bytes_complete(data)?;
+                if self.disconnect(req.dcid).is_err() {
+                    let reason = CommandRejectReason::InvalidCid {
+                        local: req.dcid,
+                        remote: req.scid,
+                    };
+                    self.reject(conn, header.identifier, reason, controller);
+                    return Ok(());
+                }
+                // The response echoes the channel ids of the request.
+                let mut tx = [0; 16];
+                let res = DisconnectionRes {
+                    dcid: req.dcid,
+                    scid: req.scid,
+                };
+                if controller
+                    .try_signal(conn, header.identifier, &res, &mut tx[..])
+                    .is_err()
+                {
+                    warn!("[l2cap] unable to send disconnection response");
+                }
+                Ok(())
+            }
+            L2capSignalCode::DisconnectionRes => {
+                let res = DisconnectionRes::from_hci_bytes_complete(data)?;
+                self.handle_disconnect_response(conn, header.identifier, &res)
+            }
+            L2capSignalCode::EchoReq
+            | L2capSignalCode::InformationReq
+            | L2capSignalCode::ConnectionReq
+            | L2capSignalCode::ConfigurationReq => {
+                self.reject(conn, header.identifier, CommandRejectReason::NotUnderstood, controller);
+                Ok(())
+            }
+            // Responses to requests we never send are ignored.
+            _ => Err(Error::NotSupported),
+        }
+    }
+
+    fn reject<T: Controller>(
+        &self,
+        conn: ConnHandle,
+        identifier: u8,
+        reason: CommandRejectReason,
+        controller: &HciController<'_, T>,
+    ) {
+        let mut tx = [0; 16];
+        // Sending from the inbound path must not wait for ACL buffers to be released.
+        if controller
+            .try_signal_encoded(conn, identifier, &CommandRejectRes { reason }, &mut tx[..])
+            .is_err()
+        {
+            warn!("[l2cap] unable to send command reject");
+        }
+    }
+
+    // Fail any pending request the peer rejected.
+    fn handle_command_reject(&self, conn: ConnHandle, identifier: u8) {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            for storage in state.channels.iter_mut() {
+                match storage.state {
+                    ChannelState::Connecting(req_id) if req_id == identifier && storage.conn == conn.raw() => {
+                        storage.state = ChannelState::Disconnected;
+                    }
+                    // A peer that does not know the channel has nothing left to close.
+                    ChannelState::DisconnectRequested(req_id) if req_id == identifier && storage.conn == conn.raw() => {
+                        storage.state = ChannelState::Disconnected;
+                    }
+                    _ => {}
+                }
+            }
+            state.create_waker.wake();
+            state.disconnect_waker.wake();
+
+            if let Some((req_id, None)) = state.reconfigure {
+                if req_id == identifier {
+                    state.reconfigure = Some((req_id, Some(CreditConnReconfigResultCode::UnacceptableParameters)));
+                    state.reconfigure_waker.wake();
+                }
+            }
+
+            // A central not understanding the request will not apply the parameters either.
+            for update in state.conn_param_updates.iter_mut() {
+                if update.conn == conn.raw() && update.req_id == identifier && update.result.is_none() {
+                    update.result = Some(CONN_PARAM_UPDATE_REJECTED);
+                }
+            }
+            state.conn_param_update_waker.wake();
+        });
+    }
+
+    // Check a channel request from the peer against the specification and our allocated channels.
+    //
+    // Returns the number of channels that can still be opened for the PSM.
+    fn check_connect_request(
+        &self,
+        conn: ConnHandle,
+        psm: u16,
+        scids: &[u16],
+        security: SecurityLevel,
+    ) -> Result<usize, LeCreditConnResultCode> {
+        self.state.lock(|state| {
+            let state = state.borrow();
+            let Some(registration) = state.psms.iter().find(|registration| registration.psm == psm) else {
+                return Err(LeCreditConnResultCode::SpsmNotSupported);
+            };
+            if security < registration.security {
+                return Err(match registration.security {
+                    SecurityLevel::EncryptedAuthenticated => LeCreditConnResultCode::InsufficientAuthentication,
+                    _ => LeCreditConnResultCode::InsufficientEncryption,
+                });
+            }
+            for (i, scid) in scids.iter().enumerate() {
+                if !(L2CAP_CID_DYN_START..=L2CAP_CID_DYN_END).contains(scid) || scids[..i].contains(scid) {
+                    return Err(LeCreditConnResultCode::InvalidSourceId);
+                }
+                if state.channels.iter().any(|storage| {
+                    storage.state != ChannelState::Disconnected
+                        && storage.conn == conn.raw()
+                        && storage.peer_cid == *scid
+                }) {
+                    return Err(LeCreditConnResultCode::ScidAlreadyAllocated);
+                }
+            }
+
+            let open = state
+                .channels
+                .iter()
+                .filter(|storage| storage.state != ChannelState::Disconnected && storage.psm == psm)
+                .count();
+            match registration.max_channels.saturating_sub(open) {
+                0 => Err(LeCreditConnResultCode::NoResources),
+                available => Ok(available),
+            }
+        })
+    }
+
+    fn handle_connect_request(
+        &self,
+        conn: ConnHandle,
+        identifier: u8,
+        req: &LeCreditConnReq,
+        security: SecurityLevel,
+    ) -> Result<(), LeCreditConnResultCode> {
+        self.check_connect_request(conn, req.psm, &[req.scid], security)?;
+        if req.mtu < 23 || req.mps < 23 {
+            return Err(LeCreditConnResultCode::UnacceptableParameters);
+        }
+        self.alloc(|storage| {
+            storage.conn = conn.raw();
+            storage.psm = req.psm;
+            storage.peer_cid = req.scid;
+            storage.peer_credits = req.credits;
+            storage.peer_mps = req.mps;
+            storage.peer_mtu = req.mtu;
+            storage.ecfc_group = 0;
+            storage.state = ChannelState::PeerConnecting(identifier);
+        })
+        .map_err(|_| LeCreditConnResultCode::NoResources)?;
+        self.state.lock(|state| {
+            state.borrow_mut().accept_waker.wake();
+        });
+        Ok(())
+    }
+
+    fn handle_connect_response(&self, conn: ConnHandle, identifier: u8, res: &LeCreditConnRes) -> Result<(), Error> {
+        match res.result_code() {
+            Ok(LeCreditConnResultCode::Success) => {
+                // Must be a response of a previous request which should already by allocated a channel for
+                self.state.lock(|state| {
+                    let mut state = state.borrow_mut();
+                    for storage in state.channels.iter_mut() {
+                        match storage.state {
+                            ChannelState::Connecting(req_id) if identifier == req_id && conn.raw() == storage.conn => {
+                                storage.peer_cid = res.dcid;
+                                storage.peer_credits = res.credits;
+                                storage.peer_mps = res.mps;
+                                storage.peer_mtu = res.mtu;
+                                storage.state = ChannelState::Connected;
+                                state.create_waker.wake();
+                                return Ok(());
+                            }
+                            _ => {}
+                        }
+                    }
+                    Err(Error::NotFound)
+                })
+            }
+            other => {
+                warn!("Channel open request failed: {:?}", res.result);
+                self.state.lock(|state| {
+                    let mut state = state.borrow_mut();
+                    for storage in state.channels.iter_mut() {
+                        match storage.state {
+                            ChannelState::Connecting(req_id) if identifier == req_id && conn.raw() == storage.conn => {
+                                storage.state = ChannelState::Refused(req_id, other.ok());
+                            }
+                            _ => {}
+                        }
+                    }
+                    state.create_waker.wake();
+                });
+                Ok(())
+            }
+        }
+    }
+
+    fn handle_credit_conn_request(
+        &self,
+        conn: ConnHandle,
+        identifier: u8,
+        req: &CreditConnReq,
+        security: SecurityLevel,
+    ) -> Result<(), LeCreditConnResultCode> {
+        let available = self.check_connect_request(conn, req.psm, &req.scids, security)?;
+        if req.mtu < L2CAP_ECFC_MIN_MTU || req.mps < L2CAP_ECFC_MIN_MTU {
+            return Err(LeCreditConnResultCode::UnacceptableParameters);
+        }
+        // Channels that cannot be allocated are refused when the request is accepted.
+        let mut allocated = 0;
+        for scid in req.scids.iter().take(available) {
+            let result = self.alloc(|storage| {
+                storage.conn = conn.raw();
+                storage.psm = req.psm;
+                storage.peer_cid = *scid;
+                storage.peer_credits = req.credits;
+                storage.peer_mps = req.mps;
+                storage.peer_mtu = req.mtu;
+                storage.ecfc_group = req.scids.len() as u8;
+                storage.state = ChannelState::PeerConnecting(identifier);
+            });
+            if result.is_err() {
+                break;
+            }
+            allocated += 1;
+        }
+        if allocated == 0 {
+            return Err(LeCreditConnResultCode::NoResources);
+        }
+        self.state.lock(|state| {
+            state.borrow_mut().accept_waker.wake();
+        });
+        Ok(())
+    }
+
+    fn handle_credit_conn_response(&self, conn: ConnHandle, identifier: u8, res: &CreditConnRes) -> Result<(), Error> {
+        // Channels refused by a successful response are refused for lack of resources.
+        let refusal = match res.result {
+            LeCreditConnResultCode::Success => LeCreditConnResultCode::NoResources,
+            other => {
+                warn!("Channel open request failed: {:?}", other);
+                other
+            }
+        };
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let mut dcids = res.dcids.iter();
+            let mut found = false;
+            for storage in state.channels.iter_mut() {
+                match storage.state {
+                    ChannelState::Connecting(req_id) if identifier == req_id && conn.raw() == storage.conn => {
+                        found = true;
+                        match dcids.next() {
+                            Some(dcid) if *dcid != 0 => {
+                                storage.peer_cid = *dcid;
+                                storage.peer_credits = res.credits;
+                                storage.peer_mps = res.mps;
+                                storage.peer_mtu = res.mtu;
+                                storage.state = ChannelState::Connected;
+                            }
+                            _ => {
+                                storage.state = ChannelState::Refused(req_id, Some(refusal));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if found {
+                state.create_waker.wake();
+                Ok(())
+            } else {
+                Err(Error::NotFound)
+            }
+        })
+    }
+
+    fn handle_reconfigure_request(
+        &self,
+        conn: ConnHandle,
+        req: &CreditConnReconfigReq,
+    ) -> CreditConnReconfigResultCode {
+        if req.mtu < L2CAP_ECFC_MIN_MTU || req.mps < L2CAP_ECFC_MIN_MTU {
+            return CreditConnReconfigResultCode::UnacceptableParameters;
+        }
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            for dcid in req.dcids.iter() {
+                let Some(storage) = state.channels.iter().find(|storage| {
+                    storage.state == ChannelState::Connected
+                        && storage.conn == conn.raw()
+                        && storage.peer_cid == *dcid
+                        && storage.ecfc_group > 0
+                }) else {
+                    return CreditConnReconfigResultCode::InvalidDestinationCid;
+                };
+                if req.mtu < storage.peer_mtu {
+                    return CreditConnReconfigResultCode::MtuReductionNotAllowed;
+                }
+                if req.dcids.len() > 1 && req.mps < storage.peer_mps {
+                    return CreditConnReconfigResultCode::MpsReductionNotAllowed;
+                }
+            }
+            for storage in state.channels.iter_mut() {
+                if storage.state == ChannelState::Connected
+                    && storage.conn == conn.raw()
+                    && req.dcids.contains(&storage.peer_cid)
+                {
+                    storage.peer_mtu = req.mtu;
+                    storage.peer_mps = req.mps;
+                }
+            }
+            CreditConnReconfigResultCode::Success
+        })
+    }
+
+    fn handle_reconfigure_response(&self, identifier: u8, res: &CreditConnReconfigRes) -> Result<(), Error> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            match state.reconfigure {
+                Some((req_id, None)) if req_id == identifier => {
+                    state.reconfigure = Some((req_id, Some(res.result)));
+                    state.reconfigure_waker.wake();
+                    Ok(())
+                }
+                _ => Err(Error::NotFound),
+            }
+        })
+    }
+
+    fn handle_conn_param_update_response(
+        &self,
+        conn: ConnHandle,
+        identifier: u8,
+        res: &ConnParamUpdateRes,
+    ) -> Result<(), Error> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let update = state
+                .conn_param_updates
+                .iter_mut()
+                .find(|update| update.conn == conn.raw() && update.req_id == identifier && update.result.is_none())
+                .ok_or(Error::NotFound)?;
+            update.result = Some(res.result);
+            state.conn_param_update_waker.wake();
+            Ok(())
+        })
+    }
+
+    fn handle_credit_flow(&self, req: &LeCreditFlowInd) -> Result<(), Error> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            for (idx, storage) in state.channels.iter_mut().enumerate() {
+                match storage.state {
                     ChannelState::Connected if storage.peer_cid == req.cid => {
                         storage.peer_credits += req.credits;
                         state.credit_wakers[idx].wake();
@@ -426,38 +1495,35 @@ impl<
         })
     }
 
-    fn handle_disconnect_response(&self, res: &DisconnectionRes) -> Result<(), Error> {
-        let cid = res.dcid;
+    fn handle_disconnect_response(
+        &self,
+        conn: ConnHandle,
+        identifier: u8,
+        res: &DisconnectionRes,
+    ) -> Result<(), Error> {
+        // The source channel id of the response is our end of the channel.
         self.state.lock(|state| {
             let mut state = state.borrow_mut();
             for storage in state.channels.iter_mut() {
                 match storage.state {
-                    ChannelState::Disconnecting if cid == storage.cid => {
+                    ChannelState::DisconnectRequested(req_id)
+                        if req_id == identifier && storage.conn == conn.raw() && storage.cid == res.scid =>
+                    {
                         storage.state = ChannelState::Disconnected;
-                        break;
-                    }
-                    ChannelState::PeerConnecting(_) if cid == storage.cid => {
-                        storage.state = ChannelState::Disconnecting;
-                        break;
-                    }
-                    ChannelState::Connecting(_) if cid == storage.cid => {
-                        storage.state = ChannelState::Disconnecting;
-                        break;
-                    }
-                    ChannelState::Connected if cid == storage.cid => {
-                        storage.state = ChannelState::Disconnecting;
-                        break;
+                        state.disconnect_waker.wake();
+                        return Ok(());
                     }
                     _ => {}
                 }
             }
-            Ok(())
+            Err(Error::NotFound)
         })
     }
 
     /// Receive data on a given channel and copy it into the buffer.
     ///
-    /// The length provided buffer slice must be equal or greater to the agreed MTU.
+    /// The length provided buffer slice must be equal or greater to the agreed MTU, otherwise
+    /// `Error::InsufficientSpace` is returned before any data is consumed.
     pub(crate) async fn receive<T: Controller>(
         &self,
         cid: u16,
@@ -465,26 +1531,32 @@ impl<
         hci: &HciController<'_, T>,
     ) -> Result<usize, AdapterError<T::Error>> {
         let idx = self.connected_channel_index(cid)?;
+        // K-frames are checked against our MTU when dispatched, so any SDU fits such a buffer.
+        let mtu = self.state.lock(|state| state.borrow().channels[idx].mtu);
+        if buf.len() < mtu as usize {
+            return Err(Error::InsufficientSpace.into());
+        }
 
         let mut n_received = 1;
-        let packet = self.receive_pdu(cid, idx, hci).await?;
+        let packet = self.receive_pdu(cid, idx).await?;
         let len = packet.len;
 
         let mut r = ReadCursor::new(packet.as_ref());
-        let remaining: u16 = r.read()?;
+        let sdu_len: u16 = r.read()?;
 
         let data = r.remaining();
         let to_copy = data.len().min(buf.len());
         buf[..to_copy].copy_from_slice(&data[..to_copy]);
         let mut pos = to_copy;
 
-        let mut remaining = remaining as usize - data.len();
+        // K-frames are checked against the SDU length when dispatched.
+        let mut remaining = sdu_len as usize - data.len();
 
         self.flow_control(cid, hci, packet.packet).await?;
 
         // We have some k-frames to reassemble
         while remaining > 0 {
-            let packet = self.receive_pdu(cid, idx, hci).await?;
+            let packet = self.receive_pdu(cid, idx).await?;
             n_received += 1;
             let to_copy = packet.len.min(buf.len() - pos);
             if to_copy > 0 {
@@ -495,33 +1567,152 @@ impl<
             self.flow_control(cid, hci, packet.packet).await?;
         }
 
+        if pos < sdu_len as usize {
+            warn!(
+                "[l2cap] discarded {} bytes of SDU not fitting the buffer",
+                sdu_len as usize - pos
+            );
+            return Err(Error::InsufficientSpace.into());
+        }
         Ok(pos)
     }
 
+    /// Receive an SDU on a given channel without copying it out of the packet pool.
+    ///
+    /// SDUs spanning several K-frames are gathered into the packet of the first one, and must fit in
+    /// a single pool packet. The credit for the returned packet is released once it is dropped, and
+    /// sent to the peer by a later call while waiting for the next SDU.
+    pub(crate) async fn receive_packet<T: Controller>(
+        &self,
+        cid: u16,
+        hci: &HciController<'_, T>,
+    ) -> Result<L2capPacket<'_, 'd>, AdapterError<T::Error>> {
+        let idx = self.connected_channel_index(cid)?;
+
+        // Credits for packets released while waiting are granted without waiting for the next SDU.
+        let mut first = loop {
+            self.grant_credits(cid, 0, hci, &mut [0; 16]).await?;
+            let released = poll_fn(|cx| self.poll_released(idx, cx));
+            if let Either::First(pdu) = select(self.receive_pdu(cid, idx), released).await {
+                break pdu?;
+            }
+        };
+
+        let mut r = ReadCursor::new(first.as_ref());
+        let sdu_len: u16 = r.read()?;
+        let end = 2 + sdu_len as usize;
+        let fits = end <= first.packet.len();
+
+        // K-frames are checked against the SDU length when dispatched.
+        let mut pos = first.len;
+        while pos < end {
+            let packet = self.receive_pdu(cid, idx).await?;
+            if fits {
+                first.packet.as_mut()[pos..pos + packet.len].copy_from_slice(packet.as_ref());
+            }
+            pos += packet.len;
+            self.flow_control(cid, hci, packet.packet).await?;
+        }
+
+        if !fits {
+            warn!("[l2cap] discarded SDU of {} bytes not fitting a packet", sdu_len);
+            self.flow_control(cid, hci, first.packet).await?;
+            return Err(Error::InsufficientSpace.into());
+        }
+        Ok(L2capPacket {
+            packet: first.packet,
+            offset: 2,
+            len: sdu_len as usize,
+            release: Some((cid, self as &dyn DynamicChannelManager)),
+        })
+    }
+
+    fn poll_released(&self, idx: usize, cx: &mut Context<'_>) -> Poll<()> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            if state.channels[idx].flow_control.ready() {
+                Poll::Ready(())
+            } else {
+                state.release_wakers[idx].register(cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+
+    /// Allocate a packet from the pool for sending on a given channel.
+    ///
+    /// Packets for sending are not counted against the packets of the channel, which back the
+    /// credits granted to the peer.
+    pub(crate) fn alloc_packet(&self, cid: u16) -> Result<L2capPacket<'_, 'd>, Error> {
+        self.connected_channel_index(cid)?;
+        let packet = self.pool.alloc(SEND_ID).ok_or(Error::OutOfMemory)?;
+        let len = packet.len() - L2capPacket::HEADROOM;
+        Ok(L2capPacket {
+            packet,
+            offset: L2capPacket::HEADROOM,
+            len,
+            release: None,
+        })
+    }
+
+    /// Send an SDU held in a pool packet over a given l2cap channel.
+    ///
+    /// The headers are written in front of the SDU, avoiding a copy when it fits a single K-frame.
+    /// Other SDUs are segmented as with [`ChannelManager::send`].
+    pub(crate) async fn send_packet<T: Controller>(
+        &self,
+        cid: u16,
+        mut packet: L2capPacket<'_, 'd>,
+        hci: &HciController<'_, T>,
+    ) -> Result<(), AdapterError<T::Error>> {
+        let (conn, mps, peer_cid) = self.connected_channel_params(cid)?;
+        if packet.offset < L2capPacket::HEADROOM || packet.len + 2 > mps as usize {
+            return self.send(cid, packet.as_ref(), hci).await;
+        }
+
+        self.request_to_send(cid, 1).await?;
+
+        let start = packet.offset - L2capPacket::HEADROOM;
+        let end = packet.offset + packet.len;
+        let len = packet.len as u16;
+        let buf = packet.packet.as_mut();
+        let mut w = WriteCursor::new(&mut buf[start..end]);
+        w.write(2 + len)?;
+        w.write(peer_cid)?;
+        w.write(len)?;
+
+        if let Err(e) = hci.send(conn, &buf[start..end]).await {
+            warn!("Replenishing credits for 1 unsent packet");
+            self.abort_send(cid, 1)?;
+            return Err(e);
+        }
+        self.sent(cid, len as usize);
+        Ok(())
+    }
+
     // Return the array index for a given active channel
-    fn connected_channel_index(&self, cid: u16) -> Result<usize, Error> {
+    pub(crate) fn connected_channel_index(&self, cid: u16) -> Result<usize, Error> {
         self.state.lock(|state| {
             let state = state.borrow();
             for (idx, chan) in state.channels.iter().enumerate() {
-                if chan.cid == cid && chan.state == ChannelState::Connected {
-                    return Ok(idx);
+                match chan.state {
+                    ChannelState::Connected if chan.cid == cid => return Ok(idx),
+                    ChannelState::Disconnecting | ChannelState::DisconnectRequested(_) if chan.cid == cid => {
+                        return Err(Error::ChannelClosed);
+                    }
+                    _ => {}
                 }
             }
             Err(Error::NotFound)
         })
     }
 
-    async fn receive_pdu<T: Controller>(
-        &self,
-        cid: u16,
-        idx: usize,
-        hci: &HciController<'_, T>,
-    ) -> Result<Pdu<'d>, AdapterError<T::Error>> {
+    pub(crate) async fn receive_pdu(&self, cid: u16, idx: usize) -> Result<Pdu<'d>, Error> {
         match self.inbound[idx].receive().await {
             Some(pdu) => Ok(pdu),
             None => {
-                self.confirm_disconnected(cid, hci).await?;
-                Err(Error::ChannelClosed.into())
+                self.confirm_disconnected(cid);
+                Err(Error::ChannelClosed)
             }
         }
     }
@@ -542,7 +1733,7 @@ impl<
         // The number of packets we'll need to send for this payload
         let n_packets = 1 + ((buf.len() as u16).saturating_sub(mps - 2)).div_ceil(mps);
 
-        poll_fn(|cx| self.poll_request_to_send(cid, n_packets, Some(cx))).await?;
+        self.request_to_send(cid, n_packets).await?;
 
         let mut unsent = n_packets;
         let result: Result<(), AdapterError<T::Error>> = async {
@@ -567,6 +1758,8 @@ impl<
         if unsent > 0 {
             warn!("Replenishing credits for {} unsent packets", unsent);
             self.abort_send(cid, unsent)?;
+        } else {
+            self.sent(cid, buf.len());
         }
         result
     }
@@ -592,6 +1785,7 @@ impl<
             Poll::Ready(res) => res?,
             Poll::Pending => {
                 warn!("l2cap: not enough credits for {} packets", n_packets);
+                self.stalled(cid);
                 return Err(Error::Busy.into());
             }
         }
@@ -619,6 +1813,8 @@ impl<
         if unsent > 0 {
             warn!("Replenishing credits for {} unsent packets", unsent);
             self.abort_send(cid, unsent)?;
+        } else {
+            self.sent(cid, buf.len());
         }
         result
     }
@@ -629,7 +1825,10 @@ impl<
             for chan in state.channels.iter() {
                 match chan.state {
                     ChannelState::Connected if chan.cid == cid => {
-                        return Ok((ConnHandle::new(chan.conn), chan.mps, chan.peer_cid));
+                        return Ok((ConnHandle::new(chan.conn), chan.mps.min(chan.peer_mps), chan.peer_cid));
+                    }
+                    ChannelState::Disconnecting | ChannelState::DisconnectRequested(_) if chan.cid == cid => {
+                        return Err(Error::ChannelClosed);
                     }
                     _ => {}
                 }
@@ -638,20 +1837,109 @@ impl<
         })
     }
 
+    /// Connection a channel belongs to, unless the channel has been released.
+    pub(crate) fn channel_conn(&self, cid: u16) -> Result<ConnHandle, Error> {
+        self.state.lock(|state| {
+            let state = state.borrow();
+            for chan in state.channels.iter() {
+                if chan.cid == cid && chan.state != ChannelState::Disconnected {
+                    return Ok(ConnHandle::new(chan.conn));
+                }
+            }
+            Err(Error::NotFound)
+        })
+    }
+
+    /// Largest SDU the peer accepts on a connected channel.
+    pub(crate) fn peer_mtu(&self, cid: u16) -> Result<u16, Error> {
+        self.state.lock(|state| {
+            let state = state.borrow();
+            for chan in state.channels.iter() {
+                if chan.cid == cid && chan.state == ChannelState::Connected {
+                    return Ok(chan.peer_mtu);
+                }
+            }
+            Err(Error::ChannelClosed)
+        })
+    }
+
+    /// Wait until the peer has granted credits for at least one K-frame on a channel.
+    pub(crate) fn poll_credits(&self, cid: u16, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            for (idx, storage) in state.channels.iter().enumerate() {
+                if storage.cid == cid && storage.state == ChannelState::Connected {
+                    if storage.peer_credits > 0 {
+                        return Poll::Ready(Ok(()));
+                    }
+                    state.credit_wakers[idx].register(cx.waker());
+                    return Poll::Pending;
+                }
+            }
+            Poll::Ready(Err(Error::ChannelClosed))
+        })
+    }
+
+    /// Connection and ATT_MTU of a connected channel used as an ATT bearer.
+    ///
+    /// The ATT_MTU of an Enhanced ATT bearer is the L2CAP MTU of the channel, limiting the size of the PDUs sent
+    /// to the peer.
+    pub(crate) fn bearer_params(&self, cid: u16) -> Result<(ConnHandle, u16), Error> {
+        self.state.lock(|state| {
+            let state = state.borrow();
+            for chan in state.channels.iter() {
+                if chan.cid == cid && chan.state == ChannelState::Connected {
+                    return Ok((ConnHandle::new(chan.conn), chan.peer_mtu));
+                }
+            }
+            Err(Error::NotFound)
+        })
+    }
+
+    // Credits granted when opening a channel, unless configured otherwise.
+    fn initial_credits(&self, policy: CreditFlowPolicy, cid: u16) -> u16 {
+        let Ok(id) = AllocId::from_channel(cid) else {
+            return 0;
+        };
+        let credits = match policy {
+            CreditFlowPolicy::Adaptive => self.pool.available(id).min(L2CAP_RXQ),
+            _ => self.pool.min_available(id),
+        };
+        credits.min(u16::MAX as usize) as u16
+    }
+
     // Check the current state of flow control and send flow indications if
     // our policy says so.
-    async fn flow_control<T: Controller>(
+    pub(crate) async fn flow_control<T: Controller>(
         &self,
         cid: u16,
         hci: &HciController<'_, T>,
         mut packet: Packet<'_>,
+    ) -> Result<(), AdapterError<T::Error>> {
+        // Reuse packet buffer for signalling data to save the extra TX buffer
+        self.grant_credits(cid, 1, hci, packet.as_mut()).await
+    }
+
+    // Account for released packets and send the credits our policy allows.
+    async fn grant_credits<T: Controller>(
+        &self,
+        cid: u16,
+        released: u16,
+        hci: &HciController<'_, T>,
+        buf: &mut [u8],
     ) -> Result<(), AdapterError<T::Error>> {
         let (conn, credits) = self.state.lock(|state| {
             let mut state = state.borrow_mut();
-            for storage in state.channels.iter_mut() {
+            for (idx, storage) in state.channels.iter_mut().enumerate() {
                 match storage.state {
                     ChannelState::Connected if cid == storage.cid => {
-                        return Ok((storage.conn, storage.flow_control.process()));
+                        storage.flow_control.released(released);
+                        let pressure = PoolPressure {
+                            free: self.pool.available(AllocId::dynamic(idx)),
+                            queued: self.inbound[idx].len(),
+                            capacity: L2CAP_RXQ,
+                        };
+                        return Ok((storage.conn, storage.flow_control.process(pressure)));
                     }
                     _ => {}
                 }
@@ -662,49 +1950,28 @@ impl<
         if let Some(credits) = credits {
             let identifier = self.next_request_id();
             let signal = LeCreditFlowInd { cid, credits };
-
-            // Reuse packet buffer for signalling data to save the extra TX buffer
-            hci.signal(ConnHandle::new(conn), identifier, &signal, packet.as_mut())
-                .await?;
+            hci.signal(ConnHandle::new(conn), identifier, &signal, buf).await?;
         }
         Ok(())
     }
 
-    async fn confirm_disconnected<T: Controller>(
-        &self,
-        cid: u16,
-        hci: &HciController<'_, T>,
-    ) -> Result<(), AdapterError<T::Error>> {
-        let (handle, dcid, scid) = self.state.lock(|state| {
+    // Release a channel closed by the peer once its receiver has observed the disconnect.
+    fn confirm_disconnected(&self, cid: u16) {
+        self.state.lock(|state| {
             let mut state = state.borrow_mut();
             for storage in state.channels.iter_mut() {
                 match storage.state {
                     ChannelState::Disconnecting if cid == storage.cid => {
                         storage.state = ChannelState::Disconnected;
-                        let scid = storage.cid;
-                        let dcid = storage.peer_cid;
-                        let handle = storage.conn;
                         storage.cid = 0;
                         storage.peer_cid = 0;
                         storage.conn = 0;
-                        return Ok((handle, dcid, scid));
+                        return;
                     }
                     _ => {}
                 }
             }
-            Err(Error::NotFound)
-        })?;
-
-        let identifier = self.next_request_id();
-        let mut tx = [0; 18];
-        hci.signal(
-            ConnHandle::new(handle),
-            identifier,
-            &DisconnectionRes { dcid, scid },
-            &mut tx[..],
-        )
-        .await?;
-        Ok(())
+        })
     }
 
     fn abort_send(&self, cid: u16, credits: u16) -> Result<(), Error> {
@@ -723,30 +1990,192 @@ impl<
         })
     }
 
+    // Take credits for sending, counting a stall if we have to wait for the peer to grant more.
+    async fn request_to_send(&self, cid: u16, credits: u16) -> Result<(), Error> {
+        match self.poll_request_to_send(cid, credits, None) {
+            Poll::Ready(res) => res,
+            Poll::Pending => {
+                self.stalled(cid);
+                poll_fn(|cx| self.poll_request_to_send(cid, credits, Some(cx))).await
+            }
+        }
+    }
+
+    fn stalled(&self, cid: u16) {
+        let _ = self.with_channel(cid, |storage| {
+            storage.stats.credit_stalls = storage.stats.credit_stalls.wrapping_add(1);
+        });
+    }
+
+    fn sent(&self, cid: u16, len: usize) {
+        let _ = self.with_channel(cid, |storage| {
+            storage.stats.sdus_sent = storage.stats.sdus_sent.wrapping_add(1);
+            storage.stats.bytes_sent = storage.stats.bytes_sent.wrapping_add(len as u32);
+        });
+    }
+
+    // Access a channel that has not been released yet.
+    fn with_channel<R, F: FnOnce(&mut ChannelStorage) -> R>(&self, cid: u16, f: F) -> Result<R, Error> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            for storage in state.channels.iter_mut() {
+                if storage.cid == cid && storage.state != ChannelState::Disconnected {
+                    return Ok(f(storage));
+                }
+            }
+            Err(Error::NotFound)
+        })
+    }
+
+    /// MTU and MPS negotiated for a connected channel, the smallest of ours and the peer's.
+    pub(crate) fn negotiated(&self, cid: u16) -> Result<(u16, u16), Error> {
+        self.with_channel(cid, |storage| match storage.state {
+            ChannelState::Connected => Ok((storage.mtu.min(storage.peer_mtu), storage.mps.min(storage.peer_mps))),
+            _ => Err(Error::ChannelClosed),
+        })?
+    }
+
+    /// Credits the peer has granted us for sending on a connected channel.
+    pub(crate) fn peer_credits(&self, cid: u16) -> Result<u16, Error> {
+        self.with_channel(cid, |storage| match storage.state {
+            ChannelState::Connected => Ok(storage.peer_credits),
+            _ => Err(Error::ChannelClosed),
+        })?
+    }
+
+    /// Counters of a channel, available until the channel is released.
+    pub(crate) fn stats(&self, cid: u16) -> Result<L2capChannelStats, Error> {
+        self.with_channel(cid, |storage| storage.stats)
+    }
+
+    /// Wait until a channel is no longer connected.
+    pub(crate) fn poll_disconnected(&self, cid: u16, cx: &mut Context<'_>) -> Poll<()> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            for (idx, storage) in state.channels.iter().enumerate() {
+                if storage.cid == cid && storage.state == ChannelState::Connected {
+                    state.closed_wakers[idx].register(cx.waker());
+                    return Poll::Pending;
+                }
+            }
+            Poll::Ready(())
+        })
+    }
+
     fn poll_request_to_send(&self, cid: u16, credits: u16, cx: Option<&mut Context<'_>>) -> Poll<Result<(), Error>> {
         self.state.lock(|state| {
             let mut state = state.borrow_mut();
             for (idx, storage) in state.channels.iter_mut().enumerate() {
-                match storage.state {
-                    ChannelState::Connected if cid == storage.cid => {
-                        if credits <= storage.peer_credits {
-                            storage.peer_credits -= credits;
-                            return Poll::Ready(Ok(()));
-                        } else {
-                            if let Some(cx) = cx {
-                                state.credit_wakers[idx].register(cx.waker());
-                            }
-                            return Poll::Pending;
-                        }
-                    }
-                    _ => {}
+                match storage.state {
+                    ChannelState::Connected if cid == storage.cid => {
+                        if credits <= storage.peer_credits {
+                            storage.peer_credits -= credits;
+                            return Poll::Ready(Ok(()));
+                        } else {
+                            if let Some(cx) = cx {
+                                state.credit_wakers[idx].register(cx.waker());
+                            }
+                            return Poll::Pending;
+                        }
+                    }
+                    ChannelState::Disconnecting | ChannelState::DisconnectRequested(_) if cid == storage.cid => {
+                        return Poll::Ready(Err(Error::ChannelClosed));
+                    }
+                    _ => {}
+                }
+            }
+            Poll::Ready(Err(Error::NotFound))
+        })
+    }
+}
+
+pub(crate) trait DynamicChannelManager {
+    fn release(&self, cid: u16);
+}
+
+impl<
+        'd,
+        M: RawMutex,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    > DynamicChannelManager for ChannelManager<'d, M, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>
+{
+    // Credit the peer for a packet handed out by `receive_packet`.
+    fn release(&self, cid: u16) {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            for (idx, storage) in state.channels.iter_mut().enumerate() {
+                if storage.cid == cid && storage.state == ChannelState::Connected {
+                    storage.flow_control.released(1);
+                    state.release_wakers[idx].wake();
+                    return;
                 }
             }
-            Poll::Ready(Err(Error::NotFound))
         })
     }
 }
 
+/// An SDU held in a packet of the adapter packet pool.
+///
+/// Packets received on a channel release their credit when dropped. Released credits are sent to
+/// the peer, according to the channel [`CreditFlowPolicy`], while a task is receiving on the channel.
+pub struct L2capPacket<'a, 'd> {
+    packet: Packet<'d>,
+    offset: usize,
+    len: usize,
+    release: Option<(u16, &'a dyn DynamicChannelManager)>,
+}
+
+impl<'a, 'd> L2capPacket<'a, 'd> {
+    // Room for the basic L2CAP header and the SDU length.
+    const HEADROOM: usize = 6;
+
+    /// Length of the SDU.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the SDU is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Shorten the SDU, typically after filling a packet allocated for sending.
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+}
+
+impl<'a, 'd> AsRef<[u8]> for L2capPacket<'a, 'd> {
+    fn as_ref(&self) -> &[u8] {
+        &self.packet.as_ref()[self.offset..self.offset + self.len]
+    }
+}
+
+impl<'a, 'd> AsMut<[u8]> for L2capPacket<'a, 'd> {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.packet.as_mut()[self.offset..self.offset + self.len]
+    }
+}
+
+impl<'a, 'd> Drop for L2capPacket<'a, 'd> {
+    fn drop(&mut self) {
+        if let Some((cid, channels)) = self.release.take() {
+            channels.release(cid);
+        }
+    }
+}
+
+fn check_rtx_timeout(timeout: Duration) -> Result<(), Error> {
+    if (L2CAP_RTX_TIMEOUT_MIN..=L2CAP_RTX_TIMEOUT_MAX).contains(&timeout) {
+        Ok(())
+    } else {
+        Err(Error::InvalidValue)
+    }
+}
+
 fn encode(data: &[u8], packet: &mut [u8], peer_cid: u16, header: Option<u16>) -> Result<usize, Error> {
     let mut w = WriteCursor::new(packet);
     if header.is_some() {
@@ -772,9 +2201,21 @@ pub struct ChannelStorage {
     mps: u16,
     mtu: u16,
     flow_control: CreditFlowControl,
+    /// Number of channels in the enhanced credit based request that opened this channel, 0 for
+    /// LE credit based channels.
+    ecfc_group: u8,
+    /// Bytes of the SDU being received that are still expected in following K-frames.
+    sdu_remaining: u16,
+    stats: L2capChannelStats,
+    /// Time to wait for the peer to answer signalling requests for this channel.
+    rtx_timeout: Duration,
+    /// Time after which a disconnection request that nobody is waiting for is given up.
+    expires: Option<Instant>,
 
     peer_cid: u16,
     peer_credits: u16,
+    peer_mps: u16,
+    peer_mtu: u16,
 }
 
 impl ChannelStorage {
@@ -787,9 +2228,60 @@ impl ChannelStorage {
         psm: 0,
 
         flow_control: CreditFlowControl::new(CreditFlowPolicy::Every(1), 0),
+        ecfc_group: 0,
+        sdu_remaining: 0,
+        stats: L2capChannelStats::new(),
+        rtx_timeout: L2CAP_RTX_TIMEOUT_DEFAULT,
+        expires: None,
         peer_cid: 0,
         peer_credits: 0,
+        peer_mps: 0,
+        peer_mtu: 0,
     };
+
+    // Whether the slot can be allocated to a new channel.
+    fn is_free(&self) -> bool {
+        match self.state {
+            ChannelState::Disconnected => true,
+            // The peer never answered the disconnection request sent when aborting the channel.
+            ChannelState::DisconnectRequested(_) => self.expires.is_some_and(|expires| expires <= Instant::now()),
+            _ => false,
+        }
+    }
+
+    // Check a K-frame payload against the SDU being received, using the MTU and MPS we advertised.
+    fn receive_kframe(&mut self, payload: &[u8]) -> Result<(), Error> {
+        if payload.len() > self.mps as usize {
+            return Err(Error::MtuExceeded);
+        }
+        if self.sdu_remaining == 0 {
+            // The first K-frame of an SDU starts with the SDU length.
+            let [lo, hi, ..] = payload else {
+                return Err(Error::InvalidValue);
+            };
+            let sdu_len = u16::from_le_bytes([*lo, *hi]);
+            if sdu_len > self.mtu {
+                return Err(Error::MtuExceeded);
+            }
+            let len = payload.len() as u16 - 2;
+            if len > sdu_len {
+                return Err(Error::InvalidValue);
+            }
+            self.sdu_remaining = sdu_len - len;
+            self.stats.bytes_received = self.stats.bytes_received.wrapping_add(len as u32);
+        } else {
+            let len = payload.len() as u16;
+            if len > self.sdu_remaining {
+                return Err(Error::InvalidValue);
+            }
+            self.sdu_remaining -= len;
+            self.stats.bytes_received = self.stats.bytes_received.wrapping_add(len as u32);
+        }
+        if self.sdu_remaining == 0 {
+            self.stats.sdus_received = self.stats.sdus_received.wrapping_add(1);
+        }
+        Ok(())
+    }
 }
 
 #[derive(PartialEq)]
@@ -799,6 +2291,12 @@ pub enum ChannelState {
     PeerConnecting(u8),
     Connected,
     Disconnecting,
+    /// Waiting for the peer to confirm a disconnection request with the given identifier.
+    DisconnectRequested(u8),
+    /// Refused by the peer in response to the request with the given identifier.
+    ///
+    /// The result code is `None` if it is unknown to this host.
+    Refused(u8, Option<LeCreditConnResultCode>),
 }
 
 /// Control how credits are issued by the receiving end.
@@ -809,6 +2307,10 @@ pub enum CreditFlowPolicy {
     Every(u16),
     /// Issue credits when below a threshold
     MinThreshold(u16),
+    /// Issue credits for the packets free for the channel in the packet pool, according to its
+    /// [`PacketQos`](crate::PacketQos), and in its receive queue. Stop issuing them while received
+    /// packets are not drained by the application.
+    Adaptive,
 }
 
 impl Default for CreditFlowPolicy {
@@ -817,12 +2319,85 @@ impl Default for CreditFlowPolicy {
     }
 }
 
+/// Traffic counters of an L2CAP channel.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct L2capChannelStats {
+    /// Number of SDUs received from the peer.
+    pub sdus_received: u32,
+    /// Number of SDU bytes received from the peer.
+    pub bytes_received: u32,
+    /// Number of SDUs sent to the peer.
+    pub sdus_sent: u32,
+    /// Number of SDU bytes sent to the peer.
+    pub bytes_sent: u32,
+    /// Number of times sending had to wait for the peer to grant credits.
+    pub credit_stalls: u32,
+}
+
+impl L2capChannelStats {
+    const fn new() -> Self {
+        Self {
+            sdus_received: 0,
+            bytes_received: 0,
+            sdus_sent: 0,
+            bytes_sent: 0,
+            credit_stalls: 0,
+        }
+    }
+}
+
+/// Registration of a PSM that peers may open channels to.
+///
+/// Connection requests for PSMs that are not registered are refused.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PsmRegistration {
+    /// Protocol/Service Multiplexer of the service.
+    pub psm: u16,
+    /// Maximum SDU size accepted on channels of this PSM.
+    pub mtu: u16,
+    /// Security level the link must have before channels are accepted.
+    pub security: SecurityLevel,
+    /// Maximum number of channels open at the same time for this PSM.
+    pub max_channels: usize,
+}
+
+impl PsmRegistration {
+    /// Registration with no security requirement and no limit on the number of channels.
+    pub const fn new(psm: u16, mtu: u16) -> Self {
+        Self {
+            psm,
+            mtu,
+            security: SecurityLevel::NoEncryption,
+            max_channels: usize::MAX,
+        }
+    }
+}
+
+// Packet pool usage of a channel, used by the adaptive credit flow policy.
+struct PoolPressure {
+    // Packets that can still be allocated for the channel.
+    free: usize,
+    // Packets received and waiting for the application.
+    queued: usize,
+    // Capacity of the receive queue.
+    capacity: usize,
+}
+
+impl PoolPressure {
+    // The application is falling behind once half of the receive queue is in use.
+    fn backlogged(&self) -> bool {
+        self.queued > 0 && self.queued * 2 >= self.capacity
+    }
+}
+
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub(crate) struct CreditFlowControl {
     policy: CreditFlowPolicy,
     credits: u16,
-    received: u16,
+    released: u16,
 }
 
 impl CreditFlowControl {
@@ -830,7 +2405,7 @@ impl CreditFlowControl {
         Self {
             policy,
             credits: initial_credits,
-            received: 0,
+            released: 0,
         }
     }
     fn available(&self) -> u16 {
@@ -839,31 +2414,1178 @@ impl CreditFlowControl {
 
     fn received(&mut self, n: u16) {
         self.credits = self.credits.saturating_sub(n);
-        self.received = self.received.saturating_add(n);
     }
 
-    fn process(&mut self) -> Option<u16> {
+    // Packets are credited back once the application is done with them.
+    fn released(&mut self, n: u16) {
+        self.released = self.released.saturating_add(n);
+    }
+
+    fn ready(&self) -> bool {
+        if self.released == 0 {
+            return false;
+        }
         match self.policy {
-            CreditFlowPolicy::Every(count) => {
-                if self.received >= count {
-                    let amount = self.received;
-                    self.received = 0;
-                    self.credits += amount;
-                    Some(amount)
-                } else {
-                    None
+            CreditFlowPolicy::Every(count) => self.released >= count,
+            CreditFlowPolicy::MinThreshold(threshold) => self.credits < threshold,
+            CreditFlowPolicy::Adaptive => true,
+        }
+    }
+
+    fn process(&mut self, pressure: PoolPressure) -> Option<u16> {
+        if !self.ready() {
+            return None;
+        }
+        let amount = match self.policy {
+            CreditFlowPolicy::Adaptive => {
+                self.released = 0;
+                if pressure.backlogged() {
+                    return None;
                 }
-            }
-            CreditFlowPolicy::MinThreshold(threshold) => {
-                if self.credits < threshold {
-                    let amount = self.received;
-                    self.received = 0;
-                    self.credits += amount;
-                    Some(amount)
+                // Every outstanding credit may consume a free packet and a slot of the receive
+                // queue. A channel with nothing outstanding is always given one credit so that it
+                // cannot stall.
+                let room = pressure.free.min(pressure.capacity.saturating_sub(pressure.queued));
+                let amount = (room.min(u16::MAX as usize) as u16).saturating_sub(self.credits);
+                if amount == 0 && self.credits == 0 && pressure.queued == 0 {
+                    1
                 } else {
-                    None
+                    amount
                 }
             }
+            _ => core::mem::take(&mut self.released),
+        };
+        if amount == 0 {
+            return None;
+        }
+        self.credits = self.credits.saturating_add(amount);
+        Some(amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_controller::MockController;
+    use crate::packet_pool::{AllocId, PacketPool, Qos};
+
+    type Manager<'d> = ChannelManager<'d, NoopRawMutex, 4, 64, 2, 2>;
+
+    // Pool, controller and connection shared by the tests.
+    struct Fixture {
+        pool: PacketPool<NoopRawMutex, 64, 8, 4>,
+        controller: MockController,
+        conn: ConnHandle,
+    }
+
+    impl Fixture {
+        fn new(qos: Qos) -> Self {
+            Self {
+                pool: PacketPool::new(qos),
+                controller: MockController::new(),
+                conn: ConnHandle::new(1),
+            }
+        }
+
+        // A manager allocating from the pool of the fixture.
+        fn parts(&self) -> (Manager<'_>, &MockController, ConnHandle) {
+            (ChannelManager::new(&self.pool), &self.controller, self.conn)
+        }
+    }
+
+    const PSM: u16 = 0x0081;
+
+    fn has_state(manager: &Manager<'_>, cid: u16, expected: ChannelState) -> bool {
+        manager.state.lock(|state| {
+            state
+                .borrow()
+                .channels
+                .iter()
+                .any(|storage| storage.cid == cid && storage.state == expected)
+        })
+    }
+
+    // Allocate channels the way an outgoing enhanced credit based request does.
+    fn connecting(manager: &Manager<'_>, conn: ConnHandle, req_id: u8, count: usize) -> ChannelIds {
+        let mut cids = ChannelIds::new();
+        for _ in 0..count {
+            manager
+                .alloc(|storage| {
+                    storage.conn = conn.raw();
+                    storage.psm = PSM;
+                    storage.ecfc_group = count as u8;
+                    storage.state = ChannelState::Connecting(req_id);
+                    let _ = cids.push(storage.cid);
+                })
+                .unwrap();
+        }
+        cids
+    }
+
+    fn credit_conn_req(scids: &[u16]) -> CreditConnReq {
+        CreditConnReq {
+            psm: PSM,
+            mtu: 128,
+            mps: 64,
+            credits: 4,
+            scids: ChannelIds::from_slice(scids).unwrap(),
+        }
+    }
+
+    fn connect_result(
+        manager: &Manager<'_>,
+        conn: ConnHandle,
+        req_id: u8,
+        cids: &[u16],
+    ) -> Poll<Result<ChannelIds, Error>> {
+        manager
+            .state
+            .lock(|state| state.borrow_mut().connect_result(conn, req_id, cids))
+    }
+
+    #[test]
+    fn test_ecfc_request() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, _, conn) = fixture.parts();
+
+        let req = credit_conn_req(&[0x40, 0x41]);
+        assert!(matches!(
+            manager.handle_credit_conn_request(conn, 1, &req, SecurityLevel::NoEncryption),
+            Err(LeCreditConnResultCode::SpsmNotSupported)
+        ));
+
+        let mut registration = PsmRegistration::new(PSM, 128);
+        registration.max_channels = 3;
+        manager.register(registration).unwrap();
+
+        let mut req = credit_conn_req(&[0x40, 0x41]);
+        req.mtu = L2CAP_ECFC_MIN_MTU - 1;
+        assert!(matches!(
+            manager.handle_credit_conn_request(conn, 1, &req, SecurityLevel::NoEncryption),
+            Err(LeCreditConnResultCode::UnacceptableParameters)
+        ));
+        let req = credit_conn_req(&[0x40, 0x40]);
+        assert!(matches!(
+            manager.handle_credit_conn_request(conn, 1, &req, SecurityLevel::NoEncryption),
+            Err(LeCreditConnResultCode::InvalidSourceId)
+        ));
+
+        // Channels beyond the limit of the PSM are left out of the request.
+        let req = credit_conn_req(&[0x40, 0x41, 0x42, 0x43]);
+        assert!(manager
+            .handle_credit_conn_request(conn, 1, &req, SecurityLevel::NoEncryption)
+            .is_ok());
+        let pending = manager.state.lock(|state| {
+            state
+                .borrow()
+                .channels
+                .iter()
+                .filter(|storage| storage.state == ChannelState::PeerConnecting(1))
+                .count()
+        });
+        assert_eq!(pending, 3);
+
+        let req = credit_conn_req(&[0x40]);
+        assert!(matches!(
+            manager.handle_credit_conn_request(conn, 2, &req, SecurityLevel::NoEncryption),
+            Err(LeCreditConnResultCode::ScidAlreadyAllocated)
+        ));
+    }
+
+    #[test]
+    fn test_ecfc_request_no_resources() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, _, conn) = fixture.parts();
+        manager.register(PsmRegistration::new(PSM, 128)).unwrap();
+        connecting(&manager, conn, 1, 4);
+
+        // Refused with a result code instead of being left unanswered.
+        let req = credit_conn_req(&[0x40, 0x41]);
+        assert!(matches!(
+            manager.handle_credit_conn_request(conn, 1, &req, SecurityLevel::NoEncryption),
+            Err(LeCreditConnResultCode::NoResources)
+        ));
+    }
+
+    #[test]
+    fn test_ecfc_parameters() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, controller, conn) = fixture.parts();
+        let accept = |manager: &Manager<'_>, mtu| {
+            embassy_futures::block_on(manager.accept(
+                conn,
+                &[PSM],
+                mtu,
+                CreditFlowPolicy::Every(1),
+                Some(4),
+                2,
+                L2CAP_RTX_TIMEOUT_DEFAULT,
+                &controller.hci(),
+            ))
+        };
+
+        // Packets of 64 bytes leave an MPS of 60, which is too small for enhanced credit based channels
+        let create = embassy_futures::block_on(manager.create_enhanced(
+            conn,
+            PSM,
+            2,
+            128,
+            CreditFlowPolicy::Every(1),
+            None,
+            L2CAP_RTX_TIMEOUT_DEFAULT,
+            &controller.hci(),
+        ));
+        assert!(matches!(create, Err(AdapterError::Adapter(Error::InvalidValue))));
+        assert!(controller.take_signal().is_none());
+
+        manager.register(PsmRegistration::new(PSM, 128)).unwrap();
+        manager
+            .handle_credit_conn_request(conn, 1, &credit_conn_req(&[0x40, 0x41]), SecurityLevel::NoEncryption)
+            .unwrap();
+        assert!(matches!(
+            accept(&manager, 128),
+            Err(AdapterError::Adapter(Error::InvalidValue))
+        ));
+
+        let pool: PacketPool<NoopRawMutex, 68, 8, 4> = PacketPool::new(Qos::None);
+        let manager: Manager<'_> = ChannelManager::new(&pool);
+        manager.register(PsmRegistration::new(PSM, 128)).unwrap();
+        manager
+            .handle_credit_conn_request(conn, 1, &credit_conn_req(&[0x40, 0x41]), SecurityLevel::NoEncryption)
+            .unwrap();
+
+        // The request is left pending when accepting with an MTU which is too small
+        assert!(matches!(
+            accept(&manager, L2CAP_ECFC_MIN_MTU - 1),
+            Err(AdapterError::Adapter(Error::InvalidValue))
+        ));
+        assert!(has_state(&manager, BASE_ID, ChannelState::PeerConnecting(1)));
+        assert_eq!(accept(&manager, 128).unwrap().len(), 2);
+        assert!(controller.take_signal().is_some());
+    }
+
+    #[test]
+    fn test_ecfc_response() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, _, conn) = fixture.parts();
+        let cids = connecting(&manager, conn, 1, 3);
+        assert!(connect_result(&manager, conn, 1, &cids).is_pending());
+
+        let res = CreditConnRes {
+            mtu: 128,
+            mps: 64,
+            credits: 2,
+            result: LeCreditConnResultCode::NoResources,
+            dcids: ChannelIds::from_slice(&[0x50, 0, 0x51]).unwrap(),
+        };
+        manager.handle_credit_conn_response(conn, 1, &res).unwrap();
+        match connect_result(&manager, conn, 1, &cids) {
+            Poll::Ready(Ok(connected)) => assert_eq!(&connected[..], &[cids[0], cids[2]]),
+            _ => panic!("expected accepted channels"),
+        }
+        assert!(has_state(&manager, cids[0], ChannelState::Connected));
+        assert!(has_state(&manager, cids[1], ChannelState::Disconnected));
+        assert!(has_state(&manager, cids[2], ChannelState::Connected));
+    }
+
+    #[test]
+    fn test_ecfc_refused() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, _, conn) = fixture.parts();
+        let cids = connecting(&manager, conn, 1, 2);
+
+        let res = CreditConnRes {
+            mtu: 0,
+            mps: 0,
+            credits: 0,
+            result: LeCreditConnResultCode::InsufficientAuthentication,
+            dcids: ChannelIds::from_slice(&[0, 0]).unwrap(),
+        };
+        manager.handle_credit_conn_response(conn, 1, &res).unwrap();
+        assert!(matches!(
+            connect_result(&manager, conn, 1, &cids),
+            Poll::Ready(Err(Error::ChannelRefused(
+                LeCreditConnResultCode::InsufficientAuthentication
+            )))
+        ));
+        // Refused channels are released once the result is observed.
+        assert!(has_state(&manager, cids[0], ChannelState::Disconnected));
+        assert!(has_state(&manager, cids[1], ChannelState::Disconnected));
+
+        // A peer rejecting the command altogether does not provide a result code.
+        let cids = connecting(&manager, conn, 2, 2);
+        manager.handle_command_reject(conn, 2);
+        assert!(matches!(
+            connect_result(&manager, conn, 2, &cids),
+            Poll::Ready(Err(Error::NotSupported))
+        ));
+    }
+
+    #[test]
+    fn test_le_credit_refused() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, _, conn) = fixture.parts();
+        let cids = connecting(&manager, conn, 1, 1);
+
+        let res = LeCreditConnRes {
+            dcid: 0,
+            mtu: 0,
+            mps: 0,
+            credits: 0,
+            result: LeCreditConnResultCode::SpsmNotSupported as u16,
+        };
+        manager.handle_connect_response(conn, 1, &res).unwrap();
+        assert!(matches!(
+            connect_result(&manager, conn, 1, &cids),
+            Poll::Ready(Err(Error::ChannelRefused(LeCreditConnResultCode::SpsmNotSupported)))
+        ));
+
+        // Unknown result codes are accepted and fail the request right away.
+        let cids = connecting(&manager, conn, 2, 1);
+        let payload = [0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0x00];
+        let res = LeCreditConnRes::from_hci_bytes_complete(&payload).unwrap();
+        assert!(res.result_code().is_err());
+        manager.handle_connect_response(conn, 2, &res).unwrap();
+        assert!(matches!(
+            connect_result(&manager, conn, 2, &cids),
+            Poll::Ready(Err(Error::InvalidValue))
+        ));
+    }
+
+    fn update_req(interval_max: u16, latency: u16, timeout: u16) -> ConnParamUpdateReq {
+        ConnParamUpdateReq {
+            interval_min: 6,
+            interval_max,
+            latency,
+            timeout,
+        }
+    }
+
+    #[test]
+    fn test_conn_param_limits() {
+        assert!(update_req(40, 0, 100).within_limits());
+        assert!(!update_req(5, 0, 100).within_limits());
+        assert!(!update_req(40, 500, 3200).within_limits());
+        // 400 ms timeout against (1 + 4) * 50 ms * 2 = 500 ms.
+        assert!(!update_req(40, 4, 40).within_limits());
+        // 510 ms timeout is just above it.
+        assert!(update_req(40, 4, 51).within_limits());
+        // Exactly equal is not enough.
+        assert!(!update_req(40, 4, 50).within_limits());
+    }
+
+    #[test]
+    fn test_conn_param_request_without_handler() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, _, conn) = fixture.parts();
+
+        // Nobody is waiting for the request, so it is rejected right away.
+        assert!(!manager.queue_conn_param_request(conn, 1, update_req(40, 0, 100)));
+
+        let handler = ConnParamHandler { state: &manager.state };
+        manager.state.lock(|state| state.borrow_mut().conn_param_handlers += 1);
+        assert!(!manager.queue_conn_param_request(conn, 2, update_req(40, 4, 40)));
+        assert!(manager.queue_conn_param_request(conn, 3, update_req(40, 0, 100)));
+        // The queue holds a single request.
+        assert!(!manager.queue_conn_param_request(conn, 4, update_req(40, 0, 100)));
+
+        drop(handler);
+        assert_eq!(manager.state.lock(|state| state.borrow().conn_param_handlers), 0);
+    }
+
+    #[test]
+    fn test_conn_param_update_per_connection() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, _, first) = fixture.parts();
+        let second = ConnHandle::new(2);
+        let result = |conn, req_id| {
+            manager
+                .state
+                .lock(|state| state.borrow().conn_param_update_result(conn, req_id))
+        };
+
+        manager.start_conn_param_update(first, 1).unwrap();
+        assert!(matches!(manager.start_conn_param_update(first, 2), Err(Error::Busy)));
+        manager.start_conn_param_update(second, 3).unwrap();
+
+        // Responses are matched to the connection they arrive on.
+        let res = ConnParamUpdateRes {
+            result: CONN_PARAM_UPDATE_ACCEPTED,
+        };
+        assert!(manager.handle_conn_param_update_response(second, 1, &res).is_err());
+        manager.handle_conn_param_update_response(first, 1, &res).unwrap();
+        assert!(matches!(result(first, 1), Poll::Ready(Ok(CONN_PARAM_UPDATE_ACCEPTED))));
+        assert!(result(second, 3).is_pending());
+
+        // Losing the link fails the request instead of leaving it waiting.
+        manager.disconnected(second).unwrap();
+        assert!(matches!(result(second, 3), Poll::Ready(Err(Error::Disconnected))));
+    }
+
+    fn signal_data(code: L2capSignalCode, identifier: u8, payload: &[u8]) -> Vec<u8, 64> {
+        let mut data = Vec::new();
+        data.push(code as u8).unwrap();
+        data.push(identifier).unwrap();
+        data.extend_from_slice(&(payload.len() as u16).to_le_bytes()).unwrap();
+        data.extend_from_slice(payload).unwrap();
+        data
+    }
+
+    fn le_credit_conn_req(psm: u16, scid: u16) -> Vec<u8, 64> {
+        let mut payload: Vec<u8, 10> = Vec::new();
+        for value in [psm, scid, 64, 64, 4] {
+            payload.extend_from_slice(&value.to_le_bytes()).unwrap();
+        }
+        signal_data(L2capSignalCode::LeCreditConnReq, 1, &payload)
+    }
+
+    fn command_reject(controller: &MockController, identifier: u8) -> CommandRejectReason {
+        let (code, id, payload) = controller.take_signal().expect("command reject");
+        assert_eq!(code, L2capSignalCode::CommandRejectRes as u8);
+        assert_eq!(id, identifier);
+        CommandRejectRes::decode(&payload).unwrap().reason
+    }
+
+    #[test]
+    fn test_command_reject() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, controller, conn) = fixture.parts();
+        let signal = |data: &[u8]| {
+            embassy_futures::block_on(manager.signal(
+                conn,
+                data,
+                LeConnRole::Central,
+                SecurityLevel::NoEncryption,
+                &controller.hci(),
+            ))
+        };
+
+        // Commands of BR/EDR signalling are not understood on LE.
+        let _ = signal(&signal_data(L2capSignalCode::EchoReq, 3, &[1, 2]));
+        assert!(matches!(
+            command_reject(&controller, 3),
+            CommandRejectReason::NotUnderstood
+        ));
+
+        // Unknown command codes are rejected with their identifier.
+        assert!(signal(&[0x30, 4, 0, 0]).is_err());
+        assert!(matches!(
+            command_reject(&controller, 4),
+            CommandRejectReason::NotUnderstood
+        ));
+
+        // Commands larger than the signalling MTU report the MTU we support.
+        assert!(signal(&signal_data(L2capSignalCode::EchoReq, 5, &[0; 24])).is_err());
+        assert!(matches!(
+            command_reject(&controller, 5),
+            CommandRejectReason::MtuExceeded(L2CAP_SIGNAL_MTU)
+        ));
+
+        // Disconnecting a channel that does not exist echoes the channel ids.
+        let mut payload = [0; 4];
+        payload[..2].copy_from_slice(&0x40u16.to_le_bytes());
+        payload[2..].copy_from_slice(&0x50u16.to_le_bytes());
+        signal(&signal_data(L2capSignalCode::DisconnectionReq, 6, &payload)).unwrap();
+        assert!(matches!(
+            command_reject(&controller, 6),
+            CommandRejectReason::InvalidCid {
+                local: 0x40,
+                remote: 0x50
+            }
+        ));
+
+        // Commands with identifier 0 are discarded without an answer.
+        assert!(signal(&signal_data(L2capSignalCode::EchoReq, 0, &[])).is_err());
+        assert!(controller.take_signal().is_none());
+    }
+
+    #[test]
+    fn test_le_credit_conn_request_result() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, controller, conn) = fixture.parts();
+        let signal = |data: &[u8]| {
+            embassy_futures::block_on(manager.signal(
+                conn,
+                data,
+                LeConnRole::Peripheral,
+                SecurityLevel::NoEncryption,
+                &controller.hci(),
+            ))
+        };
+        let result = |controller: &MockController| {
+            let (code, _, payload) = controller.take_signal().expect("connection response");
+            assert_eq!(code, L2capSignalCode::LeCreditConnRes as u8);
+            LeCreditConnRes::from_hci_bytes_complete(&payload)
+                .unwrap()
+                .result_code()
+                .unwrap()
+        };
+
+        signal(&le_credit_conn_req(0x2349, 0x40)).unwrap();
+        assert_eq!(result(&controller), LeCreditConnResultCode::SpsmNotSupported);
+
+        // Any PSM may be registered, not only the dynamic range of the specification.
+        manager.register(PsmRegistration::new(0x2349, 64)).unwrap();
+        signal(&le_credit_conn_req(0x2349, 0x02)).unwrap();
+        assert_eq!(result(&controller), LeCreditConnResultCode::InvalidSourceId);
+
+        signal(&le_credit_conn_req(0x2349, 0x40)).unwrap();
+        assert!(controller.take_signal().is_none());
+        assert!(has_state(&manager, BASE_ID, ChannelState::PeerConnecting(1)));
+
+        signal(&le_credit_conn_req(0x2349, 0x40)).unwrap();
+        assert_eq!(result(&controller), LeCreditConnResultCode::ScidAlreadyAllocated);
+
+        // Every channel slot in use.
+        for scid in 0x41..0x44 {
+            signal(&le_credit_conn_req(0x2349, scid)).unwrap();
+        }
+        signal(&le_credit_conn_req(0x2349, 0x44)).unwrap();
+        assert_eq!(result(&controller), LeCreditConnResultCode::NoResources);
+    }
+
+    #[test]
+    fn test_psm_security() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, controller, conn) = fixture.parts();
+        let signal = |data: &[u8], security| {
+            embassy_futures::block_on(manager.signal(conn, data, LeConnRole::Peripheral, security, &controller.hci()))
+        };
+        let result = |controller: &MockController| {
+            let (code, _, payload) = controller.take_signal().expect("connection response");
+            assert_eq!(code, L2capSignalCode::LeCreditConnRes as u8);
+            LeCreditConnRes::from_hci_bytes_complete(&payload)
+                .unwrap()
+                .result_code()
+                .unwrap()
+        };
+
+        let mut registration = PsmRegistration::new(0x0081, 64);
+        registration.security = SecurityLevel::Encrypted;
+        registration.max_channels = 1;
+        manager.register(registration).unwrap();
+
+        signal(&le_credit_conn_req(0x0081, 0x40), SecurityLevel::NoEncryption).unwrap();
+        assert_eq!(result(&controller), LeCreditConnResultCode::InsufficientEncryption);
+        assert!(!has_state(&manager, BASE_ID, ChannelState::PeerConnecting(1)));
+
+        signal(&le_credit_conn_req(0x0081, 0x40), SecurityLevel::Encrypted).unwrap();
+        assert!(controller.take_signal().is_none());
+        assert!(has_state(&manager, BASE_ID, ChannelState::PeerConnecting(1)));
+
+        // The registration limits the number of channels open at once.
+        signal(&le_credit_conn_req(0x0081, 0x41), SecurityLevel::Encrypted).unwrap();
+        assert_eq!(result(&controller), LeCreditConnResultCode::NoResources);
+
+        // Encryption alone does not satisfy PSMs requiring an authenticated key.
+        let mut registration = PsmRegistration::new(0x0083, 64);
+        registration.security = SecurityLevel::EncryptedAuthenticated;
+        manager.register(registration).unwrap();
+        signal(&le_credit_conn_req(0x0083, 0x42), SecurityLevel::Encrypted).unwrap();
+        assert_eq!(result(&controller), LeCreditConnResultCode::InsufficientAuthentication);
+        signal(&le_credit_conn_req(0x0083, 0x42), SecurityLevel::EncryptedAuthenticated).unwrap();
+        assert!(controller.take_signal().is_none());
+    }
+
+    #[test]
+    fn test_accept_registered_psm() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, controller, conn) = fixture.parts();
+        let accept = |psm: &[u16]| {
+            embassy_futures::block_on(manager.accept(
+                conn,
+                psm,
+                64,
+                CreditFlowPolicy::Every(1),
+                Some(4),
+                1,
+                L2CAP_RTX_TIMEOUT_DEFAULT,
+                &controller.hci(),
+            ))
+        };
+
+        // Accepting does not register the PSM implicitly.
+        assert!(matches!(accept(&[0x2349]), Err(AdapterError::Adapter(Error::NotFound))));
+        assert!(manager.state.lock(|state| state.borrow().psms.is_empty()));
+
+        manager.register(PsmRegistration::new(0x2349, 48)).unwrap();
+        embassy_futures::block_on(manager.signal(
+            conn,
+            &le_credit_conn_req(0x2349, 0x40),
+            LeConnRole::Peripheral,
+            SecurityLevel::NoEncryption,
+            &controller.hci(),
+        ))
+        .unwrap();
+        let cids = accept(&[0x2349]).unwrap();
+        assert_eq!(&cids[..], &[BASE_ID]);
+        assert!(has_state(&manager, BASE_ID, ChannelState::Connected));
+
+        let (code, _, payload) = controller.take_signal().expect("connection response");
+        assert_eq!(code, L2capSignalCode::LeCreditConnRes as u8);
+        let res = LeCreditConnRes::from_hci_bytes_complete(&payload).unwrap();
+        assert_eq!(res.result_code().unwrap(), LeCreditConnResultCode::Success);
+        assert_eq!(res.dcid, BASE_ID);
+        // The MTU of the registration bounds the one requested when accepting.
+        assert_eq!(res.mtu, 48);
+    }
+
+    fn all_disconnected(manager: &Manager<'_>) -> bool {
+        manager.state.lock(|state| {
+            state
+                .borrow()
+                .channels
+                .iter()
+                .all(|storage| storage.state == ChannelState::Disconnected)
+        })
+    }
+
+    #[test]
+    fn test_create_timeout() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, controller, conn) = fixture.parts();
+        let create = |timeout| {
+            embassy_futures::block_on(manager.create(
+                conn,
+                0x2349,
+                64,
+                CreditFlowPolicy::Every(1),
+                None,
+                timeout,
+                &controller.hci(),
+            ))
+        };
+
+        // Timeouts outside of the range of the specification are refused before sending anything.
+        assert!(matches!(
+            create(Duration::from_millis(500)),
+            Err(AdapterError::Adapter(Error::InvalidValue))
+        ));
+        assert!(controller.take_signal().is_none());
+
+        // A peer that never answers frees the half-open channel.
+        assert!(matches!(
+            create(L2CAP_RTX_TIMEOUT_MIN),
+            Err(AdapterError::Adapter(Error::Timeout))
+        ));
+        let (code, _, _) = controller.take_signal().expect("connection request");
+        assert_eq!(code, L2capSignalCode::LeCreditConnReq as u8);
+        assert!(all_disconnected(&manager));
+    }
+
+    #[test]
+    fn test_disconnect_retransmission() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, controller, conn) = fixture.parts();
+        let mut cid = 0;
+        manager
+            .alloc(|storage| {
+                cid = storage.cid;
+                storage.conn = conn.raw();
+                storage.peer_cid = 0x50;
+                storage.rtx_timeout = L2CAP_RTX_TIMEOUT_MIN;
+                storage.state = ChannelState::Connected;
+            })
+            .unwrap();
+
+        assert!(matches!(
+            embassy_futures::block_on(manager.close(cid, &controller.hci())),
+            Err(AdapterError::Adapter(Error::Timeout))
+        ));
+
+        // The request is sent again with the same identifier before the channel is released.
+        let sent = controller.take_sent();
+        assert_eq!(sent.len(), DISCONNECT_ATTEMPTS);
+        for packet in sent.iter() {
+            assert_eq!(packet[4], L2capSignalCode::DisconnectionReq as u8);
+            assert_eq!(packet[5], sent[0][5]);
+            assert_eq!(&packet[8..], &[0x50, 0x00, cid as u8, (cid >> 8) as u8]);
+        }
+        assert!(all_disconnected(&manager));
+    }
+
+    #[test]
+    fn test_conn_param_request_timeout() {
+        let fixture = Fixture::new(Qos::None);
+        let pool = &fixture.pool;
+        let (manager, controller, conn) = fixture.parts();
+        let request = |timeout| {
+            embassy_futures::block_on(manager.request_conn_params(
+                conn,
+                &update_req(40, 0, 100),
+                timeout,
+                &controller.hci(),
+            ))
+        };
+
+        assert!(matches!(
+            request(L2CAP_RTX_TIMEOUT_MAX + Duration::from_secs(1)),
+            Err(AdapterError::Adapter(Error::InvalidValue))
+        ));
+        assert!(matches!(
+            request(L2CAP_RTX_TIMEOUT_MIN),
+            Err(AdapterError::Adapter(Error::Timeout))
+        ));
+        let (code, _, _) = controller.take_signal().expect("connection parameter update request");
+        assert_eq!(code, L2capSignalCode::ConnParamUpdateReq as u8);
+
+        // The connection may send another request once the previous one timed out.
+        manager.start_conn_param_update(conn, 1).unwrap();
+    }
+
+    // Open a channel as if the peer accepted it.
+    fn connected(manager: &Manager<'_>, conn: ConnHandle, mtu: u16, credits: u16) -> u16 {
+        let mut cid = 0;
+        manager
+            .alloc(|storage| {
+                cid = storage.cid;
+                storage.conn = conn.raw();
+                storage.mtu = mtu;
+                storage.mps = 60;
+                storage.flow_control = CreditFlowControl::new(CreditFlowPolicy::Every(1), credits);
+                storage.peer_cid = 0x50;
+                storage.peer_mps = 60;
+                storage.peer_mtu = 60;
+                storage.state = ChannelState::Connected;
+            })
+            .unwrap();
+        cid
+    }
+
+    fn kframe<'d>(pool: &'d PacketPool<NoopRawMutex, 64, 8, 4>, cid: u16, payload: &[u8]) -> (L2capHeader, Packet<'d>) {
+        let mut packet = pool.alloc(AllocId::from_channel(cid).unwrap()).unwrap();
+        packet.as_mut()[..payload.len()].copy_from_slice(payload);
+        let header = L2capHeader {
+            length: payload.len() as u16,
+            channel: cid,
+        };
+        (header, packet)
+    }
+
+    #[test]
+    fn test_kframe_validation() {
+        let mut storage = ChannelStorage::DISCONNECTED;
+        storage.mtu = 10;
+        storage.mps = 6;
+
+        // Larger than the MPS we advertised.
+        assert!(matches!(storage.receive_kframe(&[0; 7]), Err(Error::MtuExceeded)));
+        // SDU larger than the MTU we advertised.
+        assert!(matches!(storage.receive_kframe(&[11, 0]), Err(Error::MtuExceeded)));
+        // First K-frame without the SDU length.
+        assert!(matches!(storage.receive_kframe(&[1]), Err(Error::InvalidValue)));
+        // First K-frame longer than the SDU.
+        assert!(matches!(
+            storage.receive_kframe(&[2, 0, 1, 2, 3]),
+            Err(Error::InvalidValue)
+        ));
+
+        storage.receive_kframe(&[10, 0, 1, 2, 3, 4]).unwrap();
+        assert_eq!(storage.sdu_remaining, 6);
+        storage.receive_kframe(&[5, 6, 7, 8]).unwrap();
+        // Continuation beyond the end of the SDU.
+        assert!(matches!(storage.receive_kframe(&[9, 10, 11]), Err(Error::InvalidValue)));
+        storage.receive_kframe(&[9, 10]).unwrap();
+        assert_eq!(storage.sdu_remaining, 0);
+        assert_eq!(storage.stats.sdus_received, 1);
+        assert_eq!(storage.stats.bytes_received, 10);
+    }
+
+    #[test]
+    fn test_abort_on_protocol_violation() {
+        let fixture = Fixture::new(Qos::None);
+        let pool = &fixture.pool;
+        let (manager, controller, conn) = fixture.parts();
+        let cid = connected(&manager, conn, 32, 0);
+
+        // Sending without credits closes the channel.
+        let (header, packet) = kframe(pool, cid, &[2, 0, 1, 2]);
+        assert!(matches!(
+            embassy_futures::block_on(manager.dispatch(conn, header, packet, &controller.hci())),
+            Err(Error::OutOfCredits)
+        ));
+        let (code, identifier, payload) = controller.take_signal().expect("disconnection request");
+        assert_eq!(code, L2capSignalCode::DisconnectionReq as u8);
+        assert_eq!(&payload[..], &[0x50, 0x00, cid as u8, (cid >> 8) as u8]);
+        assert!(has_state(&manager, cid, ChannelState::DisconnectRequested(identifier)));
+        assert!(matches!(
+            manager.connected_channel_index(cid),
+            Err(Error::ChannelClosed)
+        ));
+
+        // The response of the peer releases the channel, whether or not anybody is receiving.
+        let mut payload = [0; 4];
+        payload[..2].copy_from_slice(&0x50u16.to_le_bytes());
+        payload[2..].copy_from_slice(&cid.to_le_bytes());
+        embassy_futures::block_on(manager.signal(
+            conn,
+            &signal_data(L2capSignalCode::DisconnectionRes, identifier, &payload),
+            LeConnRole::Central,
+            SecurityLevel::NoEncryption,
+            &controller.hci(),
+        ))
+        .unwrap();
+        assert!(has_state(&manager, cid, ChannelState::Disconnected));
+    }
+
+    #[test]
+    fn test_abort_without_response() {
+        let fixture = Fixture::new(Qos::None);
+        let pool = &fixture.pool;
+        let (manager, controller, conn) = fixture.parts();
+        let cid = connected(&manager, conn, 32, 4);
+        for _ in 1..4 {
+            connected(&manager, conn, 32, 4);
+        }
+        manager.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            state.channels[0].rtx_timeout = Duration::from_ticks(0);
+            state.channels[1].rtx_timeout = L2CAP_RTX_TIMEOUT_MAX;
+        });
+
+        // An SDU longer than our MTU.
+        let (header, packet) = kframe(pool, cid, &[33, 0, 1, 2]);
+        assert!(matches!(
+            embassy_futures::block_on(manager.dispatch(conn, header, packet, &controller.hci())),
+            Err(Error::MtuExceeded)
+        ));
+        let (header, packet) = kframe(pool, cid + 1, &[2, 0, 1, 2, 3]);
+        assert!(matches!(
+            embassy_futures::block_on(manager.dispatch(conn, header, packet, &controller.hci())),
+            Err(Error::InvalidValue)
+        ));
+
+        // The slot of a peer that never answers can be reused once its RTX timer expired.
+        let reused = connected(&manager, conn, 32, 4);
+        assert_eq!(reused, cid);
+        assert!(manager.alloc(|_| {}).is_err());
+    }
+
+    #[test]
+    fn test_receive_buffer_too_small() {
+        let fixture = Fixture::new(Qos::None);
+        let pool = &fixture.pool;
+        let (manager, controller, conn) = fixture.parts();
+        let cid = connected(&manager, conn, 16, 4);
+
+        let (header, packet) = kframe(pool, cid, &[3, 0, 1, 2, 3]);
+        embassy_futures::block_on(manager.dispatch(conn, header, packet, &controller.hci())).unwrap();
+
+        // Refused without consuming the SDU.
+        let mut small = [0; 8];
+        assert!(matches!(
+            embassy_futures::block_on(manager.receive(cid, &mut small, &controller.hci())),
+            Err(AdapterError::Adapter(Error::InsufficientSpace))
+        ));
+
+        let mut buf = [0; 16];
+        let len = embassy_futures::block_on(manager.receive(cid, &mut buf, &controller.hci())).unwrap();
+        assert_eq!(&buf[..len], &[1, 2, 3]);
+    }
+
+    fn released(manager: &Manager<'_>, cid: u16) -> u16 {
+        manager.state.lock(|state| {
+            let state = state.borrow();
+            let storage = state.channels.iter().find(|storage| storage.cid == cid).unwrap();
+            storage.flow_control.released
+        })
+    }
+
+    fn set_peer_credits(manager: &Manager<'_>, cid: u16, credits: u16) {
+        manager
+            .with_channel(cid, |storage| storage.peer_credits = credits)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_receive_packet() {
+        let fixture = Fixture::new(Qos::None);
+        let pool = &fixture.pool;
+        let (manager, controller, conn) = fixture.parts();
+        let cid = connected(&manager, conn, 32, 4);
+
+        let (header, packet) = kframe(pool, cid, &[5, 0, 1, 2, 3]);
+        embassy_futures::block_on(manager.dispatch(conn, header, packet, &controller.hci())).unwrap();
+        let (header, packet) = kframe(pool, cid, &[4, 5]);
+        embassy_futures::block_on(manager.dispatch(conn, header, packet, &controller.hci())).unwrap();
+
+        // The continuation is gathered into the first packet and credited right away.
+        let packet = embassy_futures::block_on(manager.receive_packet(cid, &controller.hci())).unwrap();
+        assert_eq!(packet.as_ref(), &[1, 2, 3, 4, 5]);
+        assert_eq!(packet.len(), 5);
+        let (code, _, payload) = controller.take_signal().expect("credit indication");
+        assert_eq!(code, L2capSignalCode::LeCreditFlowInd as u8);
+        assert_eq!(&payload[..], &[cid as u8, (cid >> 8) as u8, 1, 0]);
+
+        // The first packet is released once dropped, and credited by the next receive.
+        assert_eq!(released(&manager, cid), 0);
+        drop(packet);
+        assert_eq!(released(&manager, cid), 1);
+        assert!(controller.take_signal().is_none());
+
+        let (header, packet) = kframe(pool, cid, &[1, 0, 9]);
+        embassy_futures::block_on(manager.dispatch(conn, header, packet, &controller.hci())).unwrap();
+        let packet = embassy_futures::block_on(manager.receive_packet(cid, &controller.hci())).unwrap();
+        assert_eq!(packet.as_ref(), &[9]);
+        let (code, _, payload) = controller.take_signal().expect("credit indication");
+        assert_eq!(code, L2capSignalCode::LeCreditFlowInd as u8);
+        assert_eq!(&payload[2..], &[1, 0]);
+    }
+
+    #[test]
+    fn test_receive_packet_too_large() {
+        let fixture = Fixture::new(Qos::None);
+        let pool = &fixture.pool;
+        let (manager, controller, conn) = fixture.parts();
+        let cid = connected(&manager, conn, 100, 4);
+
+        // A valid SDU of 70 bytes, which does not fit a pool packet of 64 bytes.
+        let mut first = [0; 60];
+        first[0] = 70;
+        let (header, packet) = kframe(pool, cid, &first);
+        embassy_futures::block_on(manager.dispatch(conn, header, packet, &controller.hci())).unwrap();
+        let (header, packet) = kframe(pool, cid, &[0; 12]);
+        embassy_futures::block_on(manager.dispatch(conn, header, packet, &controller.hci())).unwrap();
+
+        assert!(matches!(
+            embassy_futures::block_on(manager.receive_packet(cid, &controller.hci())),
+            Err(AdapterError::Adapter(Error::InsufficientSpace))
+        ));
+        // Both K-frames are credited back to the peer.
+        let sent = controller.take_sent();
+        assert_eq!(sent.len(), 2);
+        for packet in sent.iter() {
+            assert_eq!(packet[4], L2capSignalCode::LeCreditFlowInd as u8);
+        }
+        assert_eq!(pool.available(AllocId::from_channel(cid).unwrap()), 8);
+    }
+
+    #[test]
+    fn test_send_packet() {
+        let fixture = Fixture::new(Qos::None);
+        let pool = &fixture.pool;
+        let (manager, controller, conn) = fixture.parts();
+        let cid = connected(&manager, conn, 32, 4);
+        set_peer_credits(&manager, cid, 4);
+
+        // Room is left in front of the SDU for the headers.
+        let mut packet = manager.alloc_packet(cid).unwrap();
+        assert_eq!(packet.len(), 64 - L2capPacket::HEADROOM);
+        packet.truncate(3);
+        packet.as_mut().copy_from_slice(&[1, 2, 3]);
+        embassy_futures::block_on(manager.send_packet(cid, packet, &controller.hci())).unwrap();
+
+        let sent = controller.take_sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(&sent[0][..], &[5, 0, 0x50, 0, 3, 0, 1, 2, 3]);
+
+        // SDUs larger than a K-frame are segmented.
+        manager.with_channel(cid, |storage| storage.peer_mps = 20).unwrap();
+        let mut packet = manager.alloc_packet(cid).unwrap();
+        packet.truncate(30);
+        packet.as_mut().copy_from_slice(&[7; 30]);
+        embassy_futures::block_on(manager.send_packet(cid, packet, &controller.hci())).unwrap();
+
+        let sent = controller.take_sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(&sent[0][..6], &[20, 0, 0x50, 0, 30, 0]);
+        assert_eq!(&sent[1][..4], &[12, 0, 0x50, 0]);
+        assert!(manager.with_channel(cid, |storage| storage.peer_credits == 1).unwrap());
+        assert_eq!(pool.available(AllocId::from_channel(cid).unwrap()), 8);
+    }
+
+    #[test]
+    fn test_alloc_packet_quota() {
+        let fixture = Fixture::new(Qos::Fair);
+        let pool = &fixture.pool;
+        let (manager, _, conn) = fixture.parts();
+        let cid = connected(&manager, conn, 32, 4);
+
+        // Packets held for sending leave the packets backing the credits of the channel alone.
+        let id = AllocId::from_channel(cid).unwrap();
+        let available = pool.available(id);
+        let packet = manager.alloc_packet(cid).unwrap();
+        assert_eq!(pool.available(id), available);
+        drop(packet);
+    }
+
+    #[test]
+    fn test_send_while_receiving() {
+        let fixture = Fixture::new(Qos::None);
+        let pool = &fixture.pool;
+        let (manager, controller, conn) = fixture.parts();
+        let cid = connected(&manager, conn, 32, 4);
+        set_peer_credits(&manager, cid, 4);
+
+        let hci = controller.hci();
+        let mut buf = [0; 32];
+        let (received, _) = embassy_futures::block_on(embassy_futures::join::join(
+            manager.receive(cid, &mut buf, &hci),
+            async {
+                // Sending does not wait for the pending receive.
+                manager.send(cid, &[1, 2, 3], &hci).await.unwrap();
+                assert_eq!(controller.take_sent().len(), 1);
+                let (header, packet) = kframe(pool, cid, &[2, 0, 4, 5]);
+                manager.dispatch(conn, header, packet, &hci).await.unwrap();
+            },
+        ));
+        assert_eq!(&buf[..received.unwrap()], &[4, 5]);
+    }
+
+    #[test]
+    fn test_disconnect_observed_by_reader_and_writer() {
+        let fixture = Fixture::new(Qos::None);
+        let (manager, controller, conn) = fixture.parts();
+        let cid = connected(&manager, conn, 32, 4);
+
+        let hci = controller.hci();
+        let mut buf = [0; 32];
+        let mut payload = [0; 4];
+        payload[..2].copy_from_slice(&cid.to_le_bytes());
+        payload[2..].copy_from_slice(&0x50u16.to_le_bytes());
+        let (sent, received, _) = embassy_futures::block_on(embassy_futures::join::join3(
+            // Blocked waiting for credits.
+            manager.send(cid, &[1, 2, 3], &hci),
+            manager.receive(cid, &mut buf, &hci),
+            async {
+                embassy_futures::yield_now().await;
+                let req = signal_data(L2capSignalCode::DisconnectionReq, 1, &payload);
+                manager
+                    .signal(conn, &req, LeConnRole::Central, SecurityLevel::NoEncryption, &hci)
+                    .await
+                    .unwrap();
+            },
+        ));
+        assert!(matches!(sent, Err(AdapterError::Adapter(Error::ChannelClosed))));
+        assert!(matches!(received, Err(AdapterError::Adapter(Error::ChannelClosed))));
+        assert!(all_disconnected(&manager));
+    }
+
+    #[test]
+    fn test_channel_stats() {
+        let fixture = Fixture::new(Qos::None);
+        let pool = &fixture.pool;
+        let (manager, controller, conn) = fixture.parts();
+        let cid = connected(&manager, conn, 32, 4);
+        manager
+            .with_channel(cid, |storage| {
+                storage.peer_mtu = 48;
+                storage.peer_mps = 20;
+            })
+            .unwrap();
+        let hci = controller.hci();
+        let mut credits = [0; 4];
+        credits[..2].copy_from_slice(&0x50u16.to_le_bytes());
+        credits[2..].copy_from_slice(&2u16.to_le_bytes());
+        let credit_ind = signal_data(L2capSignalCode::LeCreditFlowInd, 1, &credits);
+        let grant = || {
+            manager.signal(
+                conn,
+                &credit_ind,
+                LeConnRole::Central,
+                SecurityLevel::NoEncryption,
+                &hci,
+            )
+        };
+
+        // The smallest of the values of both sides.
+        assert_eq!(manager.negotiated(cid).unwrap(), (32, 20));
+        assert_eq!(manager.peer_credits(cid).unwrap(), 0);
+
+        assert!(matches!(
+            manager.try_send(cid, &[1, 2, 3], &hci),
+            Err(AdapterError::Adapter(Error::Busy))
+        ));
+        embassy_futures::block_on(grant()).unwrap();
+        assert_eq!(manager.peer_credits(cid).unwrap(), 2);
+
+        // One K-frame, then an SDU segmented into two.
+        embassy_futures::block_on(manager.send(cid, &[1, 2, 3], &hci)).unwrap();
+        assert_eq!(manager.peer_credits(cid).unwrap(), 1);
+        let (sent, _) =
+            embassy_futures::block_on(embassy_futures::join::join(manager.send(cid, &[0; 30], &hci), async {
+                embassy_futures::yield_now().await;
+                grant().await.unwrap();
+            }));
+        sent.unwrap();
+        assert_eq!(manager.peer_credits(cid).unwrap(), 1);
+        assert_eq!(controller.take_sent().len(), 3);
+
+        let (header, packet) = kframe(pool, cid, &[4, 0, 1, 2]);
+        embassy_futures::block_on(manager.dispatch(conn, header, packet, &hci)).unwrap();
+        let (header, packet) = kframe(pool, cid, &[3, 4]);
+        embassy_futures::block_on(manager.dispatch(conn, header, packet, &hci)).unwrap();
+        let mut buf = [0; 32];
+        let len = embassy_futures::block_on(manager.receive(cid, &mut buf, &hci)).unwrap();
+        assert_eq!(&buf[..len], &[1, 2, 3, 4]);
+
+        let stats = manager.stats(cid).unwrap();
+        assert_eq!(stats.sdus_sent, 2);
+        assert_eq!(stats.bytes_sent, 33);
+        assert_eq!(stats.credit_stalls, 2);
+        assert_eq!(stats.sdus_received, 1);
+        assert_eq!(stats.bytes_received, 4);
+
+        // Counters remain available until the channel is released.
+        manager.disconnect(cid).unwrap();
+        assert!(matches!(manager.negotiated(cid), Err(Error::ChannelClosed)));
+        assert!(matches!(manager.peer_credits(cid), Err(Error::ChannelClosed)));
+        assert_eq!(manager.stats(cid).unwrap().sdus_sent, 2);
+        embassy_futures::block_on(manager.close(cid, &hci)).unwrap();
+        assert!(matches!(manager.stats(cid), Err(Error::NotFound)));
+    }
+
+    fn pressure(free: usize, queued: usize) -> PoolPressure {
+        PoolPressure {
+            free,
+            queued,
+            capacity: 4,
         }
     }
+
+    #[test]
+    fn test_adaptive_credits() {
+        let mut flow = CreditFlowControl::new(CreditFlowPolicy::Adaptive, 0);
+
+        // Nothing to grant until packets are released.
+        assert_eq!(flow.process(pressure(8, 0)), None);
+
+        // Bounded by the free slots of the receive queue rather than the free packets.
+        flow.released(1);
+        assert_eq!(flow.process(pressure(8, 0)), Some(4));
+        assert_eq!(flow.available(), 4);
+
+        // Outstanding credits are deducted.
+        flow.received(3);
+        flow.released(1);
+        assert_eq!(flow.process(pressure(8, 1)), Some(2));
+        assert_eq!(flow.available(), 3);
+
+        // Bounded by the free packets.
+        flow.received(3);
+        flow.released(1);
+        assert_eq!(flow.process(pressure(2, 1)), Some(2));
+
+        // Nothing is granted while the application falls behind.
+        flow.received(2);
+        flow.released(1);
+        assert_eq!(flow.process(pressure(8, 2)), None);
+        assert!(!flow.ready());
+    }
+
+    #[test]
+    fn test_adaptive_credits_no_room() {
+        let mut flow = CreditFlowControl::new(CreditFlowPolicy::Adaptive, 1);
+
+        // Credits already cover the room left.
+        flow.released(1);
+        assert_eq!(flow.process(pressure(1, 0)), None);
+
+        // A channel without credits and nothing queued is never left stalled.
+        flow.received(1);
+        flow.released(1);
+        assert_eq!(flow.process(pressure(0, 0)), Some(1));
+    }
 }
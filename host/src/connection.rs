@@ -1,18 +1,47 @@
+use core::future::poll_fn;
+
 use bt_hci::cmd::le::LeConnUpdate;
 use bt_hci::cmd::link_control::Disconnect;
 use bt_hci::cmd::status::ReadRssi;
 use bt_hci::controller::{Controller, ControllerCmdAsync, ControllerCmdSync};
-use bt_hci::param::{BdAddr, ConnHandle, DisconnectReason, LeConnRole};
+use bt_hci::param::{BdAddr, ConnHandle, DisconnectReason, LeConnRole, PhyKind, Status};
 use embassy_sync::blocking_mutex::raw::RawMutex;
 use embassy_time::Duration;
 
 use crate::adapter::Adapter;
+use crate::connection_manager::DynamicConnectionManager;
 use crate::scan::ScanConfig;
-use crate::AdapterError;
+use crate::types::l2cap::ConnParamUpdateReq;
+use crate::{AdapterError, Error};
 
-#[derive(Clone)]
-pub struct Connection {
+/// Handle to a connected link.
+///
+/// Handles are reference counted: the connection slot of a link is only reused for a new link once
+/// every handle to it has been dropped. Operations on a handle to a link that has been disconnected
+/// fail with `Error::Disconnected`, even if the controller reuses its connection handle.
+pub struct Connection<'d> {
+    index: u8,
+    generation: u16,
     handle: ConnHandle,
+    manager: &'d dyn DynamicConnectionManager,
+}
+
+impl<'d> Clone for Connection<'d> {
+    fn clone(&self) -> Self {
+        self.manager.inc_ref(self.index);
+        Self {
+            index: self.index,
+            generation: self.generation,
+            handle: self.handle,
+            manager: self.manager,
+        }
+    }
+}
+
+impl<'d> Drop for Connection<'d> {
+    fn drop(&mut self) {
+        self.manager.dec_ref(self.index);
+    }
 }
 
 pub struct ConnectConfig<'d> {
@@ -40,15 +69,119 @@ impl Default for ConnectParams {
     }
 }
 
-impl Connection {
-    pub(crate) fn new(handle: ConnHandle) -> Self {
-        Self { handle }
+/// Security level of a connection, ordered from least to most secure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SecurityLevel {
+    /// The link is not encrypted.
+    NoEncryption,
+    /// The link is encrypted with an unauthenticated key.
+    Encrypted,
+    /// The link is encrypted with an authenticated key.
+    EncryptedAuthenticated,
+}
+
+impl ConnectParams {
+    pub(crate) fn to_update_request(&self) -> ConnParamUpdateReq {
+        ConnParamUpdateReq {
+            interval_min: (self.min_connection_interval.as_micros() / 1250) as u16,
+            interval_max: (self.max_connection_interval.as_micros() / 1250) as u16,
+            latency: self.max_latency,
+            timeout: (self.supervision_timeout.as_millis() / 10) as u16,
+        }
+    }
+
+    pub(crate) fn from_update_request(req: &ConnParamUpdateReq) -> Self {
+        Self {
+            min_connection_interval: Duration::from_micros(req.interval_min as u64 * 1250),
+            max_connection_interval: Duration::from_micros(req.interval_max as u64 * 1250),
+            max_latency: req.latency,
+            event_length: Duration::from_secs(0),
+            supervision_timeout: Duration::from_millis(req.timeout as u64 * 10),
+        }
+    }
+}
+
+/// Event affecting an established connection.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConnectionEvent {
+    /// The connection was closed, for the reason reported by the controller.
+    Disconnected { reason: Status },
+    /// The connection parameters were updated.
+    ConnectionParamsUpdated {
+        interval: Duration,
+        latency: u16,
+        supervision_timeout: Duration,
+    },
+    /// The PHYs used by the connection changed.
+    PhyUpdated { tx_phy: PhyKind, rx_phy: PhyKind },
+    /// The maximum payload sizes and transmission times of link layer packets changed.
+    DataLengthChanged {
+        max_tx_octets: u16,
+        max_tx_time: u16,
+        max_rx_octets: u16,
+        max_rx_time: u16,
+    },
+    /// The security level of the link changed.
+    SecurityChanged { level: SecurityLevel },
+    /// The ATT MTU was negotiated with the peer.
+    AttMtuChanged { mtu: u16 },
+}
+
+/// Policy deciding whether connection parameters requested by a peripheral are accepted.
+pub trait ConnectionParamsPolicy {
+    fn accept(&self, connection: &Connection<'_>, params: &ConnectParams) -> bool;
+}
+
+impl<F: Fn(&Connection<'_>, &ConnectParams) -> bool> ConnectionParamsPolicy for F {
+    fn accept(&self, connection: &Connection<'_>, params: &ConnectParams) -> bool {
+        self(connection, params)
+    }
+}
+
+/// Outcome of a connection parameter update request received from a peripheral.
+pub struct ConnectionParamsUpdate<'d> {
+    pub connection: Connection<'d>,
+    pub params: ConnectParams,
+    pub accepted: bool,
+}
+
+impl<'d> Connection<'d> {
+    /// Create a handle for a connection slot whose reference count was already incremented.
+    pub(crate) fn new(
+        index: u8,
+        generation: u16,
+        handle: ConnHandle,
+        manager: &'d dyn DynamicConnectionManager,
+    ) -> Self {
+        Self {
+            index,
+            generation,
+            handle,
+            manager,
+        }
     }
 
     pub fn handle(&self) -> ConnHandle {
         self.handle
     }
 
+    /// Whether the link is still connected.
+    pub fn is_connected(&self) -> bool {
+        self.live_handle().is_ok()
+    }
+
+    /// Index and generation of the connection slot, which identify the link even if its handle is reused.
+    pub(crate) fn slot(&self) -> (u8, u16) {
+        (self.index, self.generation)
+    }
+
+    /// Handle of the link, or `Error::Disconnected` if it was disconnected since this handle was obtained.
+    pub(crate) fn live_handle(&self) -> Result<ConnHandle, Error> {
+        self.manager.handle(self.index, self.generation)
+    }
+
     pub fn disconnect<
         M: RawMutex,
         T: Controller + ControllerCmdSync<Disconnect>,
@@ -61,7 +194,10 @@ impl Connection {
         &mut self,
         adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
     ) -> Result<(), AdapterError<T::Error>> {
-        adapter.try_command(Disconnect::new(self.handle, DisconnectReason::RemoteUserTerminatedConn))?;
+        adapter.try_command(Disconnect::new(
+            self.live_handle()?,
+            DisconnectReason::RemoteUserTerminatedConn,
+        ))?;
         Ok(())
     }
 
@@ -77,7 +213,7 @@ impl Connection {
         &self,
         adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
     ) -> Result<LeConnRole, AdapterError<T::Error>> {
-        let role = adapter.connections.role(self.handle)?;
+        let role = adapter.connections.role(self.live_handle()?)?;
         Ok(role)
     }
 
@@ -93,7 +229,7 @@ impl Connection {
         &self,
         adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
     ) -> Result<BdAddr, AdapterError<T::Error>> {
-        let addr = adapter.connections.peer_address(self.handle)?;
+        let addr = adapter.connections.peer_address(self.live_handle()?)?;
         Ok(addr)
     }
 
@@ -111,7 +247,7 @@ impl Connection {
     where
         T: ControllerCmdSync<ReadRssi>,
     {
-        let ret = adapter.command(ReadRssi::new(self.handle)).await?;
+        let ret = adapter.command(ReadRssi::new(self.live_handle()?)).await?;
         Ok(ret.rssi)
     }
 
@@ -132,7 +268,7 @@ impl Connection {
     {
         adapter
             .async_command(LeConnUpdate::new(
-                self.handle,
+                self.live_handle()?,
                 params.min_connection_interval.into(),
                 params.max_connection_interval.into(),
                 params.max_latency,
@@ -143,4 +279,97 @@ impl Connection {
             .await?;
         Ok(())
     }
+
+    /// Current security level of the link.
+    pub fn security_level<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+    ) -> Result<SecurityLevel, AdapterError<T::Error>> {
+        let level = adapter.connections.security_level(self.live_handle()?)?;
+        Ok(level)
+    }
+
+    /// Record the security level of the link.
+    ///
+    /// The controller only reports whether the link is encrypted, so the application raises the
+    /// level to [`SecurityLevel::EncryptedAuthenticated`] once pairing produced an authenticated key.
+    /// The level is reset by the next encryption change reported by the controller.
+    pub fn set_security_level<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        level: SecurityLevel,
+    ) -> Result<(), AdapterError<T::Error>> {
+        adapter.connections.set_security_level(self.live_handle()?, level)?;
+        Ok(())
+    }
+
+    /// Wait for the next event of this connection.
+    ///
+    /// Events are kept in a small queue per connection, from which the oldest events are dropped if
+    /// they are not read in time. Once the disconnection has been reported, `Error::Disconnected` is returned.
+    pub async fn next_event<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+    ) -> Result<ConnectionEvent, AdapterError<T::Error>> {
+        let event = poll_fn(|cx| adapter.connections.poll_next_event(self.index, self.generation, cx)).await?;
+        Ok(event)
+    }
+
+    /// Ask the central to update the connection parameters using the L2CAP signalling channel.
+    ///
+    /// This is the way for a peripheral to change parameters on controllers where the LE
+    /// Connection Update command is only available to the central. Returns an error if the
+    /// central rejects the request, `Error::Disconnected` if the link is lost before it answers,
+    /// and `Error::Timeout` if it does not answer within `timeout`, which must be between
+    /// [`L2CAP_RTX_TIMEOUT_MIN`] and [`L2CAP_RTX_TIMEOUT_MAX`].
+    ///
+    /// [`L2CAP_RTX_TIMEOUT_MIN`]: crate::l2cap::L2CAP_RTX_TIMEOUT_MIN
+    /// [`L2CAP_RTX_TIMEOUT_MAX`]: crate::l2cap::L2CAP_RTX_TIMEOUT_MAX
+    pub async fn request_connection_params<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        params: &ConnectParams,
+        timeout: Duration,
+    ) -> Result<(), AdapterError<T::Error>> {
+        let handle = self.live_handle()?;
+        if !matches!(adapter.connections.role(handle)?, LeConnRole::Peripheral) {
+            return Err(Error::InvalidState.into());
+        }
+        adapter
+            .channels
+            .request_conn_params(handle, &params.to_update_request(), timeout, &adapter.hci())
+            .await
+    }
 }
@@ -3,17 +3,38 @@ use core::future::poll_fn;
 use core::task::{Context, Poll};
 
 use bt_hci::event::le::LeConnectionComplete;
-use bt_hci::param::{AddrKind, BdAddr, ConnHandle, LeConnRole};
+use bt_hci::param::{AddrKind, BdAddr, ConnHandle, LeConnRole, Status};
 use embassy_sync::blocking_mutex::raw::RawMutex;
 use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::signal::Signal;
 use embassy_sync::waitqueue::WakerRegistration;
+use heapless::Deque;
 
+use crate::connection::{Connection, ConnectionEvent, SecurityLevel};
 use crate::Error;
 
+/// Number of events kept for a connection until the application reads them.
+const EVENT_QUEUE_LEN: usize = 4;
+
 struct State<const CONNS: usize> {
     connections: [ConnectionStorage; CONNS],
     waker: WakerRegistration,
+    free_waker: WakerRegistration,
+    events: [Deque<ConnectionEvent, EVENT_QUEUE_LEN>; CONNS],
+    event_wakers: [WakerRegistration; CONNS],
+}
+
+impl<const CONNS: usize> State<CONNS> {
+    // Queue an event for the connection in a slot, dropping the oldest event if the application is behind.
+    fn post(&mut self, idx: usize, event: ConnectionEvent) {
+        let events = &mut self.events[idx];
+        if events.is_full() {
+            warn!("[conn] event queue full, dropping oldest event");
+            events.pop_front();
+        }
+        let _ = events.push_back(event);
+        self.event_wakers[idx].wake();
+    }
 }
 
 pub(crate) struct ConnectionManager<M: RawMutex, const CONNS: usize> {
@@ -22,11 +43,17 @@ pub(crate) struct ConnectionManager<M: RawMutex, const CONNS: usize> {
 }
 
 impl<M: RawMutex, const CONNS: usize> ConnectionManager<M, CONNS> {
+    const EVENTS: Deque<ConnectionEvent, EVENT_QUEUE_LEN> = Deque::new();
+    const EVENT_WAKER: WakerRegistration = WakerRegistration::new();
+
     pub(crate) fn new() -> Self {
         Self {
             state: Mutex::new(RefCell::new(State {
                 connections: [ConnectionStorage::DISCONNECTED; CONNS],
                 waker: WakerRegistration::new(),
+                free_waker: WakerRegistration::new(),
+                events: [Self::EVENTS; CONNS],
+                event_wakers: [Self::EVENT_WAKER; CONNS],
             })),
             canceled: Signal::new(),
         }
@@ -56,16 +83,45 @@ impl<M: RawMutex, const CONNS: usize> ConnectionManager<M, CONNS> {
         })
     }
 
-    pub(crate) fn disconnect(&self, h: ConnHandle) -> Result<(), Error> {
+    pub(crate) fn security_level(&self, h: ConnHandle) -> Result<SecurityLevel, Error> {
+        self.state.lock(|state| {
+            let state = state.borrow();
+            for storage in state.connections.iter() {
+                if storage.state != ConnectionState::Disconnected && storage.handle.unwrap() == h {
+                    return Ok(storage.security_level);
+                }
+            }
+            Err(Error::NotFound)
+        })
+    }
+
+    pub(crate) fn set_security_level(&self, h: ConnHandle, level: SecurityLevel) -> Result<(), Error> {
         self.state.lock(|state| {
             let mut state = state.borrow_mut();
-            for storage in state.connections.iter_mut() {
+            for (idx, storage) in state.connections.iter_mut().enumerate() {
+                if storage.state != ConnectionState::Disconnected && storage.handle.unwrap() == h {
+                    storage.security_level = level;
+                    state.post(idx, ConnectionEvent::SecurityChanged { level });
+                    return Ok(());
+                }
+            }
+            Err(Error::NotFound)
+        })
+    }
+
+    pub(crate) fn disconnect(&self, h: ConnHandle, reason: Status) -> Result<(), Error> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            for idx in 0..CONNS {
+                let storage = &mut state.connections[idx];
                 match storage.state {
-                    ConnectionState::Connecting if storage.handle.unwrap() == h => {
-                        storage.state = ConnectionState::Disconnected;
-                    }
-                    ConnectionState::Connected if storage.handle.unwrap() == h => {
+                    ConnectionState::Connecting | ConnectionState::Connected if storage.handle.unwrap() == h => {
+                        // The handle is kept so that the disconnection can still be observed by the application.
                         storage.state = ConnectionState::Disconnected;
+                        if storage.refs == 0 {
+                            state.free_waker.wake();
+                        }
+                        state.post(idx, ConnectionEvent::Disconnected { reason });
                     }
                     _ => {}
                 }
@@ -74,16 +130,59 @@ impl<M: RawMutex, const CONNS: usize> ConnectionManager<M, CONNS> {
         })
     }
 
+    /// Queue an event for a connection that has not been disconnected.
+    pub(crate) fn post_event(&self, h: ConnHandle, event: ConnectionEvent) -> Result<(), Error> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            for idx in 0..CONNS {
+                let storage = &state.connections[idx];
+                if storage.state != ConnectionState::Disconnected && storage.handle.unwrap() == h {
+                    state.post(idx, event);
+                    return Ok(());
+                }
+            }
+            Err(Error::NotFound)
+        })
+    }
+
+    /// Events of a connection remain available after it is disconnected, until its slot is recycled.
+    pub(crate) fn poll_next_event(
+        &self,
+        index: u8,
+        generation: u16,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<ConnectionEvent, Error>> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let idx = index as usize;
+            if state.connections[idx].generation != generation {
+                return Poll::Ready(Err(Error::Disconnected));
+            }
+            if let Some(event) = state.events[idx].pop_front() {
+                return Poll::Ready(Ok(event));
+            }
+            if state.connections[idx].state == ConnectionState::Disconnected {
+                return Poll::Ready(Err(Error::Disconnected));
+            }
+            state.event_wakers[idx].register(cx.waker());
+            Poll::Pending
+        })
+    }
+
     pub(crate) fn connect(&self, handle: ConnHandle, info: &LeConnectionComplete) -> Result<(), Error> {
         self.state.lock(|state| {
             let mut state = state.borrow_mut();
-            for storage in state.connections.iter_mut() {
-                if let ConnectionState::Disconnected = storage.state {
+            for (idx, storage) in state.connections.iter_mut().enumerate() {
+                // Slots of disconnected links are only recycled once every handle to them is dropped.
+                if storage.state == ConnectionState::Disconnected && storage.refs == 0 {
                     storage.state = ConnectionState::Connecting;
+                    storage.generation = storage.generation.wrapping_add(1);
                     storage.handle.replace(handle);
                     storage.peer_addr_kind.replace(info.peer_addr_kind);
                     storage.peer_addr.replace(info.peer_addr);
                     storage.role.replace(info.role);
+                    storage.security_level = SecurityLevel::NoEncryption;
+                    state.events[idx].clear();
                     state.waker.wake();
                     return Ok(());
                 }
@@ -101,22 +200,24 @@ impl<M: RawMutex, const CONNS: usize> ConnectionManager<M, CONNS> {
         self.canceled.signal(());
     }
 
-    pub(crate) fn poll_accept(&self, peers: &[(AddrKind, &BdAddr)], cx: &mut Context<'_>) -> Poll<ConnHandle> {
+    pub(crate) fn poll_accept(
+        &self,
+        peers: &[(AddrKind, &BdAddr)],
+        cx: &mut Context<'_>,
+    ) -> Poll<(u8, u16, ConnHandle)> {
         self.state.lock(|state| {
             let mut state = state.borrow_mut();
-            for storage in state.connections.iter_mut() {
+            for (idx, storage) in state.connections.iter_mut().enumerate() {
                 if let ConnectionState::Connecting = storage.state {
                     let handle = storage.handle.unwrap();
-                    if !peers.is_empty() {
-                        for peer in peers.iter() {
-                            if storage.peer_addr_kind.unwrap() == peer.0 && &storage.peer_addr.unwrap() == peer.1 {
-                                storage.state = ConnectionState::Connected;
-                                return Poll::Ready(handle);
-                            }
-                        }
-                    } else {
+                    let accepted = peers.is_empty()
+                        || peers.iter().any(|peer| {
+                            storage.peer_addr_kind.unwrap() == peer.0 && &storage.peer_addr.unwrap() == peer.1
+                        });
+                    if accepted {
                         storage.state = ConnectionState::Connected;
-                        return Poll::Ready(handle);
+                        storage.refs += 1;
+                        return Poll::Ready((idx as u8, storage.generation, handle));
                     }
                 }
             }
@@ -125,17 +226,98 @@ impl<M: RawMutex, const CONNS: usize> ConnectionManager<M, CONNS> {
         })
     }
 
-    pub(crate) async fn accept(&self, peers: &[(AddrKind, &BdAddr)]) -> ConnHandle {
-        poll_fn(move |cx| self.poll_accept(peers, cx)).await
+    /// Whether a slot is available for a new link.
+    pub(crate) fn has_free_slot(&self) -> bool {
+        self.state.lock(|state| {
+            let state = state.borrow();
+            state
+                .connections
+                .iter()
+                .any(|s| s.state == ConnectionState::Disconnected && s.refs == 0)
+        })
+    }
+
+    pub(crate) fn poll_free_slot(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            if state
+                .connections
+                .iter()
+                .any(|s| s.state == ConnectionState::Disconnected && s.refs == 0)
+            {
+                return Poll::Ready(());
+            }
+            state.free_waker.register(cx.waker());
+            Poll::Pending
+        })
+    }
+
+    /// Wait until a slot is available for a new link.
+    pub(crate) async fn wait_free_slot(&self) {
+        poll_fn(|cx| self.poll_free_slot(cx)).await
+    }
+
+    pub(crate) async fn accept(&self, peers: &[(AddrKind, &BdAddr)]) -> Connection<'_> {
+        let (index, generation, handle) = poll_fn(move |cx| self.poll_accept(peers, cx)).await;
+        Connection::new(index, generation, handle, self)
     }
 }
 
 pub trait DynamicConnectionManager {
     fn get_att_mtu(&self, conn: ConnHandle) -> u16;
     fn exchange_att_mtu(&self, conn: ConnHandle, mtu: u16) -> u16;
+    /// Handle of a connection slot, unless the link was disconnected since the slot was handed out.
+    fn handle(&self, index: u8, generation: u16) -> Result<ConnHandle, Error>;
+    /// Obtain a new reference to a connected link.
+    fn connection(&self, conn: ConnHandle) -> Option<Connection<'_>>;
+    fn inc_ref(&self, index: u8);
+    fn dec_ref(&self, index: u8);
 }
 
 impl<M: RawMutex, const CONNS: usize> DynamicConnectionManager for ConnectionManager<M, CONNS> {
+    fn handle(&self, index: u8, generation: u16) -> Result<ConnHandle, Error> {
+        self.state.lock(|state| {
+            let state = state.borrow();
+            let storage = &state.connections[index as usize];
+            match storage.state {
+                ConnectionState::Connected if storage.generation == generation => Ok(storage.handle.unwrap()),
+                _ => Err(Error::Disconnected),
+            }
+        })
+    }
+
+    fn connection(&self, conn: ConnHandle) -> Option<Connection<'_>> {
+        let (index, generation) = self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            for (idx, storage) in state.connections.iter_mut().enumerate() {
+                if storage.state == ConnectionState::Connected && storage.handle.unwrap() == conn {
+                    storage.refs += 1;
+                    return Some((idx as u8, storage.generation));
+                }
+            }
+            None
+        })?;
+        Some(Connection::new(index, generation, conn, self))
+    }
+
+    fn inc_ref(&self, index: u8) {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            state.connections[index as usize].refs += 1;
+        })
+    }
+
+    fn dec_ref(&self, index: u8) {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let storage = &mut state.connections[index as usize];
+            storage.refs = storage.refs.saturating_sub(1);
+            if storage.refs == 0 && storage.state == ConnectionState::Disconnected {
+                state.free_waker.wake();
+            }
+        })
+    }
+
     fn get_att_mtu(&self, conn: ConnHandle) -> u16 {
         self.state.lock(|state| {
             let mut state = state.borrow_mut();
@@ -153,11 +335,17 @@ impl<M: RawMutex, const CONNS: usize> DynamicConnectionManager for ConnectionMan
     fn exchange_att_mtu(&self, conn: ConnHandle, mtu: u16) -> u16 {
         self.state.lock(|state| {
             let mut state = state.borrow_mut();
-            for storage in state.connections.iter_mut() {
+            for idx in 0..CONNS {
+                let storage = &mut state.connections[idx];
                 match storage.state {
                     ConnectionState::Connected if storage.handle.unwrap() == conn => {
+                        let previous = storage.att_mtu;
                         storage.att_mtu = storage.att_mtu.min(mtu);
-                        return storage.att_mtu;
+                        let mtu = storage.att_mtu;
+                        if mtu != previous {
+                            state.post(idx, ConnectionEvent::AttMtuChanged { mtu });
+                        }
+                        return mtu;
                     }
                     _ => {}
                 }
@@ -176,6 +364,11 @@ pub struct ConnectionStorage {
     pub peer_addr_kind: Option<AddrKind>,
     pub peer_addr: Option<BdAddr>,
     pub att_mtu: u16,
+    pub security_level: SecurityLevel,
+    /// Number of `Connection` handles referring to this slot.
+    pub refs: u16,
+    /// Incremented every time the slot is used for a new link.
+    pub generation: u16,
 }
 
 impl ConnectionStorage {
@@ -186,6 +379,9 @@ impl ConnectionStorage {
         peer_addr_kind: None,
         peer_addr: None,
         att_mtu: 23,
+        security_level: SecurityLevel::NoEncryption,
+        refs: 0,
+        generation: 0,
     };
 }
 
@@ -196,3 +392,186 @@ pub enum ConnectionState {
     Connecting,
     Connected,
 }
+
+#[cfg(test)]
+mod tests {
+    use bt_hci::FromHciBytes;
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::mock_controller::connect;
+
+    type Manager = ConnectionManager<NoopRawMutex, 2>;
+
+    fn next_event(manager: &Manager, index: u8, generation: u16) -> Result<ConnectionEvent, Error> {
+        block_on(poll_fn(|cx| manager.poll_next_event(index, generation, cx)))
+    }
+
+    fn pending_event(manager: &Manager, index: u8, generation: u16) -> bool {
+        embassy_futures::poll_once(poll_fn(|cx| manager.poll_next_event(index, generation, cx))).is_pending()
+    }
+
+    fn remote_user_terminated() -> Status {
+        Status::from_hci_bytes_complete(&[0x13]).unwrap()
+    }
+
+    #[test]
+    fn test_connection_events() {
+        let manager = Manager::new();
+        connect(&manager, 1, LeConnRole::Peripheral).unwrap();
+        let (index, generation, handle) = block_on(poll_fn(|cx| manager.poll_accept(&[], cx)));
+        assert!(pending_event(&manager, index, generation));
+
+        manager.set_security_level(handle, SecurityLevel::Encrypted).unwrap();
+        assert_eq!(manager.exchange_att_mtu(handle, 23), 23);
+        manager
+            .state
+            .lock(|state| state.borrow_mut().connections[index as usize].att_mtu = 247);
+        assert_eq!(manager.exchange_att_mtu(handle, 100), 100);
+
+        // Events are reported in order, the unchanged ATT MTU is not reported.
+        assert!(matches!(
+            next_event(&manager, index, generation),
+            Ok(ConnectionEvent::SecurityChanged {
+                level: SecurityLevel::Encrypted
+            })
+        ));
+        assert!(matches!(
+            next_event(&manager, index, generation),
+            Ok(ConnectionEvent::AttMtuChanged { mtu: 100 })
+        ));
+        assert!(pending_event(&manager, index, generation));
+        assert!(manager
+            .post_event(ConnHandle::new(2), ConnectionEvent::AttMtuChanged { mtu: 50 })
+            .is_err());
+    }
+
+    #[test]
+    fn test_connection_event_queue_full() {
+        let manager = Manager::new();
+        connect(&manager, 1, LeConnRole::Peripheral).unwrap();
+        let (index, generation, handle) = block_on(poll_fn(|cx| manager.poll_accept(&[], cx)));
+
+        // The oldest events are dropped.
+        for mtu in 0..EVENT_QUEUE_LEN as u16 + 2 {
+            manager
+                .post_event(handle, ConnectionEvent::AttMtuChanged { mtu })
+                .unwrap();
+        }
+        for expected in 2..EVENT_QUEUE_LEN as u16 + 2 {
+            match next_event(&manager, index, generation) {
+                Ok(ConnectionEvent::AttMtuChanged { mtu }) => assert_eq!(mtu, expected),
+                _ => panic!("unexpected event"),
+            }
+        }
+        assert!(pending_event(&manager, index, generation));
+    }
+
+    #[test]
+    fn test_disconnection_event() {
+        let manager = Manager::new();
+        connect(&manager, 1, LeConnRole::Central).unwrap();
+        let (index, generation, handle) = block_on(poll_fn(|cx| manager.poll_accept(&[], cx)));
+
+        manager
+            .post_event(handle, ConnectionEvent::AttMtuChanged { mtu: 50 })
+            .unwrap();
+        manager.disconnect(handle, remote_user_terminated()).unwrap();
+        // No events are queued once disconnected.
+        assert!(manager
+            .post_event(handle, ConnectionEvent::AttMtuChanged { mtu: 60 })
+            .is_err());
+
+        // Pending events are still reported, followed by the disconnection.
+        assert!(matches!(
+            next_event(&manager, index, generation),
+            Ok(ConnectionEvent::AttMtuChanged { mtu: 50 })
+        ));
+        match next_event(&manager, index, generation) {
+            Ok(ConnectionEvent::Disconnected { reason }) => assert_eq!(reason, remote_user_terminated()),
+            _ => panic!("expected disconnection"),
+        }
+        assert!(matches!(
+            next_event(&manager, index, generation),
+            Err(Error::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn test_stale_connection() {
+        let manager = Manager::new();
+        connect(&manager, 1, LeConnRole::Peripheral).unwrap();
+        let connection = block_on(manager.accept(&[]));
+        let clone = connection.clone();
+        assert!(connection.is_connected());
+
+        manager
+            .disconnect(connection.handle(), remote_user_terminated())
+            .unwrap();
+        assert!(!connection.is_connected());
+        assert!(matches!(clone.live_handle(), Err(Error::Disconnected)));
+        assert!(manager.connection(ConnHandle::new(1)).is_none());
+
+        // The controller reuses the handle for another link, in another slot.
+        connect(&manager, 1, LeConnRole::Peripheral).unwrap();
+        let other = block_on(manager.accept(&[]));
+        assert_eq!(other.handle(), connection.handle());
+        assert!(other.is_connected());
+        assert!(matches!(connection.live_handle(), Err(Error::Disconnected)));
+        assert!(manager.connection(ConnHandle::new(1)).is_some());
+    }
+
+    #[test]
+    fn test_slot_recycling() {
+        let manager = Manager::new();
+        connect(&manager, 1, LeConnRole::Peripheral).unwrap();
+        connect(&manager, 2, LeConnRole::Peripheral).unwrap();
+        let first = block_on(manager.accept(&[]));
+        let second = block_on(manager.accept(&[]));
+        assert!(!manager.has_free_slot());
+        assert!(connect(&manager, 3, LeConnRole::Peripheral).is_err());
+
+        // The slot is only released once every handle has been dropped.
+        let clone = first.clone();
+        manager.disconnect(first.handle(), remote_user_terminated()).unwrap();
+        assert!(!manager.has_free_slot());
+        drop(first);
+        assert!(!manager.has_free_slot());
+        drop(clone);
+        assert!(manager.has_free_slot());
+
+        // A new link gets a new generation of the slot, events of the previous link are gone.
+        connect(&manager, 3, LeConnRole::Peripheral).unwrap();
+        let (index, generation, handle) = block_on(poll_fn(|cx| manager.poll_accept(&[], cx)));
+        assert_eq!(handle, ConnHandle::new(3));
+        assert_eq!((index, generation), (0, 2));
+        assert!(matches!(manager.handle(0, 1), Err(Error::Disconnected)));
+        assert!(matches!(manager.handle(0, 2), Ok(h) if h == handle));
+        assert!(matches!(next_event(&manager, 0, 1), Err(Error::Disconnected)));
+        assert!(pending_event(&manager, 0, 2));
+        drop(second);
+    }
+
+    #[test]
+    fn test_free_slot_wakeup() {
+        let manager: ConnectionManager<NoopRawMutex, 1> = ConnectionManager::new();
+        connect(&manager, 1, LeConnRole::Peripheral).unwrap();
+        let connection = block_on(manager.accept(&[]));
+        assert!(embassy_futures::poll_once(manager.wait_free_slot()).is_pending());
+
+        let (_, freed) = block_on(embassy_futures::join::join(manager.wait_free_slot(), async {
+            embassy_futures::yield_now().await;
+            manager
+                .disconnect(connection.handle(), remote_user_terminated())
+                .unwrap();
+            embassy_futures::yield_now().await;
+            let freed = manager.has_free_slot();
+            drop(connection);
+            freed
+        }));
+        // Waiting completes once the last handle of the disconnected link is dropped.
+        assert!(!freed);
+        assert!(manager.has_free_slot());
+    }
+}
@@ -1,21 +1,31 @@
 use core::fmt;
+use core::future::poll_fn;
 
 use bt_hci::controller::Controller;
 use bt_hci::param::ConnHandle;
+use embassy_futures::select::{select, Either};
 use embassy_sync::blocking_mutex::raw::RawMutex;
 use embassy_sync::channel::DynamicReceiver;
+use heapless::Vec;
 
-use crate::adapter::HciController;
-use crate::att::{self, Att, ATT_HANDLE_VALUE_NTF_OPTCODE};
+use crate::adapter::{Adapter, HciController};
+use crate::att::{self, Att, AttErrorCode, ATT_HANDLE_VALUE_IND_OPCODE, ATT_HANDLE_VALUE_NTF_OPTCODE};
 use crate::attribute::CharacteristicHandle;
 use crate::attribute_server::AttributeServer;
 use crate::connection::Connection;
 use crate::connection_manager::DynamicConnectionManager;
 use crate::cursor::WriteCursor;
+use crate::l2cap::{L2capChannel, L2capChannelConfig, L2CAP_ECFC_MAX_CHANNELS};
 use crate::packet_pool::{AllocId, DynamicPacketPool};
 use crate::pdu::Pdu;
 use crate::{AdapterError, Error};
 
+/// PSM of the L2CAP channels used as Enhanced ATT (EATT) bearers.
+///
+/// Only the server role of EATT is supported: bearers created by either side serve requests of the peer's GATT
+/// client, and carry notifications of this server.
+pub const EATT_PSM: u16 = 0x0027;
+
 pub struct GattServer<'reference, 'values, 'resources, M: RawMutex, T: Controller, const MAX: usize> {
     pub(crate) server: AttributeServer<'reference, 'values, M, MAX>,
     pub(crate) rx: DynamicReceiver<'reference, (ConnHandle, Pdu<'resources>)>,
@@ -28,11 +38,27 @@ pub struct GattServer<'reference, 'values, 'resources, M: RawMutex, T: Controlle
 impl<'reference, 'values, 'resources, M: RawMutex, T: Controller, const MAX: usize>
     GattServer<'reference, 'values, 'resources, M, T, MAX>
 {
-    pub async fn next(&self) -> Result<GattEvent<'reference, 'values>, AdapterError<T::Error>> {
+    /// Process incoming ATT requests until a client writes a characteristic value.
+    ///
+    /// While processing requests, clients which have subscribed to the Service Changed characteristic are sent
+    /// an indication whenever services are added to or removed from the attribute table.
+    pub async fn next(&self) -> Result<GattEvent<'reference>, AdapterError<T::Error>> {
         loop {
-            let (handle, pdu) = self.rx.receive().await;
+            let service_changed = poll_fn(|cx| self.server.poll_service_changed(cx));
+            let (handle, pdu) = match select(self.rx.receive(), service_changed).await {
+                Either::First(request) => request,
+                Either::Second(changes) => {
+                    self.indicate_service_changed(changes).await?;
+                    continue;
+                }
+            };
             match Att::decode(pdu.as_ref()) {
                 Ok(att) => {
+                    let written = match &att {
+                        Att::WriteReq { handle, .. } => Some(*handle),
+                        _ => None,
+                    };
+
                     let Some(mut response) = self.pool.alloc(self.pool_id) else {
                         return Err(Error::OutOfMemory.into());
                     };
@@ -50,15 +76,41 @@ impl<'reference, 'values, 'resources, M: RawMutex, T: Controller, const MAX: usi
                             let len = header.len() + data.len();
                             self.tx.send(handle, Pdu::new(response, len).as_ref()).await?;
                         }
+                        Att::WriteCmd { handle: value, data } => {
+                            // Write commands have no response, so only successful writes are reported
+                            if self.server.handle_write_cmd(value, data) {
+                                if let Some(event) = self.write_event(handle, value) {
+                                    return Ok(event);
+                                }
+                            }
+                        }
+                        Att::HandleValueConfirmation => {
+                            let Some(connection) = self.connections.connection(handle) else {
+                                continue;
+                            };
+                            // Services changed again while the indication was outstanding
+                            if self.server.confirm_indication(&connection)
+                                && matches!(self.server.start_indication(&connection, self.connections), Ok(true))
+                            {
+                                self.send_service_changed(handle, true).await?;
+                            }
+                        }
                         _ => match self.server.process(handle, att, data.write_buf()) {
-                            Ok(Some(written)) => {
+                            Ok(Some(len)) => {
                                 let mtu = self.connections.get_att_mtu(handle);
-                                data.commit(written)?;
+                                let success = data.write_buf()[0] != att::ATT_ERROR_RESPONSE_OPCODE;
+                                data.commit(len)?;
                                 data.truncate(mtu as usize);
-                                header.write(written as u16)?;
+                                header.write(len as u16)?;
                                 header.write(4_u16)?;
                                 let len = header.len() + data.len();
                                 self.tx.send(handle, Pdu::new(response, len).as_ref()).await?;
+
+                                if let Some(event) =
+                                    written.filter(|_| success).and_then(|w| self.write_event(handle, w))
+                                {
+                                    return Ok(event);
+                                }
                             }
                             Ok(None) => {
                                 debug!("No response sent");
@@ -76,6 +128,158 @@ impl<'reference, 'values, 'resources, M: RawMutex, T: Controller, const MAX: usi
         }
     }
 
+    /// Create up to five Enhanced ATT bearers to a connection, using an enhanced credit based connection request.
+    ///
+    /// The peer may accept fewer bearers than requested. Requests of the peer on each bearer are processed with
+    /// [`GattServer::next_eatt`].
+    pub async fn create_eatt<
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        connection: &Connection<'_>,
+        count: usize,
+        config: &L2capChannelConfig,
+    ) -> Result<Vec<L2capChannel, L2CAP_ECFC_MAX_CHANNELS>, AdapterError<T::Error>> {
+        L2capChannel::create_multiple(adapter, connection, EATT_PSM, count, config).await
+    }
+
+    /// Await Enhanced ATT bearers created by a connection, accepting every bearer of the request.
+    ///
+    /// [`EATT_PSM`] must be registered with [`Adapter::register_psm`] first.
+    pub async fn accept_eatt<
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        connection: &Connection<'_>,
+        config: &L2capChannelConfig,
+    ) -> Result<Vec<L2capChannel, L2CAP_ECFC_MAX_CHANNELS>, AdapterError<T::Error>> {
+        L2capChannel::accept_multiple(adapter, connection, &[EATT_PSM], config).await
+    }
+
+    /// Process incoming ATT requests on an Enhanced ATT bearer until a client writes a characteristic value.
+    ///
+    /// An EATT bearer is an L2CAP channel on [`EATT_PSM`], created with [`GattServer::create_eatt`] or accepted with
+    /// [`GattServer::accept_eatt`]. Each bearer
+    /// processes requests independently of the other bearers and of the fixed ATT channel, so a connection may have
+    /// multiple requests outstanding by running one task per bearer.
+    pub async fn next_eatt<
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        bearer: &mut L2capChannel,
+    ) -> Result<GattEvent<'reference>, AdapterError<T::Error>> {
+        loop {
+            let Some(mut request) = self.pool.alloc(self.pool_id) else {
+                return Err(Error::OutOfMemory.into());
+            };
+            let len = bearer.receive(adapter, request.as_mut()).await?;
+            let (conn, mtu) = adapter.channels.bearer_params(bearer.cid())?;
+
+            let att = match Att::decode(&request.as_ref()[..len]) {
+                Ok(att) => att,
+                Err(e) => {
+                    warn!("Error decoding attribute request: {:?}", e);
+                    continue;
+                }
+            };
+            let written = match &att {
+                Att::WriteReq { handle, .. } => Some(*handle),
+                _ => None,
+            };
+
+            let Some(mut response) = self.pool.alloc(self.pool_id) else {
+                return Err(Error::OutOfMemory.into());
+            };
+            let result = match att {
+                // The MTU of an EATT bearer is the MTU of the L2CAP channel
+                Att::ExchangeMtu { .. } => AttributeServer::<M, MAX>::error_response(
+                    WriteCursor::new(response.as_mut()),
+                    att::ATT_EXCHANGE_MTU_REQUEST_OPCODE,
+                    0,
+                    AttErrorCode::RequestNotSupported,
+                )
+                .map(Some)
+                .map_err(Into::into),
+                Att::WriteCmd { handle, data } => {
+                    // Write commands have no response, so only successful writes are reported
+                    if self.server.handle_write_cmd(handle, data) {
+                        if let Some(event) = self.write_event(conn, handle) {
+                            return Ok(event);
+                        }
+                    }
+                    continue;
+                }
+                att => self.server.process(conn, att, response.as_mut()),
+            };
+
+            match result {
+                Ok(Some(len)) => {
+                    let success = response.as_ref()[0] != att::ATT_ERROR_RESPONSE_OPCODE;
+                    let len = len.min(mtu as usize);
+                    bearer.send(adapter, &response.as_ref()[..len]).await?;
+
+                    if let Some(event) = written.filter(|_| success).and_then(|w| self.write_event(conn, w)) {
+                        return Ok(event);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("Error processing attribute: {:?}", e);
+                }
+            }
+        }
+    }
+
+    /// Notify the connection of an Enhanced ATT bearer with the current value of a characteristic, if it has
+    /// subscribed to it.
+    ///
+    /// Notifications sent on a bearer are not blocked by requests outstanding on other bearers.
+    pub async fn notify_eatt<
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        bearer: &mut L2capChannel,
+        handle: CharacteristicHandle,
+    ) -> Result<(), AdapterError<T::Error>> {
+        let (conn, mtu) = adapter.channels.bearer_params(bearer.cid())?;
+        let cccd_handle = handle.cccd_handle.ok_or(Error::Other)?;
+        if !self.server.should_notify(conn, cccd_handle) {
+            return Ok(());
+        }
+
+        let Some(mut packet) = self.pool.alloc(self.pool_id) else {
+            return Err(Error::OutOfMemory.into());
+        };
+        let len = self.encode_value(ATT_HANDLE_VALUE_NTF_OPTCODE, handle, packet.as_mut())?;
+        bearer.send(adapter, &packet.as_ref()[..len.min(mtu as usize)]).await
+    }
+
+    fn write_event(&self, conn: ConnHandle, handle: u16) -> Option<GattEvent<'reference>> {
+        let handle = self.server.table.find_characteristic(handle)?;
+        let connection = self.connections.connection(conn)?;
+        Some(GattEvent::Write { connection, handle })
+    }
+
     /// Write a value to a characteristic, and notify a connection with the new value of the characteristic.
     ///
     /// If the provided connection has not subscribed for this characteristic, it will not be notified.
@@ -84,12 +288,97 @@ impl<'reference, 'values, 'resources, M: RawMutex, T: Controller, const MAX: usi
     pub async fn notify(
         &self,
         handle: CharacteristicHandle,
-        connection: &Connection,
+        connection: &Connection<'_>,
+        value: &[u8],
+    ) -> Result<(), AdapterError<T::Error>> {
+        self.server.table.set(handle, value)?;
+        self.send_notification(handle, connection).await
+    }
+
+    /// Notify a connection with the current value of a characteristic, if it has subscribed to it.
+    pub(crate) async fn send_notification(
+        &self,
+        handle: CharacteristicHandle,
+        connection: &Connection<'_>,
+    ) -> Result<(), AdapterError<T::Error>> {
+        self.send_value(ATT_HANDLE_VALUE_NTF_OPTCODE, handle, connection).await
+    }
+
+    /// Write a value to a characteristic, and indicate the new value of the characteristic to a connection.
+    ///
+    /// If the provided connection has not subscribed for this characteristic, it will not be indicated. Only one
+    /// indication may await confirmation by a connection at a time, so [`Error::Busy`] is returned until the
+    /// previous indication has been confirmed.
+    ///
+    /// If the characteristic for the handle cannot be found, an error is returned.
+    pub async fn indicate(
+        &self,
+        handle: CharacteristicHandle,
+        connection: &Connection<'_>,
         value: &[u8],
     ) -> Result<(), AdapterError<T::Error>> {
-        let conn = connection.handle();
         self.server.table.set(handle, value)?;
+        self.send_indication(handle, connection).await
+    }
+
+    /// Indicate the current value of a characteristic to a connection, if it has subscribed to it.
+    pub(crate) async fn send_indication(
+        &self,
+        handle: CharacteristicHandle,
+        connection: &Connection<'_>,
+    ) -> Result<(), AdapterError<T::Error>> {
+        self.send_value(ATT_HANDLE_VALUE_IND_OPCODE, handle, connection).await
+    }
+
+    /// Indicate a change of services to the connections which have subscribed to the Service Changed
+    /// characteristic.
+    async fn indicate_service_changed(&self, changes: u32) -> Result<(), AdapterError<T::Error>> {
+        for conn in self.server.table.service_changed_subscribers() {
+            let Some(connection) = self.connections.connection(conn) else {
+                continue;
+            };
+            match self.server.start_indication(&connection, self.connections) {
+                Ok(true) => self.send_service_changed(conn, changes > 1).await?,
+                Ok(false) => {}
+                Err(e) => warn!("Unable to indicate service changes to {:?}: {:?}", conn, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a Service Changed indication with the handle range of the latest change, or with the whole handle range
+    /// if multiple changes are indicated at once.
+    async fn send_service_changed(&self, conn: ConnHandle, all: bool) -> Result<(), AdapterError<T::Error>> {
+        let handle = self.server.table.service_changed_handle().ok_or(Error::NotFound)?;
+        let Some(mut packet) = self.pool.alloc(self.pool_id) else {
+            return Err(Error::OutOfMemory.into());
+        };
+        let mut w = WriteCursor::new(packet.as_mut());
+        let (mut header, mut data) = w.split(4)?;
+        if all {
+            data.write(ATT_HANDLE_VALUE_IND_OPCODE)?;
+            data.write(handle.handle)?;
+            data.write(0x0001_u16)?;
+            data.write(0xffff_u16)?;
+        } else {
+            let len = self.encode_value(ATT_HANDLE_VALUE_IND_OPCODE, handle, data.write_buf())?;
+            data.commit(len)?;
+        }
+
+        header.write(data.len() as u16)?;
+        header.write(4_u16)?;
+        let total = header.len() + data.len();
+        self.tx.send(conn, Pdu::new(packet, total).as_ref()).await?;
+        Ok(())
+    }
 
+    async fn send_value(
+        &self,
+        opcode: u8,
+        handle: CharacteristicHandle,
+        connection: &Connection<'_>,
+    ) -> Result<(), AdapterError<T::Error>> {
+        let conn = connection.live_handle()?;
         let cccd_handle = handle.cccd_handle.ok_or(Error::Other)?;
 
         if !self.server.should_notify(conn, cccd_handle) {
@@ -97,46 +386,69 @@ impl<'reference, 'values, 'resources, M: RawMutex, T: Controller, const MAX: usi
             return Ok(());
         }
 
+        let indication = opcode == ATT_HANDLE_VALUE_IND_OPCODE;
+        if indication && !self.server.start_value_indication(connection, self.connections)? {
+            return Err(Error::Busy.into());
+        }
+
         let Some(mut packet) = self.pool.alloc(self.pool_id) else {
             return Err(Error::OutOfMemory.into());
         };
         let mut w = WriteCursor::new(packet.as_mut());
         let (mut header, mut data) = w.split(4)?;
-        data.write(ATT_HANDLE_VALUE_NTF_OPTCODE)?;
-        data.write(handle.handle)?;
-        data.append(value)?;
+        let len = self.encode_value(opcode, handle, data.write_buf())?;
+        data.commit(len)?;
 
         header.write(data.len() as u16)?;
         header.write(4_u16)?;
         let total = header.len() + data.len();
-        self.tx.send(conn, Pdu::new(packet, total).as_ref()).await?;
+        let result = self.tx.send(conn, Pdu::new(packet, total).as_ref()).await;
+        if indication && result.is_err() {
+            // No confirmation will arrive for an indication which was not sent
+            self.server.confirm_indication(connection);
+        }
+        result?;
         Ok(())
     }
+
+    /// Encode a handle value notification or indication with the current value of a characteristic.
+    fn encode_value(&self, opcode: u8, handle: CharacteristicHandle, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut w = WriteCursor::new(buf);
+        w.write(opcode)?;
+        w.write(handle.handle)?;
+        self.server.table.get(handle, |value| w.append(value))??;
+        Ok(w.len())
+    }
 }
 
+/// An event produced by the GATT server.
 #[derive(Clone)]
-pub enum GattEvent<'reference, 'values> {
+pub enum GattEvent<'d> {
+    /// A client wrote the value of a characteristic.
+    ///
+    /// The event holds the connection of the client and the handle of the written characteristic. The new value
+    /// is stored in the attribute table, where it can be read using the handle, or decoded into a typed event by
+    /// services declared with `#[gatt_service]`.
     Write {
-        connection: &'reference Connection,
+        connection: Connection<'d>,
         handle: CharacteristicHandle,
-        value: &'values [u8],
     },
 }
 
-impl<'reference, 'values> fmt::Debug for GattEvent<'reference, 'values> {
+impl<'d> fmt::Debug for GattEvent<'d> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Write {
-                connection: _,
-                handle: _,
-                value: _,
-            } => f.debug_struct("GattEvent::Write").finish(),
+            Self::Write { connection, handle } => f
+                .debug_struct("GattEvent::Write")
+                .field("connection", &connection.handle())
+                .field("handle", handle)
+                .finish(),
         }
     }
 }
 
 #[cfg(feature = "defmt")]
-impl<'reference, 'values> defmt::Format for GattEvent<'reference, 'values> {
+impl<'d> defmt::Format for GattEvent<'d> {
     fn format(&self, fmt: defmt::Formatter) {
         defmt::write!(fmt, "{}", defmt::Debug2Format(self))
     }
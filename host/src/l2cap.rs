@@ -1,15 +1,34 @@
+use core::future::poll_fn;
+
 use bt_hci::cmd::link_control::Disconnect;
 use bt_hci::controller::{Controller, ControllerCmdSync};
 use bt_hci::param::DisconnectReason;
 use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::Duration;
+use heapless::Vec;
 
 use crate::adapter::Adapter;
-pub use crate::channel_manager::CreditFlowPolicy;
+use crate::channel_manager::DynamicChannelManager;
+pub use crate::channel_manager::{CreditFlowPolicy, L2capChannelStats, L2capPacket, PsmRegistration};
 use crate::connection::Connection;
-use crate::AdapterError;
+use crate::pdu::Pdu;
+pub use crate::types::l2cap::{
+    LeCreditConnResultCode, L2CAP_CID_FIXED_CUSTOM_END, L2CAP_CID_FIXED_CUSTOM_START, L2CAP_ECFC_MAX_CHANNELS,
+    L2CAP_RTX_TIMEOUT_DEFAULT, L2CAP_RTX_TIMEOUT_MAX, L2CAP_RTX_TIMEOUT_MIN,
+};
+use crate::{AdapterError, Error};
 
 pub(crate) mod sar;
 
+/// Handler for PDUs received on a custom fixed channel.
+///
+/// Handlers are registered with [`Adapter::register_fixed_channel`] and replies are sent with
+/// [`Adapter::send_fixed`].
+pub trait FixedChannelHandler {
+    /// Called from the adapter run loop with a PDU reassembled from the ACL fragments received on `cid`.
+    fn on_pdu(&self, connection: &Connection<'_>, cid: u16, pdu: &[u8]);
+}
+
 /// Handle representing an L2CAP channel.
 #[derive(Clone)]
 pub struct L2capChannel {
@@ -25,6 +44,9 @@ pub struct L2capChannelConfig {
     pub flow_policy: CreditFlowPolicy,
     /// Initial credits for connection oriented channels.
     pub initial_credits: Option<u16>,
+    /// Time to wait for the peer to answer signalling requests for this channel, between
+    /// [`L2CAP_RTX_TIMEOUT_MIN`] and [`L2CAP_RTX_TIMEOUT_MAX`].
+    pub timeout: Duration,
 }
 
 impl Default for L2capChannelConfig {
@@ -33,11 +55,47 @@ impl Default for L2capChannelConfig {
             mtu: 23,
             flow_policy: Default::default(),
             initial_credits: None,
+            timeout: L2CAP_RTX_TIMEOUT_DEFAULT,
         }
     }
 }
 
 impl L2capChannel {
+    pub(crate) fn cid(&self) -> u16 {
+        self.cid
+    }
+
+    /// Bind the channel to its adapter, providing byte stream semantics through the
+    /// `embedded_io_async` traits.
+    pub fn bind<
+        'a,
+        'd,
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        self,
+        adapter: &'a Adapter<'d, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+    ) -> L2capStream<'a, 'd, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ> {
+        L2capStream {
+            adapter,
+            cid: self.cid,
+            rx: None,
+            sdu_remaining: 0,
+        }
+    }
+
+    /// Split the channel into a reader and a writer that can be used from separate tasks.
+    ///
+    /// Both halves observe the channel being disconnected, returning `Error::ChannelClosed`.
+    pub fn split(self) -> (L2capReader, L2capWriter) {
+        (L2capReader { channel: self.clone() }, L2capWriter { channel: self })
+    }
+
     /// Send the provided buffer over this l2cap channel.
     ///
     /// The buffer will be segmented to the maximum payload size agreed in the opening handshake.
@@ -84,7 +142,8 @@ impl L2capChannel {
 
     /// Receive data on this channel and copy it into the buffer.
     ///
-    /// The length provided buffer slice must be equal or greater to the agreed MTU.
+    /// The length provided buffer slice must be equal or greater to the agreed MTU, otherwise
+    /// `Error::InsufficientSpace` is returned before any data is consumed.
     pub async fn receive<
         M: RawMutex,
         T: Controller,
@@ -101,7 +160,152 @@ impl L2capChannel {
         adapter.channels.receive(self.cid, buf, &adapter.hci()).await
     }
 
+    /// Receive an SDU on this channel without copying it out of the adapter packet pool.
+    ///
+    /// The SDU must fit in a single pool packet. Once the packet is dropped, the peer is credited for
+    /// it according to the channel [`CreditFlowPolicy`] by the next call receiving on the channel.
+    pub async fn receive_packet<
+        'a,
+        'd,
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &mut self,
+        adapter: &'a Adapter<'d, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+    ) -> Result<L2capPacket<'a, 'd>, AdapterError<T::Error>> {
+        adapter.channels.receive_packet(self.cid, &adapter.hci()).await
+    }
+
+    /// Allocate a packet from the adapter packet pool to be filled and sent with [`L2capChannel::send_packet`].
+    ///
+    /// Returns `Error::OutOfMemory` if the pool has no packet available for sending.
+    pub fn alloc_packet<
+        'a,
+        'd,
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &mut self,
+        adapter: &'a Adapter<'d, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+    ) -> Result<L2capPacket<'a, 'd>, AdapterError<T::Error>> {
+        Ok(adapter.channels.alloc_packet(self.cid)?)
+    }
+
+    /// Send an SDU held in a pool packet over this l2cap channel.
+    ///
+    /// SDUs fitting in a single K-frame are sent without being copied, others are segmented like
+    /// with [`L2capChannel::send`].
+    pub async fn send_packet<
+        'a,
+        'd,
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &mut self,
+        adapter: &'a Adapter<'d, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        packet: L2capPacket<'a, 'd>,
+    ) -> Result<(), AdapterError<T::Error>> {
+        adapter.channels.send_packet(self.cid, packet, &adapter.hci()).await
+    }
+
+    /// The largest SDU that can be sent and received on this channel, the smallest of both sides.
+    pub fn mtu<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+    ) -> Result<u16, AdapterError<T::Error>> {
+        Ok(adapter.channels.negotiated(self.cid)?.0)
+    }
+
+    /// The largest K-frame payload that can be sent and received on this channel, the smallest of both sides.
+    pub fn mps<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+    ) -> Result<u16, AdapterError<T::Error>> {
+        Ok(adapter.channels.negotiated(self.cid)?.1)
+    }
+
+    /// Number of K-frames the peer currently allows us to send.
+    pub fn peer_credits<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+    ) -> Result<u16, AdapterError<T::Error>> {
+        Ok(adapter.channels.peer_credits(self.cid)?)
+    }
+
+    /// Traffic counters of this channel, available until the channel is released.
+    pub fn stats<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+    ) -> Result<L2capChannelStats, AdapterError<T::Error>> {
+        Ok(adapter.channels.stats(self.cid)?)
+    }
+
+    /// Wait until this channel is disconnected, either locally or by the peer.
+    pub async fn wait_disconnected<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+    ) {
+        poll_fn(|cx| adapter.channels.poll_disconnected(self.cid, cx)).await
+    }
+
     /// Await an incoming connection request matching the list of PSM.
+    ///
+    /// Every PSM must be registered with [`Adapter::register_psm`] first, otherwise `Error::NotFound` is returned.
     pub async fn accept<
         M: RawMutex,
         T: Controller,
@@ -112,12 +316,12 @@ impl L2capChannel {
         const L2CAP_RXQ: usize,
     >(
         adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
-        connection: &Connection,
+        connection: &Connection<'_>,
         psm: &[u16],
         config: &L2capChannelConfig,
     ) -> Result<L2capChannel, AdapterError<T::Error>> {
-        let handle = connection.handle();
-        let cid = adapter
+        let handle = connection.live_handle()?;
+        let cids = adapter
             .channels
             .accept(
                 handle,
@@ -125,15 +329,56 @@ impl L2capChannel {
                 config.mtu,
                 config.flow_policy,
                 config.initial_credits,
+                1,
+                config.timeout,
                 &adapter.hci(),
             )
             .await?;
 
-        Ok(Self { cid })
+        Ok(Self { cid: cids[0] })
+    }
+
+    /// Await an incoming connection request matching the list of PSM, accepting every channel
+    /// of an enhanced credit based request.
+    ///
+    /// Every PSM must be registered with [`Adapter::register_psm`] first, otherwise `Error::NotFound` is returned.
+    pub async fn accept_multiple<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        connection: &Connection<'_>,
+        psm: &[u16],
+        config: &L2capChannelConfig,
+    ) -> Result<Vec<L2capChannel, L2CAP_ECFC_MAX_CHANNELS>, AdapterError<T::Error>> {
+        let cids = adapter
+            .channels
+            .accept(
+                connection.live_handle()?,
+                psm,
+                config.mtu,
+                config.flow_policy,
+                config.initial_credits,
+                L2CAP_ECFC_MAX_CHANNELS,
+                config.timeout,
+                &adapter.hci(),
+            )
+            .await?;
+
+        Ok(cids.iter().map(|cid| Self { cid: *cid }).collect())
     }
 
     /// Disconnect this channel.
-    pub fn disconnect<
+    ///
+    /// The disconnection request is sent again if the peer does not answer in time, after which the
+    /// channel is released and `Error::Timeout` is returned. Completes once the peer has confirmed
+    /// the disconnection.
+    pub async fn disconnect<
         M: RawMutex,
         T: Controller + ControllerCmdSync<Disconnect>,
         const CONNS: usize,
@@ -146,14 +391,18 @@ impl L2capChannel {
         adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
         close_connection: bool,
     ) -> Result<(), AdapterError<T::Error>> {
-        let handle = adapter.channels.disconnect(self.cid)?;
+        let handle = adapter.channels.channel_conn(self.cid)?;
+        let result = adapter.channels.close(self.cid, &adapter.hci()).await;
         if close_connection {
             adapter.try_command(Disconnect::new(handle, DisconnectReason::RemoteUserTerminatedConn))?;
         }
-        Ok(())
+        result.map(|_| ())
     }
 
     /// Create a new connection request with the provided PSM.
+    ///
+    /// Returns `Error::Timeout` if the peer does not answer within the configured timeout, and
+    /// `Error::ChannelRefused` with the result code of the response if the peer refuses the channel.
     pub async fn create<
         M: RawMutex,
         T: Controller,
@@ -164,24 +413,617 @@ impl L2capChannel {
         const L2CAP_RXQ: usize,
     >(
         adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
-        connection: &Connection,
+        connection: &Connection<'_>,
         psm: u16,
         config: &L2capChannelConfig,
     ) -> Result<Self, AdapterError<T::Error>>
 where {
-        let handle = connection.handle();
         let cid = adapter
             .channels
             .create(
-                connection.handle(),
+                connection.live_handle()?,
                 psm,
                 config.mtu,
                 config.flow_policy,
                 config.initial_credits,
+                config.timeout,
                 &adapter.hci(),
             )
             .await?;
 
         Ok(Self { cid })
     }
+
+    /// Create up to five channels with the provided PSM using an enhanced credit based connection request.
+    ///
+    /// The peer may accept fewer channels than requested. If it refuses all of them, the result code
+    /// of the response is returned as `Error::ChannelRefused`.
+    pub async fn create_multiple<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        connection: &Connection<'_>,
+        psm: u16,
+        count: usize,
+        config: &L2capChannelConfig,
+    ) -> Result<Vec<L2capChannel, L2CAP_ECFC_MAX_CHANNELS>, AdapterError<T::Error>> {
+        let cids = adapter
+            .channels
+            .create_enhanced(
+                connection.live_handle()?,
+                psm,
+                count,
+                config.mtu,
+                config.flow_policy,
+                config.initial_credits,
+                config.timeout,
+                &adapter.hci(),
+            )
+            .await?;
+
+        Ok(cids.iter().map(|cid| Self { cid: *cid }).collect())
+    }
+
+    /// Change the MTU and MPS used to receive data on enhanced credit based channels of the same connection.
+    ///
+    /// The MTU cannot be reduced, and the MPS can only be reduced when a single channel is reconfigured.
+    pub async fn reconfigure<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        channels: &[L2capChannel],
+        mtu: u16,
+        mps: u16,
+    ) -> Result<(), AdapterError<T::Error>> {
+        let mut cids: Vec<u16, L2CAP_ECFC_MAX_CHANNELS> = Vec::new();
+        for channel in channels {
+            cids.push(channel.cid).map_err(|_| Error::InvalidValue)?;
+        }
+        adapter.channels.reconfigure(&cids, mtu, mps, &adapter.hci()).await
+    }
+}
+
+/// Receiving half of an L2CAP channel, see [`L2capChannel::split`].
+pub struct L2capReader {
+    channel: L2capChannel,
+}
+
+impl L2capReader {
+    /// Receive data on this channel and copy it into the buffer, see [`L2capChannel::receive`].
+    pub async fn receive<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &mut self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        buf: &mut [u8],
+    ) -> Result<usize, AdapterError<T::Error>> {
+        self.channel.receive(adapter, buf).await
+    }
+
+    /// Receive an SDU on this channel without copying it out of the adapter packet pool, see
+    /// [`L2capChannel::receive_packet`].
+    pub async fn receive_packet<
+        'a,
+        'd,
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &mut self,
+        adapter: &'a Adapter<'d, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+    ) -> Result<L2capPacket<'a, 'd>, AdapterError<T::Error>> {
+        self.channel.receive_packet(adapter).await
+    }
+}
+
+/// Sending half of an L2CAP channel, see [`L2capChannel::split`].
+pub struct L2capWriter {
+    channel: L2capChannel,
+}
+
+impl L2capWriter {
+    /// Send the provided buffer over this l2cap channel, see [`L2capChannel::send`].
+    pub async fn send<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &mut self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        buf: &[u8],
+    ) -> Result<(), AdapterError<T::Error>> {
+        self.channel.send(adapter, buf).await
+    }
+
+    /// Send the provided buffer over this l2cap channel without waiting for credits, see
+    /// [`L2capChannel::try_send`].
+    pub fn try_send<
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &mut self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        buf: &[u8],
+    ) -> Result<(), AdapterError<T::Error>> {
+        self.channel.try_send(adapter, buf)
+    }
+
+    /// Allocate a packet to be filled and sent with [`L2capWriter::send_packet`], see
+    /// [`L2capChannel::alloc_packet`].
+    pub fn alloc_packet<
+        'a,
+        'd,
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &mut self,
+        adapter: &'a Adapter<'d, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+    ) -> Result<L2capPacket<'a, 'd>, AdapterError<T::Error>> {
+        self.channel.alloc_packet(adapter)
+    }
+
+    /// Send an SDU held in a pool packet over this l2cap channel, see [`L2capChannel::send_packet`].
+    pub async fn send_packet<
+        'a,
+        'd,
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &mut self,
+        adapter: &'a Adapter<'d, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        packet: L2capPacket<'a, 'd>,
+    ) -> Result<(), AdapterError<T::Error>> {
+        self.channel.send_packet(adapter, packet).await
+    }
+
+    /// Disconnect this channel, see [`L2capChannel::disconnect`].
+    pub async fn disconnect<
+        M: RawMutex,
+        T: Controller + ControllerCmdSync<Disconnect>,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    >(
+        &mut self,
+        adapter: &Adapter<'_, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+        close_connection: bool,
+    ) -> Result<(), AdapterError<T::Error>> {
+        self.channel.disconnect(adapter, close_connection).await
+    }
+}
+
+/// An L2CAP channel bound to its adapter, reading and writing bytes over SDUs.
+///
+/// Reads return data of received SDUs regardless of their boundaries, and return 0 once the
+/// channel is disconnected. Each write sends at most one SDU, waiting for credits as needed.
+/// Flushing waits until the peer has granted credits for more data.
+pub struct L2capStream<
+    'a,
+    'd,
+    M: RawMutex,
+    T: Controller,
+    const CONNS: usize,
+    const CHANNELS: usize,
+    const L2CAP_MTU: usize,
+    const L2CAP_TXQ: usize,
+    const L2CAP_RXQ: usize,
+> {
+    adapter: &'a Adapter<'d, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>,
+    cid: u16,
+    // K-frame being read and the position of the next byte in it.
+    rx: Option<(Pdu<'d>, usize)>,
+    // Bytes of the current SDU still expected in following K-frames.
+    sdu_remaining: usize,
+}
+
+impl<
+        'a,
+        'd,
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    > L2capStream<'a, 'd, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>
+{
+    /// Release the channel from the adapter.
+    ///
+    /// Data left of a partly read K-frame is discarded, and the K-frame credited back to the peer.
+    pub fn unbind(self) -> L2capChannel {
+        L2capChannel { cid: self.cid }
+    }
+
+    // Wait for the next K-frame, returning None once the channel is closed.
+    async fn next_kframe(&mut self) -> Result<Option<(Pdu<'d>, usize)>, AdapterError<T::Error>> {
+        let channels = &self.adapter.channels;
+        let pdu = match channels.connected_channel_index(self.cid) {
+            Ok(idx) => channels.receive_pdu(self.cid, idx).await,
+            Err(e) => Err(e),
+        };
+        let pdu = match pdu {
+            Ok(pdu) => pdu,
+            // The channel slot is released once the disconnect has been observed.
+            Err(Error::ChannelClosed | Error::NotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        if self.sdu_remaining > 0 {
+            return Ok(Some((pdu, 0)));
+        }
+        // The first K-frame of an SDU starts with the SDU length, checked when dispatched.
+        let data = pdu.as_ref();
+        self.sdu_remaining = u16::from_le_bytes([data[0], data[1]]) as usize;
+        Ok(Some((pdu, 2)))
+    }
+}
+
+impl<
+        'a,
+        'd,
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    > Drop for L2capStream<'a, 'd, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>
+{
+    // The credit of a partly read K-frame is sent with the next credits granted on the channel.
+    fn drop(&mut self) {
+        if let Some((pdu, _)) = self.rx.take() {
+            drop(pdu);
+            self.adapter.channels.release(self.cid);
+        }
+    }
+}
+
+impl<
+        'a,
+        'd,
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    > embedded_io_async::ErrorType for L2capStream<'a, 'd, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>
+{
+    type Error = AdapterError<T::Error>;
+}
+
+impl<
+        'a,
+        'd,
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    > embedded_io_async::Read for L2capStream<'a, 'd, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.rx.is_none() {
+                match self.next_kframe().await? {
+                    Some(rx) => self.rx = Some(rx),
+                    None => return Ok(0),
+                }
+            }
+
+            let Some((pdu, pos)) = self.rx.as_mut() else {
+                continue;
+            };
+            let data = &pdu.as_ref()[*pos..];
+            let n = data.len().min(buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            *pos += n;
+            self.sdu_remaining -= n;
+
+            if *pos == pdu.len {
+                if let Some((pdu, _)) = self.rx.take() {
+                    self.adapter
+                        .channels
+                        .flow_control(self.cid, &self.adapter.hci(), pdu.packet)
+                        .await?;
+                }
+            }
+            // Empty SDUs carry no data for the stream.
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+impl<
+        'a,
+        'd,
+        M: RawMutex,
+        T: Controller,
+        const CONNS: usize,
+        const CHANNELS: usize,
+        const L2CAP_MTU: usize,
+        const L2CAP_TXQ: usize,
+        const L2CAP_RXQ: usize,
+    > embedded_io_async::Write for L2capStream<'a, 'd, M, T, CONNS, CHANNELS, L2CAP_MTU, L2CAP_TXQ, L2CAP_RXQ>
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mtu = self.adapter.channels.peer_mtu(self.cid)? as usize;
+        let n = buf.len().min(mtu);
+        self.adapter
+            .channels
+            .send(self.cid, &buf[..n], &self.adapter.hci())
+            .await?;
+        Ok(n)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        poll_fn(|cx| self.adapter.channels.poll_credits(self.cid, cx)).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bt_hci::param::{ConnHandle, LeConnRole};
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embedded_io_async::{Read, Write};
+
+    use super::*;
+    use crate::adapter::HostResources;
+    use crate::connection::SecurityLevel;
+    use crate::mock_controller::MockController;
+    use crate::packet_pool::{AllocId, Qos};
+    use crate::types::l2cap::{L2capHeader, L2capSignalCode};
+
+    type TestAdapter<'d> = Adapter<'d, NoopRawMutex, MockController, 1, 4, 64, 2, 4>;
+
+    const PSM: u16 = 0x0081;
+    const PEER_CID: u16 = 0x40;
+
+    fn signal(adapter: &TestAdapter<'_>, code: L2capSignalCode, payload: &[u16]) {
+        let mut data: Vec<u8, 16> = Vec::new();
+        data.extend_from_slice(&[code as u8, 1]).unwrap();
+        data.extend_from_slice(&(2 * payload.len() as u16).to_le_bytes())
+            .unwrap();
+        for value in payload {
+            data.extend_from_slice(&value.to_le_bytes()).unwrap();
+        }
+        block_on(adapter.channels.signal(
+            ConnHandle::new(1),
+            &data,
+            LeConnRole::Peripheral,
+            SecurityLevel::NoEncryption,
+            &adapter.hci(),
+        ))
+        .unwrap();
+    }
+
+    // Accept a channel requested by the peer, with an MTU of 23 and 4 credits on both sides.
+    fn open(adapter: &TestAdapter<'_>) -> L2capChannel {
+        adapter.permits.set(16);
+        adapter.channels.register(PsmRegistration::new(PSM, 64)).unwrap();
+        signal(adapter, L2capSignalCode::LeCreditConnReq, &[PSM, PEER_CID, 23, 64, 4]);
+        let cids = block_on(adapter.channels.accept(
+            ConnHandle::new(1),
+            &[PSM],
+            64,
+            CreditFlowPolicy::Every(1),
+            Some(4),
+            1,
+            L2CAP_RTX_TIMEOUT_DEFAULT,
+            &adapter.hci(),
+        ))
+        .unwrap();
+        adapter.controller.take_sent();
+        L2capChannel { cid: cids[0] }
+    }
+
+    // Run a test with a channel open on a fresh adapter.
+    fn with_channel(test: impl FnOnce(&TestAdapter<'_>, L2capChannel)) {
+        let mut resources: HostResources<NoopRawMutex, 4, 8, 64> = HostResources::new(Qos::None);
+        let adapter: TestAdapter<'_> = Adapter::new(MockController::new(), &mut resources);
+        let channel = open(&adapter);
+        test(&adapter, channel);
+    }
+
+    fn kframe(adapter: &TestAdapter<'_>, cid: u16, payload: &[u8]) {
+        let mut packet = adapter.pool.alloc(AllocId::from_channel(cid).unwrap()).unwrap();
+        packet.as_mut()[..payload.len()].copy_from_slice(payload);
+        let header = L2capHeader {
+            length: payload.len() as u16,
+            channel: cid,
+        };
+        block_on(
+            adapter
+                .channels
+                .dispatch(ConnHandle::new(1), header, packet, &adapter.hci()),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_stream_read() {
+        with_channel(|adapter, channel| {
+            let cid = channel.cid();
+            let mut stream = channel.bind(adapter);
+
+            // An SDU of two K-frames followed by another SDU.
+            kframe(adapter, cid, &[5, 0, 1, 2, 3]);
+            kframe(adapter, cid, &[4, 5]);
+            kframe(adapter, cid, &[2, 0, 6, 7]);
+
+            let mut buf = [0; 2];
+            assert_eq!(block_on(stream.read(&mut buf)).unwrap(), 2);
+            assert_eq!(buf, [1, 2]);
+
+            // Reads are not bound to SDUs.
+            let mut buf = [0; 5];
+            block_on(stream.read_exact(&mut buf)).unwrap();
+            assert_eq!(buf, [3, 4, 5, 6, 7]);
+
+            // Each K-frame is credited once it has been read entirely.
+            let sent = adapter.controller.take_sent();
+            assert_eq!(sent.len(), 3);
+            for packet in sent.iter() {
+                assert_eq!(packet[4], L2capSignalCode::LeCreditFlowInd as u8);
+            }
+        });
+    }
+
+    #[test]
+    fn test_stream_eof() {
+        with_channel(|adapter, channel| {
+            let cid = channel.cid();
+            let mut stream = channel.bind(adapter);
+
+            // Empty SDUs carry no data for the stream.
+            kframe(adapter, cid, &[0, 0]);
+            kframe(adapter, cid, &[2, 0, 1, 2]);
+            signal(adapter, L2capSignalCode::DisconnectionReq, &[cid, PEER_CID]);
+
+            // Data received before the disconnect is still read.
+            let mut buf = [0; 8];
+            assert_eq!(block_on(stream.read(&mut buf)).unwrap(), 2);
+            assert_eq!(&buf[..2], &[1, 2]);
+            assert_eq!(block_on(stream.read(&mut buf)).unwrap(), 0);
+            // The end of the stream is reported again once the channel has been released.
+            assert_eq!(block_on(stream.read(&mut buf)).unwrap(), 0);
+            assert!(block_on(stream.write(&[1, 2, 3])).is_err());
+        });
+    }
+
+    #[test]
+    fn test_stream_write() {
+        with_channel(|adapter, channel| {
+            let mut stream = channel.bind(adapter);
+
+            // Each write sends at most one SDU of the peer MTU.
+            assert_eq!(block_on(stream.write(&[7; 30])).unwrap(), 23);
+            assert_eq!(block_on(stream.write(&[])).unwrap(), 0);
+            let sent = adapter.controller.take_sent();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(&sent[0][..6], &[25, 0, PEER_CID as u8, 0, 23, 0]);
+            assert_eq!(&sent[0][6..], &[7; 23]);
+
+            // Flushing completes while the peer has credits left.
+            block_on(stream.flush()).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_split() {
+        with_channel(|adapter, channel| {
+            let cid = channel.cid();
+            let (mut reader, mut writer) = channel.split();
+
+            kframe(adapter, cid, &[2, 0, 1, 2]);
+            kframe(adapter, cid, &[1, 0, 3]);
+            let mut buf = [0; 64];
+            assert_eq!(block_on(reader.receive(adapter, &mut buf)).unwrap(), 2);
+            assert_eq!(&buf[..2], &[1, 2]);
+            let packet = block_on(reader.receive_packet(adapter)).unwrap();
+            assert_eq!(packet.as_ref(), &[3]);
+            drop(packet);
+            adapter.controller.take_sent();
+
+            block_on(writer.send(adapter, &[4, 5])).unwrap();
+            let mut packet = writer.alloc_packet(adapter).unwrap();
+            packet.truncate(1);
+            packet.as_mut().copy_from_slice(&[6]);
+            block_on(writer.send_packet(adapter, packet)).unwrap();
+            let sent = adapter.controller.take_sent();
+            assert_eq!(sent.len(), 2);
+            assert_eq!(&sent[0][..], &[4, 0, PEER_CID as u8, 0, 2, 0, 4, 5]);
+            assert_eq!(&sent[1][..], &[3, 0, PEER_CID as u8, 0, 1, 0, 6]);
+
+            // Both halves observe the peer closing the channel.
+            signal(adapter, L2capSignalCode::DisconnectionReq, &[cid, PEER_CID]);
+            assert!(matches!(
+                block_on(reader.receive(adapter, &mut buf)),
+                Err(AdapterError::Adapter(Error::ChannelClosed))
+            ));
+            assert!(writer.try_send(adapter, &[7]).is_err());
+        });
+    }
+
+    #[test]
+    fn test_stream_unbind() {
+        with_channel(|adapter, channel| {
+            let cid = channel.cid();
+            let mut stream = channel.bind(adapter);
+
+            kframe(adapter, cid, &[3, 0, 1, 2, 3]);
+            let mut buf = [0; 1];
+            assert_eq!(block_on(stream.read(&mut buf)).unwrap(), 1);
+            assert!(adapter.controller.take_sent().is_empty());
+
+            // The partly read K-frame is credited along with the next one.
+            let mut channel = stream.unbind();
+            kframe(adapter, cid, &[1, 0, 4]);
+            let mut buf = [0; 64];
+            assert_eq!(block_on(channel.receive(adapter, &mut buf)).unwrap(), 1);
+            assert_eq!(&buf[..1], &[4]);
+            let sent = adapter.controller.take_sent();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0][4], L2capSignalCode::LeCreditFlowInd as u8);
+            assert_eq!(&sent[0][10..], &[2, 0]);
+        });
+    }
 }
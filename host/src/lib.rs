@@ -20,6 +20,8 @@ mod channel_manager;
 mod codec;
 mod connection_manager;
 mod cursor;
+#[cfg(test)]
+mod mock_controller;
 mod packet_pool;
 mod pdu;
 pub mod types;
@@ -39,6 +41,17 @@ mod attribute_server;
 #[cfg(feature = "gatt")]
 pub mod gatt;
 
+#[cfg(feature = "gatt")]
+pub use trouble_host_macros::gatt_service;
+
+/// Re-exports used by code generated by the `gatt_service` macro.
+#[cfg(feature = "gatt")]
+#[doc(hidden)]
+pub mod __macro_support {
+    pub use bt_hci::controller::Controller;
+    pub use embassy_sync::blocking_mutex::raw::RawMutex;
+}
+
 #[derive(Debug, Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Address {
@@ -81,9 +94,26 @@ pub enum Error {
     Busy,
     NoPermits,
     Disconnected,
+    MtuExceeded,
+    OutOfCredits,
+    ChannelRefused(types::l2cap::LeCreditConnResultCode),
     Other,
 }
 
+impl<E: core::fmt::Debug> embedded_io_async::Error for AdapterError<E> {
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        match self {
+            AdapterError::Adapter(Error::ChannelClosed) | AdapterError::Adapter(Error::Disconnected) => {
+                embedded_io_async::ErrorKind::NotConnected
+            }
+            AdapterError::Adapter(Error::Timeout) => embedded_io_async::ErrorKind::TimedOut,
+            AdapterError::Adapter(Error::OutOfMemory) => embedded_io_async::ErrorKind::OutOfMemory,
+            AdapterError::Adapter(Error::InvalidValue) => embedded_io_async::ErrorKind::InvalidInput,
+            _ => embedded_io_async::ErrorKind::Other,
+        }
+    }
+}
+
 impl<E> From<Error> for AdapterError<E> {
     fn from(value: Error) -> Self {
         Self::Adapter(value)
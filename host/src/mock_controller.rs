@@ -0,0 +1,94 @@
+//! Controller used by unit tests, recording the ACL data written by the host.
+use core::cell::RefCell;
+use core::convert::Infallible;
+use core::future::pending;
+
+use bt_hci::controller::Controller;
+use bt_hci::data::{AclPacket, IsoPacket, SyncPacket};
+use bt_hci::event::le::LeConnectionComplete;
+use bt_hci::param::{ConnHandle, LeConnRole};
+use bt_hci::{ControllerToHostPacket, FromHciBytes};
+use embassy_sync::blocking_mutex::raw::{NoopRawMutex, RawMutex};
+use embassy_sync::semaphore::GreedySemaphore;
+use heapless::Vec;
+
+use crate::adapter::HciController;
+use crate::connection_manager::ConnectionManager;
+use crate::Error;
+
+pub(crate) type SentPacket = Vec<u8, 64>;
+
+pub(crate) struct MockController {
+    sent: RefCell<Vec<SentPacket, 16>>,
+    permits: GreedySemaphore<NoopRawMutex>,
+}
+
+impl MockController {
+    pub(crate) fn new() -> Self {
+        Self {
+            sent: RefCell::new(Vec::new()),
+            permits: GreedySemaphore::new(16),
+        }
+    }
+
+    pub(crate) fn hci(&self) -> HciController<'_, Self> {
+        HciController {
+            controller: self,
+            permits: &self.permits,
+        }
+    }
+
+    /// Take the L2CAP frames written so far, oldest first.
+    pub(crate) fn take_sent(&self) -> Vec<SentPacket, 16> {
+        core::mem::take(&mut *self.sent.borrow_mut())
+    }
+
+    /// Take the first signalling command written since the last call, returning its code, identifier and payload.
+    pub(crate) fn take_signal(&self) -> Option<(u8, u8, SentPacket)> {
+        let sent = self.take_sent();
+        let packet = sent.first()?;
+        Some((packet[4], packet[5], SentPacket::from_slice(&packet[8..]).unwrap()))
+    }
+}
+
+impl embedded_io_async::ErrorType for MockController {
+    type Error = Infallible;
+}
+
+impl Controller for MockController {
+    async fn write_acl_data(&self, packet: &AclPacket<'_>) -> Result<(), Self::Error> {
+        let data = SentPacket::from_slice(packet.data()).unwrap();
+        self.sent.borrow_mut().push(data).unwrap();
+        Ok(())
+    }
+
+    async fn write_sync_data(&self, _packet: &SyncPacket<'_>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn write_iso_data(&self, _packet: &IsoPacket<'_>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn read<'a>(&self, _buf: &'a mut [u8]) -> Result<ControllerToHostPacket<'a>, Self::Error> {
+        pending().await
+    }
+}
+
+/// Register a link the way the adapter does when the controller reports a new connection.
+pub(crate) fn connect<M: RawMutex, const CONNS: usize>(
+    manager: &ConnectionManager<M, CONNS>,
+    handle: u16,
+    role: LeConnRole,
+) -> Result<(), Error> {
+    let mut params = [0; 18];
+    params[1..3].copy_from_slice(&handle.to_le_bytes());
+    params[3] = match role {
+        LeConnRole::Central => 0,
+        LeConnRole::Peripheral => 1,
+    };
+    // Peer address
+    params[5..11].copy_from_slice(&[1, 2, 3, 4, 5, handle as u8]);
+    let info = LeConnectionComplete::from_hci_bytes_complete(&params).unwrap();
+    manager.connect(ConnHandle::new(handle), &info)
+}
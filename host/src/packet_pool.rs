@@ -3,11 +3,21 @@ use core::cell::{RefCell, UnsafeCell};
 use embassy_sync::blocking_mutex::raw::RawMutex;
 use embassy_sync::blocking_mutex::Mutex;
 
-use crate::types::l2cap::{L2CAP_CID_ATT, L2CAP_CID_DYN_START};
+#[cfg(feature = "gatt")]
+use crate::types::l2cap::L2CAP_CID_ATT;
+use crate::types::l2cap::{L2CAP_CID_DYN_START, L2CAP_CID_FIXED_CUSTOM_END, L2CAP_CID_FIXED_CUSTOM_START};
+use crate::Error;
 
 // Generic client ID used by ATT PDU
 #[cfg(feature = "gatt")]
-pub(crate) const ATT_ID: AllocId = AllocId(0);
+pub(crate) const ATT_ID: AllocId = AllocId::fixed(0);
+// Client ID shared by custom fixed channels
+pub(crate) const FIXED_ID: AllocId = AllocId::fixed(1);
+// Client ID of packets allocated for sending, kept apart from the packets received on channels
+pub(crate) const SEND_ID: AllocId = AllocId::fixed(2);
+
+// Fixed clients are allocated apart from the clients of the pool, which are the dynamic channels.
+const FIXED_CLIENTS: usize = 3;
 
 #[derive(Clone, Copy, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -15,23 +25,29 @@ pub struct AllocId(usize);
 
 impl AllocId {
     pub fn dynamic(idx: usize) -> AllocId {
-        // Dynamic range starts at 2
-        #[cfg(feature = "gatt")]
-        return AllocId(1 + idx);
-        #[cfg(not(feature = "gatt"))]
-        return AllocId(idx);
+        AllocId(idx)
     }
 
-    pub fn from_channel(cid: u16) -> AllocId {
+    // Fixed clients are numbered down from the end of the range.
+    const fn fixed(idx: usize) -> AllocId {
+        AllocId(usize::MAX - idx)
+    }
+
+    fn fixed_index(self) -> Option<usize> {
+        let idx = usize::MAX - self.0;
+        (idx < FIXED_CLIENTS).then_some(idx)
+    }
+
+    /// Client allocating the packets received on a channel.
+    ///
+    /// Returns `Error::InvalidChannelId` for channels without an allocation of their own.
+    pub fn from_channel(cid: u16) -> Result<AllocId, Error> {
         match cid {
-            L2CAP_CID_ATT => {
-                #[cfg(feature = "gatt")]
-                return ATT_ID;
-                #[cfg(not(feature = "gatt"))]
-                panic!("gatt feature must be enabled to support gatt");
-            }
-            cid if cid >= L2CAP_CID_DYN_START => Self::dynamic((cid - L2CAP_CID_DYN_START) as usize),
-            _ => unimplemented!(),
+            #[cfg(feature = "gatt")]
+            L2CAP_CID_ATT => Ok(ATT_ID),
+            L2CAP_CID_FIXED_CUSTOM_START..=L2CAP_CID_FIXED_CUSTOM_END => Ok(FIXED_ID),
+            cid if cid >= L2CAP_CID_DYN_START => Ok(Self::dynamic((cid - L2CAP_CID_DYN_START) as usize)),
+            _ => Err(Error::InvalidChannelId),
         }
     }
 }
@@ -55,7 +71,7 @@ impl<const MTU: usize> PacketBuf<MTU> {
 /// Quality of service policy for packet allocation
 #[derive(Clone, Copy)]
 pub enum Qos {
-    /// Distribute evenly among client
+    /// Distribute evenly among clients, fixed channels included
     Fair,
     /// Reserve at least N packets for each client
     Guaranteed(usize),
@@ -63,27 +79,64 @@ pub enum Qos {
     None,
 }
 
+struct Usage<const CLIENTS: usize> {
+    clients: [usize; CLIENTS],
+    fixed: [usize; FIXED_CLIENTS],
+}
+
+impl<const CLIENTS: usize> Usage<CLIENTS> {
+    fn of(&self, id: AllocId) -> usize {
+        match id.fixed_index() {
+            Some(idx) => self.fixed[idx],
+            None => self.clients[id.0],
+        }
+    }
+
+    fn of_mut(&mut self, id: AllocId) -> &mut usize {
+        match id.fixed_index() {
+            Some(idx) => &mut self.fixed[idx],
+            None => &mut self.clients[id.0],
+        }
+    }
+
+    fn total(&self) -> usize {
+        self.clients.iter().sum::<usize>() + self.fixed.iter().sum::<usize>()
+    }
+}
+
 struct State<const MTU: usize, const N: usize, const CLIENTS: usize> {
     packets: UnsafeCell<[PacketBuf<MTU>; N]>,
-    usage: RefCell<[usize; CLIENTS]>,
+    usage: RefCell<Usage<CLIENTS>>,
 }
 
 impl<const MTU: usize, const N: usize, const CLIENTS: usize> State<MTU, N, CLIENTS> {
     pub fn new() -> Self {
         Self {
             packets: UnsafeCell::new([PacketBuf::NEW; N]),
-            usage: RefCell::new([0; CLIENTS]),
+            usage: RefCell::new(Usage {
+                clients: [0; CLIENTS],
+                fixed: [0; FIXED_CLIENTS],
+            }),
         }
     }
 
+    // Share of each client, fixed clients included so that all shares fit in the pool.
+    const fn fair_share() -> usize {
+        N / (CLIENTS + FIXED_CLIENTS)
+    }
+
     // Guaranteed available
     fn min_available(&self, qos: Qos, client: AllocId) -> usize {
+        // Nothing is reserved for fixed channels, they use the packets other clients leave.
+        if client.fixed_index().is_some() {
+            return self.available(qos, client);
+        }
         let usage = self.usage.borrow();
         let min = match qos {
-            Qos::None => N.saturating_sub(usage.iter().sum()),
-            Qos::Fair => (N / CLIENTS).saturating_sub(usage[client.0]),
+            Qos::None => N.saturating_sub(usage.total()),
+            Qos::Fair => Self::fair_share().saturating_sub(usage.of(client)),
             Qos::Guaranteed(n) => {
-                let usage = usage[client.0];
+                let usage = usage.of(client);
                 n.saturating_sub(usage)
             }
         };
@@ -94,13 +147,16 @@ impl<const MTU: usize, const N: usize, const CLIENTS: usize> State<MTU, N, CLIEN
     fn available(&self, qos: Qos, client: AllocId) -> usize {
         let usage = self.usage.borrow();
         let available = match qos {
-            Qos::None => N.saturating_sub(usage.iter().sum()),
-            Qos::Fair => (N / CLIENTS).saturating_sub(usage[client.0]),
+            Qos::None => N.saturating_sub(usage.total()),
+            Qos::Fair => Self::fair_share().saturating_sub(usage.of(client)),
             Qos::Guaranteed(n) => {
-                // Reserved for clients that should have minimum
-                let reserved = n * usage.iter().filter(|c| **c == 0).count();
-                let reserved = reserved - if usage[client.0] < n { n - usage[client.0] } else { 0 };
-                let usage = reserved + usage.iter().sum::<usize>();
+                // Reserved for clients that should have minimum, fixed channels have none
+                let reserved = n * usage.clients.iter().filter(|c| **c == 0).count();
+                let reserved = match client.fixed_index() {
+                    None if usage.of(client) < n => reserved - (n - usage.of(client)),
+                    _ => reserved,
+                };
+                let usage = reserved + usage.total();
                 N.saturating_sub(usage)
             }
         };
@@ -116,7 +172,7 @@ impl<const MTU: usize, const N: usize, const CLIENTS: usize> State<MTU, N, CLIEN
                 // info!("[{}] alloc {}", id.0, idx);
                 packet.free = false;
                 packet.buf.iter_mut().for_each(|b| *b = 0);
-                usage[id.0] += 1;
+                *usage.of_mut(id) += 1;
                 return Some(PacketRef {
                     idx,
                     buf: &mut packet.buf[..],
@@ -131,7 +187,7 @@ impl<const MTU: usize, const N: usize, const CLIENTS: usize> State<MTU, N, CLIEN
         let packets = unsafe { &mut *self.packets.get() };
         // info!("[{}] free {}", id.0, p_ref.idx);
         packets[p_ref.idx].free = true;
-        usage[id.0] -= 1;
+        *usage.of_mut(id) -= 1;
     }
 }
 
@@ -146,9 +202,6 @@ pub struct PacketPool<M: RawMutex, const MTU: usize, const N: usize, const CLIEN
 
 impl<M: RawMutex, const MTU: usize, const N: usize, const CLIENTS: usize> PacketPool<M, MTU, N, CLIENTS> {
     pub fn new(qos: Qos) -> Self {
-        // Need at least 1 for gatt
-        #[cfg(feature = "gatt")]
-        assert!(CLIENTS >= 1);
         Self {
             state: Mutex::new(State::new()),
             qos,
@@ -259,12 +312,13 @@ impl<'d> AsMut<[u8]> for Packet<'d> {
 #[cfg(test)]
 mod tests {
     use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use heapless::Vec;
 
     use super::*;
 
     #[test]
     fn test_fair_qos() {
-        let pool: PacketPool<NoopRawMutex, 1, 8, 4> = PacketPool::new(Qos::Fair);
+        let pool: PacketPool<NoopRawMutex, 1, 14, 4> = PacketPool::new(Qos::Fair);
 
         let a1 = pool.alloc(AllocId(0));
         assert!(a1.is_some());
@@ -359,4 +413,73 @@ mod tests {
         assert!(d1.is_some());
         assert!(pool.alloc(AllocId(3)).is_none());
     }
+
+    #[test]
+    fn test_fixed_clients() {
+        let pool: PacketPool<NoopRawMutex, 1, 14, 4> = PacketPool::new(Qos::Fair);
+
+        // Custom fixed channels have a share of their own.
+        let f1 = pool.alloc(FIXED_ID);
+        assert!(f1.is_some());
+        let f2 = pool.alloc(FIXED_ID);
+        assert!(f2.is_some());
+        assert!(pool.alloc(FIXED_ID).is_none());
+
+        let a1 = pool.alloc(AllocId(0));
+        assert!(a1.is_some());
+        let a2 = pool.alloc(AllocId(0));
+        assert!(a2.is_some());
+        drop(f1);
+        let f1 = pool.alloc(FIXED_ID);
+        assert!(f1.is_some());
+
+        // Every client can hold its whole share at once.
+        let mut ids: Vec<AllocId, 6> = Vec::new();
+        ids.extend_from_slice(&[AllocId(1), AllocId(2), AllocId(3), SEND_ID])
+            .unwrap();
+        #[cfg(feature = "gatt")]
+        ids.push(ATT_ID).unwrap();
+        let mut held: Vec<Packet, 12> = Vec::new();
+        for id in ids.iter() {
+            for _ in 0..2 {
+                assert!(held.push(pool.alloc(*id).unwrap()).is_ok());
+            }
+            assert!(pool.alloc(*id).is_none());
+        }
+    }
+
+    #[test]
+    fn test_fixed_clients_guaranteed_qos() {
+        let pool: PacketPool<NoopRawMutex, 1, 8, 4> = PacketPool::new(Qos::Guaranteed(1));
+
+        // Packets reserved for the other clients are not available to fixed channels.
+        let mut fixed = [None, None, None, None];
+        for f in fixed.iter_mut() {
+            *f = pool.alloc(FIXED_ID);
+            assert!(f.is_some());
+        }
+        assert!(pool.alloc(FIXED_ID).is_none());
+        assert_eq!(pool.min_available(FIXED_ID), 0);
+
+        let mut dynamic = [None, None, None, None];
+        for (id, p) in dynamic.iter_mut().enumerate() {
+            assert_eq!(pool.min_available(AllocId(id)), 1);
+            *p = pool.alloc(AllocId(id));
+            assert!(p.is_some());
+        }
+    }
+
+    #[test]
+    fn test_from_channel() {
+        assert_eq!(AllocId::from_channel(0x0040).unwrap().0, 0);
+        assert_eq!(AllocId::from_channel(0x0043).unwrap().0, 3);
+        assert_eq!(AllocId::from_channel(0x0020).unwrap().0, FIXED_ID.0);
+        assert!(matches!(AllocId::from_channel(0x0001), Err(Error::InvalidChannelId)));
+        assert!(matches!(AllocId::from_channel(0x0006), Err(Error::InvalidChannelId)));
+
+        #[cfg(feature = "gatt")]
+        assert_eq!(AllocId::from_channel(0x0004).unwrap().0, ATT_ID.0);
+        #[cfg(not(feature = "gatt"))]
+        assert!(matches!(AllocId::from_channel(0x0004), Err(Error::InvalidChannelId)));
+    }
 }
@@ -0,0 +1,154 @@
+//! Conversion of characteristic values to and from their GATT representation.
+
+use heapless::{String, Vec};
+
+/// Error converting a value from its GATT representation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FromGattError {
+    /// The length of the data does not match the type.
+    InvalidLength,
+    /// The data is not a valid value of the type.
+    InvalidValue,
+}
+
+/// A value which can be stored in a characteristic.
+///
+/// Values are encoded as little endian, as mandated by the Bluetooth specification. Fixed size types have
+/// `MIN_SIZE` equal to `MAX_SIZE`.
+pub trait GattValue: Sized {
+    /// Minimum size of the encoded value.
+    const MIN_SIZE: usize;
+    /// Maximum size of the encoded value, which is the amount of storage needed in the attribute table.
+    const MAX_SIZE: usize;
+
+    /// Decode a value from its GATT representation.
+    fn from_gatt(data: &[u8]) -> Result<Self, FromGattError>;
+
+    /// Encode the value into the destination buffer, returning the number of bytes written.
+    ///
+    /// The destination buffer must be at least `MAX_SIZE` bytes long.
+    fn to_gatt(&self, dest: &mut [u8]) -> usize;
+}
+
+macro_rules! primitive {
+    ($($ty:ty),*) => {
+        $(
+            impl GattValue for $ty {
+                const MIN_SIZE: usize = core::mem::size_of::<$ty>();
+                const MAX_SIZE: usize = core::mem::size_of::<$ty>();
+
+                fn from_gatt(data: &[u8]) -> Result<Self, FromGattError> {
+                    let bytes = data.try_into().map_err(|_| FromGattError::InvalidLength)?;
+                    Ok(<$ty>::from_le_bytes(bytes))
+                }
+
+                fn to_gatt(&self, dest: &mut [u8]) -> usize {
+                    dest[..Self::MAX_SIZE].copy_from_slice(&self.to_le_bytes());
+                    Self::MAX_SIZE
+                }
+            }
+        )*
+    };
+}
+
+primitive!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl GattValue for bool {
+    const MIN_SIZE: usize = 1;
+    const MAX_SIZE: usize = 1;
+
+    fn from_gatt(data: &[u8]) -> Result<Self, FromGattError> {
+        match data {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            [_] => Err(FromGattError::InvalidValue),
+            _ => Err(FromGattError::InvalidLength),
+        }
+    }
+
+    fn to_gatt(&self, dest: &mut [u8]) -> usize {
+        dest[0] = *self as u8;
+        1
+    }
+}
+
+impl<const N: usize> GattValue for [u8; N] {
+    const MIN_SIZE: usize = N;
+    const MAX_SIZE: usize = N;
+
+    fn from_gatt(data: &[u8]) -> Result<Self, FromGattError> {
+        data.try_into().map_err(|_| FromGattError::InvalidLength)
+    }
+
+    fn to_gatt(&self, dest: &mut [u8]) -> usize {
+        dest[..N].copy_from_slice(self);
+        N
+    }
+}
+
+impl<const N: usize> GattValue for Vec<u8, N> {
+    const MIN_SIZE: usize = 0;
+    const MAX_SIZE: usize = N;
+
+    fn from_gatt(data: &[u8]) -> Result<Self, FromGattError> {
+        Vec::from_slice(data).map_err(|_| FromGattError::InvalidLength)
+    }
+
+    fn to_gatt(&self, dest: &mut [u8]) -> usize {
+        dest[..self.len()].copy_from_slice(self);
+        self.len()
+    }
+}
+
+impl<const N: usize> GattValue for String<N> {
+    const MIN_SIZE: usize = 0;
+    const MAX_SIZE: usize = N;
+
+    fn from_gatt(data: &[u8]) -> Result<Self, FromGattError> {
+        let s = core::str::from_utf8(data).map_err(|_| FromGattError::InvalidValue)?;
+        let mut value = String::new();
+        value.push_str(s).map_err(|_| FromGattError::InvalidLength)?;
+        Ok(value)
+    }
+
+    fn to_gatt(&self, dest: &mut [u8]) -> usize {
+        dest[..self.len()].copy_from_slice(self.as_bytes());
+        self.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_primitives() {
+        let mut buf = [0; 8];
+        assert_eq!(0x1234u16.to_gatt(&mut buf), 2);
+        assert_eq!(buf[..2], [0x34, 0x12]);
+        assert_eq!(u16::from_gatt(&buf[..2]), Ok(0x1234));
+        assert_eq!(u16::from_gatt(&buf[..3]), Err(FromGattError::InvalidLength));
+
+        assert_eq!(1.5f32.to_gatt(&mut buf), 4);
+        assert_eq!(f32::from_gatt(&buf[..4]), Ok(1.5));
+
+        assert_eq!(true.to_gatt(&mut buf), 1);
+        assert_eq!(bool::from_gatt(&buf[..1]), Ok(true));
+        assert_eq!(bool::from_gatt(&[2]), Err(FromGattError::InvalidValue));
+    }
+
+    #[test]
+    fn test_variable_length() {
+        let mut buf = [0; 8];
+        let name: String<8> = String::try_from("trouble").unwrap();
+        assert_eq!(name.to_gatt(&mut buf), 7);
+        assert_eq!(String::<8>::from_gatt(&buf[..7]), Ok(name));
+        assert_eq!(String::<4>::from_gatt(&buf[..7]), Err(FromGattError::InvalidLength));
+        assert_eq!(String::<8>::from_gatt(&[0xff]), Err(FromGattError::InvalidValue));
+
+        let data: Vec<u8, 4> = Vec::from_slice(&[1, 2, 3]).unwrap();
+        assert_eq!(data.to_gatt(&mut buf), 3);
+        assert_eq!(Vec::<u8, 4>::from_gatt(&buf[..3]), Ok(data));
+    }
+}
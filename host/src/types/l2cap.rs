@@ -1,10 +1,31 @@
-use bt_hci::{FixedSizeValue, WriteHci};
+use bt_hci::FixedSizeValue;
+use embassy_time::Duration;
 
-use crate::codec::Error;
+use crate::codec::{Decode, Encode, Error, FixedSize, Type};
+use crate::cursor::{ReadCursor, WriteCursor};
 
 pub(crate) const L2CAP_CID_ATT: u16 = 0x0004;
 pub(crate) const L2CAP_CID_LE_U_SIGNAL: u16 = 0x0005;
 pub(crate) const L2CAP_CID_DYN_START: u16 = 0x0040;
+pub(crate) const L2CAP_CID_DYN_END: u16 = 0x007F;
+
+/// First fixed channel identifier available to custom protocols.
+pub const L2CAP_CID_FIXED_CUSTOM_START: u16 = 0x0007;
+
+/// Last fixed channel identifier available to custom protocols.
+pub const L2CAP_CID_FIXED_CUSTOM_END: u16 = 0x003F;
+
+/// Largest signalling packet accepted on the LE signalling channel.
+pub(crate) const L2CAP_SIGNAL_MTU: u16 = 23;
+
+/// Shortest signalling response timeout (RTX) allowed by the specification.
+pub const L2CAP_RTX_TIMEOUT_MIN: Duration = Duration::from_secs(1);
+
+/// Longest signalling response timeout (RTX) allowed by the specification.
+pub const L2CAP_RTX_TIMEOUT_MAX: Duration = Duration::from_secs(60);
+
+/// Signalling response timeout used unless configured otherwise.
+pub const L2CAP_RTX_TIMEOUT_DEFAULT: Duration = Duration::from_secs(10);
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(Debug, Clone, Copy)]
@@ -31,11 +52,11 @@ pub struct L2capSignalHeader {
 
 unsafe impl FixedSizeValue for L2capSignalHeader {
     fn is_valid(data: &[u8]) -> bool {
-        return true;
+        L2capSignalCode::try_from(data[0]).is_ok()
     }
 }
 
-pub trait L2capSignal: WriteHci + FixedSizeValue {
+pub trait L2capSignal {
     fn channel() -> u16 {
         L2CAP_CID_LE_U_SIGNAL
     }
@@ -120,7 +141,7 @@ impl L2capSignal for LeCreditConnReq {
 }
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
 pub enum LeCreditConnResultCode {
     Success = 0x0000,
@@ -133,6 +154,27 @@ pub enum LeCreditConnResultCode {
     InvalidSourceId = 0x0009,
     ScidAlreadyAllocated = 0x000A,
     UnacceptableParameters = 0x000B,
+    InvalidParameters = 0x000C,
+}
+
+impl TryFrom<u16> for LeCreditConnResultCode {
+    type Error = Error;
+    fn try_from(val: u16) -> Result<Self, Error> {
+        Ok(match val {
+            0x0000 => Self::Success,
+            0x0002 => Self::SpsmNotSupported,
+            0x0004 => Self::NoResources,
+            0x0005 => Self::InsufficientAuthentication,
+            0x0006 => Self::InsufficientAuthorization,
+            0x0007 => Self::EncryptionKeyTooShort,
+            0x0008 => Self::InsufficientEncryption,
+            0x0009 => Self::InvalidSourceId,
+            0x000A => Self::ScidAlreadyAllocated,
+            0x000B => Self::UnacceptableParameters,
+            0x000C => Self::InvalidParameters,
+            _ => return Err(Error::InvalidValue),
+        })
+    }
 }
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -143,7 +185,15 @@ pub struct LeCreditConnRes {
     pub mtu: u16,
     pub mps: u16,
     pub credits: u16,
-    pub result: LeCreditConnResultCode,
+    /// Raw result code, which may be one this host does not know.
+    pub result: u16,
+}
+
+impl LeCreditConnRes {
+    /// Result of the request, or [`Error::InvalidValue`] if the result code is unknown.
+    pub fn result_code(&self) -> Result<LeCreditConnResultCode, Error> {
+        LeCreditConnResultCode::try_from(self.result)
+    }
 }
 
 impl L2capSignal for LeCreditConnRes {
@@ -153,8 +203,8 @@ impl L2capSignal for LeCreditConnRes {
 }
 
 unsafe impl FixedSizeValue for LeCreditConnRes {
-    fn is_valid(data: &[u8]) -> bool {
-        return true;
+    fn is_valid(_data: &[u8]) -> bool {
+        true
     }
 }
 
@@ -178,17 +228,306 @@ impl L2capSignal for LeCreditFlowInd {
     }
 }
 
+/// Maximum number of channels that can be opened or reconfigured with a single
+/// enhanced credit based flow control request.
+pub const L2CAP_ECFC_MAX_CHANNELS: usize = 5;
+
+/// Minimum MTU and MPS of an enhanced credit based flow control channel.
+pub const L2CAP_ECFC_MIN_MTU: u16 = 64;
+
+/// Channel identifiers carried by enhanced credit based flow control signals.
+pub type ChannelIds = heapless::Vec<u16, L2CAP_ECFC_MAX_CHANNELS>;
+
+fn encode_cids(w: &mut WriteCursor<'_>, cids: &[u16]) -> Result<(), Error> {
+    for cid in cids {
+        w.write(*cid)?;
+    }
+    Ok(())
+}
+
+fn decode_cids(r: &mut ReadCursor<'_>) -> Result<ChannelIds, Error> {
+    let mut cids = ChannelIds::new();
+    if r.available() % 2 != 0 {
+        return Err(Error::InvalidValue);
+    }
+    while r.available() > 0 {
+        cids.push(r.read()?).map_err(|_| Error::InvalidValue)?;
+    }
+    Ok(cids)
+}
+
+/// Request to open up to five channels in enhanced credit based flow control mode.
+#[derive(Debug, Clone)]
+pub struct CreditConnReq {
+    pub psm: u16,
+    pub mtu: u16,
+    pub mps: u16,
+    pub credits: u16,
+    pub scids: ChannelIds,
+}
+
+impl L2capSignal for CreditConnReq {
+    fn code() -> L2capSignalCode {
+        L2capSignalCode::CreditConnReq
+    }
+}
+
+impl Type for CreditConnReq {
+    fn size(&self) -> usize {
+        8 + 2 * self.scids.len()
+    }
+}
+
+impl Encode for CreditConnReq {
+    fn encode(&self, dest: &mut [u8]) -> Result<(), Error> {
+        let mut w = WriteCursor::new(dest);
+        w.write(self.psm)?;
+        w.write(self.mtu)?;
+        w.write(self.mps)?;
+        w.write(self.credits)?;
+        encode_cids(&mut w, &self.scids)
+    }
+}
+
+impl Decode for CreditConnReq {
+    fn decode(src: &[u8]) -> Result<Self, Error> {
+        if src.len() < 10 {
+            return Err(Error::InvalidValue);
+        }
+        let mut r = ReadCursor::new(src);
+        Ok(Self {
+            psm: r.read()?,
+            mtu: r.read()?,
+            mps: r.read()?,
+            credits: r.read()?,
+            scids: decode_cids(&mut r)?,
+        })
+    }
+}
+
+/// Response to an enhanced credit based connection request.
+///
+/// A destination channel id of 0 means the corresponding source channel was refused.
+#[derive(Debug, Clone)]
+pub struct CreditConnRes {
+    pub mtu: u16,
+    pub mps: u16,
+    pub credits: u16,
+    pub result: LeCreditConnResultCode,
+    pub dcids: ChannelIds,
+}
+
+impl L2capSignal for CreditConnRes {
+    fn code() -> L2capSignalCode {
+        L2capSignalCode::CreditConnRes
+    }
+}
+
+impl Type for CreditConnRes {
+    fn size(&self) -> usize {
+        8 + 2 * self.dcids.len()
+    }
+}
+
+impl Encode for CreditConnRes {
+    fn encode(&self, dest: &mut [u8]) -> Result<(), Error> {
+        let mut w = WriteCursor::new(dest);
+        w.write(self.mtu)?;
+        w.write(self.mps)?;
+        w.write(self.credits)?;
+        w.write(self.result as u16)?;
+        encode_cids(&mut w, &self.dcids)
+    }
+}
+
+impl Decode for CreditConnRes {
+    fn decode(src: &[u8]) -> Result<Self, Error> {
+        if src.len() < 8 {
+            return Err(Error::InvalidValue);
+        }
+        let mut r = ReadCursor::new(src);
+        Ok(Self {
+            mtu: r.read()?,
+            mps: r.read()?,
+            credits: r.read()?,
+            result: LeCreditConnResultCode::try_from(r.read::<u16>()?)?,
+            dcids: decode_cids(&mut r)?,
+        })
+    }
+}
+
+/// Request to change the MTU and MPS of one or more enhanced credit based channels.
+///
+/// The channel ids are the ones used by the sender of the request to receive data.
+#[derive(Debug, Clone)]
+pub struct CreditConnReconfigReq {
+    pub mtu: u16,
+    pub mps: u16,
+    pub dcids: ChannelIds,
+}
+
+impl L2capSignal for CreditConnReconfigReq {
+    fn code() -> L2capSignalCode {
+        L2capSignalCode::CreditConnReconfigReq
+    }
+}
+
+impl Type for CreditConnReconfigReq {
+    fn size(&self) -> usize {
+        4 + 2 * self.dcids.len()
+    }
+}
+
+impl Encode for CreditConnReconfigReq {
+    fn encode(&self, dest: &mut [u8]) -> Result<(), Error> {
+        let mut w = WriteCursor::new(dest);
+        w.write(self.mtu)?;
+        w.write(self.mps)?;
+        encode_cids(&mut w, &self.dcids)
+    }
+}
+
+impl Decode for CreditConnReconfigReq {
+    fn decode(src: &[u8]) -> Result<Self, Error> {
+        if src.len() < 6 {
+            return Err(Error::InvalidValue);
+        }
+        let mut r = ReadCursor::new(src);
+        Ok(Self {
+            mtu: r.read()?,
+            mps: r.read()?,
+            dcids: decode_cids(&mut r)?,
+        })
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u16)]
+pub enum CreditConnReconfigResultCode {
+    Success = 0x0000,
+    MtuReductionNotAllowed = 0x0001,
+    MpsReductionNotAllowed = 0x0002,
+    InvalidDestinationCid = 0x0003,
+    UnacceptableParameters = 0x0004,
+}
+
+impl TryFrom<u16> for CreditConnReconfigResultCode {
+    type Error = Error;
+    fn try_from(val: u16) -> Result<Self, Error> {
+        Ok(match val {
+            0x0000 => Self::Success,
+            0x0001 => Self::MtuReductionNotAllowed,
+            0x0002 => Self::MpsReductionNotAllowed,
+            0x0003 => Self::InvalidDestinationCid,
+            0x0004 => Self::UnacceptableParameters,
+            _ => return Err(Error::InvalidValue),
+        })
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy)]
+pub struct CreditConnReconfigRes {
+    pub result: CreditConnReconfigResultCode,
+}
+
+impl L2capSignal for CreditConnReconfigRes {
+    fn code() -> L2capSignalCode {
+        L2capSignalCode::CreditConnReconfigRes
+    }
+}
+
+impl FixedSize for CreditConnReconfigRes {
+    const SIZE: usize = 2;
+}
+
+impl Encode for CreditConnReconfigRes {
+    fn encode(&self, dest: &mut [u8]) -> Result<(), Error> {
+        (self.result as u16).encode(dest)
+    }
+}
+
+impl Decode for CreditConnReconfigRes {
+    fn decode(src: &[u8]) -> Result<Self, Error> {
+        if src.len() < 2 {
+            return Err(Error::InvalidValue);
+        }
+        Ok(Self {
+            result: CreditConnReconfigResultCode::try_from(u16::decode(src)?)?,
+        })
+    }
+}
+
+/// Reason for rejecting a signalling command, with the data fields that go with it.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandRejectReason {
+    /// The command code is not understood or not supported.
+    NotUnderstood,
+    /// The command exceeds the signalling MTU, which is included.
+    MtuExceeded(u16),
+    /// The request refers to a channel that does not exist.
+    InvalidCid { local: u16, remote: u16 },
+}
+
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CommandRejectRes {
-    pub reason: u16,
-    // TODO: Optional fields pub data: u16,
+    pub reason: CommandRejectReason,
 }
 
-unsafe impl FixedSizeValue for CommandRejectRes {
-    fn is_valid(data: &[u8]) -> bool {
-        return true;
+impl L2capSignal for CommandRejectRes {
+    fn code() -> L2capSignalCode {
+        L2capSignalCode::CommandRejectRes
+    }
+}
+
+impl Type for CommandRejectRes {
+    fn size(&self) -> usize {
+        match self.reason {
+            CommandRejectReason::NotUnderstood => 2,
+            CommandRejectReason::MtuExceeded(_) => 4,
+            CommandRejectReason::InvalidCid { .. } => 6,
+        }
+    }
+}
+
+impl Encode for CommandRejectRes {
+    fn encode(&self, dest: &mut [u8]) -> Result<(), Error> {
+        let mut w = WriteCursor::new(dest);
+        match self.reason {
+            CommandRejectReason::NotUnderstood => w.write(0x0000u16)?,
+            CommandRejectReason::MtuExceeded(mtu) => {
+                w.write(0x0001u16)?;
+                w.write(mtu)?;
+            }
+            CommandRejectReason::InvalidCid { local, remote } => {
+                w.write(0x0002u16)?;
+                w.write(local)?;
+                w.write(remote)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Decode for CommandRejectRes {
+    fn decode(src: &[u8]) -> Result<Self, Error> {
+        if src.len() < 2 {
+            return Err(Error::InvalidValue);
+        }
+        let mut r = ReadCursor::new(src);
+        let reason = match r.read::<u16>()? {
+            0x0000 => CommandRejectReason::NotUnderstood,
+            0x0001 if src.len() >= 4 => CommandRejectReason::MtuExceeded(r.read()?),
+            0x0002 if src.len() >= 6 => CommandRejectReason::InvalidCid {
+                local: r.read()?,
+                remote: r.read()?,
+            },
+            _ => return Err(Error::InvalidValue),
+        };
+        Ok(Self { reason })
     }
 }
 
@@ -225,3 +564,65 @@ impl L2capSignal for DisconnectionRes {
         L2capSignalCode::DisconnectionRes
     }
 }
+
+/// Connection parameters requested by a peripheral, in controller units.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ConnParamUpdateReq {
+    /// Minimum connection interval in units of 1.25 ms.
+    pub interval_min: u16,
+    /// Maximum connection interval in units of 1.25 ms.
+    pub interval_max: u16,
+    pub latency: u16,
+    /// Supervision timeout in units of 10 ms.
+    pub timeout: u16,
+}
+
+impl ConnParamUpdateReq {
+    /// Check the parameters are within the ranges allowed by the specification.
+    ///
+    /// The supervision timeout must also exceed (1 + latency) * interval_max * 2, which in
+    /// controller units is timeout * 4 > (1 + latency) * interval_max.
+    pub fn within_limits(&self) -> bool {
+        (6..=3200).contains(&self.interval_min)
+            && (self.interval_min..=3200).contains(&self.interval_max)
+            && self.latency <= 499
+            && (10..=3200).contains(&self.timeout)
+            && self.timeout as u32 * 4 > (1 + self.latency as u32) * self.interval_max as u32
+    }
+}
+
+unsafe impl FixedSizeValue for ConnParamUpdateReq {
+    fn is_valid(_data: &[u8]) -> bool {
+        true
+    }
+}
+
+impl L2capSignal for ConnParamUpdateReq {
+    fn code() -> L2capSignalCode {
+        L2capSignalCode::ConnParamUpdateReq
+    }
+}
+
+pub const CONN_PARAM_UPDATE_ACCEPTED: u16 = 0x0000;
+pub const CONN_PARAM_UPDATE_REJECTED: u16 = 0x0001;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ConnParamUpdateRes {
+    pub result: u16,
+}
+
+unsafe impl FixedSizeValue for ConnParamUpdateRes {
+    fn is_valid(_data: &[u8]) -> bool {
+        true
+    }
+}
+
+impl L2capSignal for ConnParamUpdateRes {
+    fn code() -> L2capSignalCode {
+        L2capSignalCode::ConnParamUpdateRes
+    }
+}
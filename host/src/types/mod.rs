@@ -1,3 +1,4 @@
+pub mod gatt_traits;
 pub(crate) mod l2cap;
 pub(crate) mod primitives;
 pub mod uuid;
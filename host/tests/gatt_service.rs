@@ -0,0 +1,45 @@
+//! Checks that services declared with `#[gatt_service]` expand to code that compiles and registers the
+//! characteristics in an attribute table.
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use trouble_host::attribute::AttributeTable;
+use trouble_host::gatt_service;
+
+#[gatt_service(uuid = "180f")]
+pub struct BatteryService {
+    #[characteristic(uuid = "2a19", read, notify, value = 100)]
+    level: u8,
+    #[characteristic(uuid = "12345678-1234-5678-1234-56789abcdef0", read, write)]
+    threshold: u16,
+    #[characteristic(uuid = "2a1a", write_without_response)]
+    charging: bool,
+    #[characteristic(uuid = "2a1b", read, indicate, value = 5)]
+    critical: u8,
+}
+
+// Only writable characteristics produce events, carrying the connection and the decoded value
+fn enabled(event: BatteryServiceEvent<'_>) -> bool {
+    match event {
+        BatteryServiceEvent::Threshold { connection, value } => connection.is_connected() && value > 0,
+        BatteryServiceEvent::Charging { connection, value } => connection.is_connected() && value,
+    }
+}
+
+#[test]
+fn gatt_service_registers_characteristics() {
+    let mut storage = BatteryServiceStorage::new();
+    let table: AttributeTable<'_, NoopRawMutex, 16> = AttributeTable::new();
+    let service = BatteryService::new(&table, &mut storage).unwrap();
+
+    assert_eq!(service.get_level(&table).unwrap(), 100);
+    assert_eq!(service.get_threshold(&table).unwrap(), 0);
+    assert!(!service.get_charging(&table).unwrap());
+    assert_eq!(service.get_critical(&table).unwrap(), 5);
+
+    service.set_threshold(&table, 20).unwrap();
+    service.set_charging(&table, true).unwrap();
+    assert_eq!(service.get_threshold(&table).unwrap(), 20);
+    assert!(service.get_charging(&table).unwrap());
+
+    assert_ne!(service.level.handle(), service.threshold.handle());
+    let _ = enabled;
+}
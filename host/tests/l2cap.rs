@@ -10,7 +10,7 @@ use tokio_serial::{DataBits, Parity, SerialStream, StopBits};
 use trouble_host::adapter::{Adapter, HostResources};
 use trouble_host::advertise::{AdStructure, Advertisement, BR_EDR_NOT_SUPPORTED, LE_GENERAL_DISCOVERABLE};
 use trouble_host::connection::ConnectConfig;
-use trouble_host::l2cap::{L2capChannel, L2capChannelConfig};
+use trouble_host::l2cap::{L2capChannel, L2capChannelConfig, PsmRegistration};
 use trouble_host::scan::ScanConfig;
 use trouble_host::{Address, PacketQos};
 
@@ -76,6 +76,7 @@ async fn l2cap_connection_oriented_channels() {
             Adapter::new(controller_peripheral, &mut host_resources);
 
         adapter.set_random_address(peripheral_address);
+        adapter.register_psm(PsmRegistration::new(0x2349, PAYLOAD_LEN as u16)).unwrap();
 
         select! {
             r = adapter.run() => {